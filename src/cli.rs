@@ -0,0 +1,694 @@
+//! Command line interface for running without a window, e.g. on a NAS or from scripts. Every subcommand prints its
+//! result as json to stdout, progress is printed to stderr. Anything else that would be printed to stdout, like what
+//! libraries log while writing images, is sent to stderr as well.
+//!
+//! Exit codes:
+//! - 0: success
+//! - 1: the command failed as a whole, e.g. because the source couldn't be read
+//! - 2: invalid arguments
+//! - 3: some files failed
+//! - 4: `verify` found files whose metadata doesn't match their json file
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+
+use crate::services::{
+    self, ArchiveFormat, ArchiveOptions, DateSource, EditedMode, Event, FileDiff, LinkMode,
+    MetadataOptions, OutputMode, Parallelism, Phase, Progress, RunControl, RunOptions, TagChange,
+    TimeZoneMode,
+};
+
+const EXIT_FATAL: u8 = 1;
+const EXIT_FILES_FAILED: u8 = 3;
+const EXIT_MISMATCH: u8 = 4;
+
+/// How often progress is printed while files are written
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Open the window, which is also done if no subcommand is given
+    #[arg(long)]
+    pub gui: bool,
+    /// Write the result into this file instead of printing it
+    #[arg(long, global = true)]
+    pub result: Option<PathBuf>,
+}
+impl Cli {
+    /// The subcommand to run, or `None` if the window is opened instead, with `--gui` or without a subcommand.
+    pub fn subcommand(&mut self) -> Option<Command> {
+        self.command.take().filter(|_| !self.gui)
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Show how the files of a Takeout pair up, without modifying anything
+    Scan { source: PathBuf },
+    /// Apply the metadata from the json files to their media
+    Apply {
+        /// Takeout archive (zip or tgz, any part of a multi-part set) or extracted Takeout folder
+        source: PathBuf,
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        metadata: MetadataArgs,
+        #[command(flatten)]
+        run: RunArgs,
+        /// Process files again even if a previous run already wrote them
+        #[arg(long)]
+        force: bool,
+    },
+    /// Show the metadata every file would get, without modifying anything
+    Report {
+        source: PathBuf,
+        /// Print CSV instead of json
+        #[arg(long)]
+        csv: bool,
+        #[command(flatten)]
+        metadata: MetadataArgs,
+        #[command(flatten)]
+        run: RunArgs,
+    },
+    /// Check that every file written by `apply` has the metadata of its json file. Takes the same options as the
+    /// `apply` that wrote the files, so the files are looked for where it wrote them.
+    Verify {
+        source: PathBuf,
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        metadata: MetadataArgs,
+        #[command(flatten)]
+        run: RunArgs,
+    },
+    /// Restore the files that were modified in place
+    Undo { source: PathBuf },
+}
+
+#[derive(Debug, clap::Args)]
+pub struct OutputArgs {
+    /// Write results into this folder instead of modifying the source
+    #[arg(long, conflicts_with = "archive")]
+    output_dir: Option<PathBuf>,
+    /// How files that don't need to be modified are placed into the output folder. Only applies to folder sources,
+    /// files from archives are always written as new files.
+    #[arg(long, value_enum, default_value_t = Link::Copy, requires = "output_dir")]
+    link: Link,
+    /// Package results into a new archive instead of modifying the source
+    #[arg(long)]
+    archive: Option<PathBuf>,
+    /// Format of the archive, taken from its extension if not given
+    #[arg(long, value_enum, requires = "archive")]
+    format: Option<Format>,
+    #[arg(long, default_value_t = 6, value_parser = clap::value_parser!(u32).range(0..=9), requires = "archive")]
+    compression_level: u32,
+    /// Split the archive into parts of at most this many MB
+    #[arg(long, requires = "archive")]
+    split_size: Option<u64>,
+    /// Leave the Takeout json files out of the archive
+    #[arg(long, requires = "archive")]
+    no_json: bool,
+    /// Leave the manifest out of the archive
+    #[arg(long, requires = "archive")]
+    no_manifest: bool,
+}
+impl OutputArgs {
+    fn output_mode(&self) -> OutputMode {
+        if let Some(dir) = self.output_dir.clone() {
+            return OutputMode::Tree {
+                dir,
+                link: self.link.into(),
+            };
+        }
+        let Some(path) = self.archive.clone() else {
+            return OutputMode::InPlace;
+        };
+        let mut options = ArchiveOptions::new(path);
+        match self.format.map(ArchiveFormat::from) {
+            Some(format) if ArchiveFormat::from_path(&options.path) != Some(format) => {
+                options.set_format(format)
+            }
+            None if ArchiveFormat::from_path(&options.path).is_none() => {
+                options.set_format(ArchiveFormat::default())
+            }
+            _ => {}
+        }
+        options.compression_level = self.compression_level;
+        options.split_size = self.split_size.map(|mb| mb * 1_000_000);
+        options.include_json = !self.no_json;
+        options.include_manifest = !self.no_manifest;
+        OutputMode::Archive(options)
+    }
+}
+
+#[derive(Debug, clap::Args)]
+pub struct MetadataArgs {
+    /// Don't write the dates
+    #[arg(long)]
+    no_dates: bool,
+    /// Don't write the location
+    #[arg(long)]
+    no_gps: bool,
+    /// Don't write the description
+    #[arg(long)]
+    no_description: bool,
+    /// Write the title too. Google Photos uses the file name as title unless it was changed.
+    #[arg(long)]
+    title: bool,
+    /// Don't write the names of the people tagged in the photo
+    #[arg(long)]
+    no_people: bool,
+    /// Don't give favorites a five star rating
+    #[arg(long)]
+    no_favorites: bool,
+    /// Which date is written as the date the photo was taken
+    #[arg(long, value_enum, default_value_t = Date::Upload)]
+    date_source: Date,
+    /// Time zone the dates are written in: utc, local or a fixed offset from UTC in hours, e.g. +2 or -5.5
+    #[arg(long, default_value = "utc", value_parser = parse_time_zone, allow_hyphen_values = true)]
+    time_zone: TimeZoneMode,
+    /// Which versions of a photo edited in Google Photos get metadata
+    #[arg(long, value_enum, default_value_t = Versions::Both)]
+    edited: Versions,
+}
+impl MetadataArgs {
+    fn options(&self) -> MetadataOptions {
+        MetadataOptions {
+            dates: !self.no_dates,
+            gps: !self.no_gps,
+            description: !self.no_description,
+            title: self.title,
+            people: !self.no_people,
+            favorites: !self.no_favorites,
+            date_source: self.date_source.into(),
+            time_zone: self.time_zone,
+            edited: self.edited.into(),
+        }
+    }
+}
+
+fn parse_time_zone(value: &str) -> Result<TimeZoneMode, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "utc" => Ok(TimeZoneMode::Utc),
+        "local" => Ok(TimeZoneMode::Local),
+        offset => match offset.parse::<f64>() {
+            Ok(hours) if (-12.0..=14.0).contains(&hours) => {
+                Ok(TimeZoneMode::Offset((hours * 60.0).round() as i32))
+            }
+            _ => Err("expected utc, local or an offset in hours between -12 and +14".to_string()),
+        },
+    }
+}
+
+#[derive(Debug, clap::Args)]
+pub struct RunArgs {
+    /// Number of worker threads, defaults to the number of CPU cores
+    #[arg(long)]
+    workers: Option<usize>,
+}
+impl RunArgs {
+    fn parallelism(&self) -> Parallelism {
+        self.workers.map(Parallelism::new).unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Link {
+    Copy,
+    Hardlink,
+    Reflink,
+}
+impl From<Link> for LinkMode {
+    fn from(value: Link) -> Self {
+        match value {
+            Link::Copy => LinkMode::Copy,
+            Link::Hardlink => LinkMode::Hardlink,
+            Link::Reflink => LinkMode::Reflink,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    Zip,
+    Tar,
+    Tgz,
+}
+impl From<Format> for ArchiveFormat {
+    fn from(value: Format) -> Self {
+        match value {
+            Format::Zip => ArchiveFormat::Zip,
+            Format::Tar => ArchiveFormat::Tar,
+            Format::Tgz => ArchiveFormat::TarGz,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Date {
+    Upload,
+    PhotoTaken,
+}
+impl From<Date> for DateSource {
+    fn from(value: Date) -> Self {
+        match value {
+            Date::Upload => DateSource::Upload,
+            Date::PhotoTaken => DateSource::PhotoTaken,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Versions {
+    Both,
+    Original,
+    Edited,
+}
+impl From<Versions> for EditedMode {
+    fn from(value: Versions) -> Self {
+        match value {
+            Versions::Both => EditedMode::Both,
+            Versions::Original => EditedMode::OriginalOnly,
+            Versions::Edited => EditedMode::EditedOnly,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct FileError {
+    path: PathBuf,
+    /// Step of the run the error happened in, if it was part of one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    phase: Option<Phase>,
+    error: String,
+}
+impl From<(PathBuf, io::Error)> for FileError {
+    fn from((path, err): (PathBuf, io::Error)) -> Self {
+        Self {
+            path,
+            phase: None,
+            error: err.to_string(),
+        }
+    }
+}
+impl From<services::Error> for FileError {
+    fn from(err: services::Error) -> Self {
+        Self {
+            path: err.path().to_owned(),
+            phase: Some(err.phase()),
+            error: err.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct FailedFiles {
+    errors: Vec<FileError>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Verification {
+    verified: usize,
+    mismatches: Vec<Mismatch>,
+    errors: Vec<FileError>,
+}
+
+#[derive(Debug, Serialize)]
+struct Mismatch {
+    path: PathBuf,
+    /// Only the tags that don't have the value from the json file
+    changes: Vec<TagChange>,
+}
+
+#[derive(Debug, Serialize)]
+struct Fatal {
+    error: String,
+}
+
+/// Run a subcommand and print its result, or write it to `result_file`.
+pub fn run(command: Command, result_file: Option<&Path>) -> ExitCode {
+    // keeps the json on stdout parseable
+    let stdout = match result_file {
+        Some(_) => None,
+        None => reserve_stdout()
+            .inspect_err(|err| eprintln!("Can't keep stdout for the result: {}", err))
+            .ok(),
+    };
+    let result = match command {
+        Command::Scan { source } => {
+            services::scan(&source).map(|summary| (to_json(&summary), ExitCode::SUCCESS))
+        }
+        Command::Apply {
+            source,
+            output,
+            metadata,
+            run,
+            force,
+        } => apply(
+            source,
+            RunOptions {
+                output: output.output_mode(),
+                metadata: metadata.options(),
+                parallelism: run.parallelism(),
+                force,
+                ..Default::default()
+            },
+        ),
+        Command::Report {
+            source,
+            csv,
+            metadata,
+            run,
+        } => services::dry_run(&source, run.parallelism(), &metadata.options()).map(|report| {
+            let output = if csv {
+                report.to_csv()
+            } else {
+                report.to_json()
+            };
+            (output, ExitCode::SUCCESS)
+        }),
+        Command::Verify {
+            source,
+            output,
+            metadata,
+            run,
+        } => {
+            // the files apply wrote, the archive is read like any Takeout
+            let written = match output.output_mode() {
+                OutputMode::Archive(options) => options.part_path(1),
+                output => services::output_dir(&source, &output),
+            };
+            services::dry_run(&written, run.parallelism(), &metadata.options()).map(|report| {
+                let verification = verify(report.files);
+                let code = if verification.mismatches.is_empty() && verification.errors.is_empty() {
+                    ExitCode::SUCCESS
+                } else {
+                    ExitCode::from(EXIT_MISMATCH)
+                };
+                (to_json(&verification), code)
+            })
+        }
+        Command::Undo { source } => services::undo(&source)
+            .map(|failed| failed_files(failed.into_iter().map(FileError::from).collect())),
+    };
+    let (output, code) = result.unwrap_or_else(|err| {
+        let fatal = Fatal {
+            error: err.to_string(),
+        };
+        (to_json(&fatal), ExitCode::from(EXIT_FATAL))
+    });
+
+    match result_file {
+        Some(path) => {
+            if let Err(err) = fs::write(path, output) {
+                eprintln!("Failed to write result to {}: {}", path.display(), err);
+                return ExitCode::from(EXIT_FATAL);
+            }
+        }
+        None => {
+            let printed = match stdout {
+                Some(mut stdout) => writeln!(stdout, "{}", output.trim_end()),
+                None => writeln!(io::stdout(), "{}", output.trim_end()),
+            };
+            if let Err(err) = printed {
+                eprintln!("Failed to print result: {}", err);
+                return ExitCode::from(EXIT_FATAL);
+            }
+        }
+    }
+    code
+}
+
+/// Point stdout at stderr for the rest of the process and return the original stdout, so only the result is written
+/// to it.
+#[cfg(unix)]
+fn reserve_stdout() -> io::Result<fs::File> {
+    use std::os::fd::FromRawFd;
+
+    io::stdout().flush()?;
+    // SAFETY: only duplicates file descriptors, the duplicate is owned by the returned file
+    unsafe {
+        let original = libc::dup(libc::STDOUT_FILENO);
+        if original < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let original = fs::File::from_raw_fd(original);
+        if libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(original)
+    }
+}
+
+/// Point stdout at stderr for the rest of the process and return the original stdout, so only the result is written
+/// to it.
+#[cfg(windows)]
+fn reserve_stdout() -> io::Result<fs::File> {
+    use std::os::windows::io::{FromRawHandle, RawHandle};
+
+    const STD_OUTPUT_HANDLE: u32 = -11i32 as u32;
+    const STD_ERROR_HANDLE: u32 = -12i32 as u32;
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn GetStdHandle(std_handle: u32) -> RawHandle;
+        fn SetStdHandle(std_handle: u32, handle: RawHandle) -> i32;
+    }
+
+    io::stdout().flush()?;
+    // SAFETY: the original handle is owned by the returned file from now on, the standard handle is replaced by stderr
+    unsafe {
+        let original = GetStdHandle(STD_OUTPUT_HANDLE);
+        if original.is_null() || original as isize == -1 {
+            return Err(io::Error::other("There is no stdout"));
+        }
+        if SetStdHandle(STD_OUTPUT_HANDLE, GetStdHandle(STD_ERROR_HANDLE)) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(fs::File::from_raw_handle(original))
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn reserve_stdout() -> io::Result<fs::File> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Apply metadata and collect every error. Progress is printed to stderr.
+fn apply(source: PathBuf, options: RunOptions) -> io::Result<(String, ExitCode)> {
+    let (tx_events, rx_events) = mpsc::channel();
+    let handle = thread::spawn(move || {
+        services::extract_and_apply_metadata(&source, &options, &RunControl::default(), &tx_events);
+    });
+
+    let mut errors = Vec::new();
+    let mut progress = Progress::default();
+    let mut printed = Instant::now();
+    for event in rx_events.iter() {
+        progress.update(&event);
+        match event {
+            Event::Stage(stage) => eprintln!("{}", stage),
+            Event::FileDone { .. } if printed.elapsed() >= PROGRESS_INTERVAL => {
+                printed = Instant::now();
+                eprintln!("{}", progress_line(&progress));
+            }
+            Event::Error(err) => errors.push(FileError::from(err)),
+            Event::Summary(summary) => eprintln!(
+                "{} written, {} skipped, {} failed, {} images without json",
+                summary.processed, summary.skipped, summary.failed, summary.images_without_json
+            ),
+            Event::Done => eprintln!("{}", progress_line(&progress)),
+            _ => {}
+        }
+    }
+    if handle.join().is_err() {
+        return Err(io::Error::other("Applying metadata failed unexpectedly"));
+    }
+    Ok(failed_files(errors))
+}
+
+fn progress_line(progress: &Progress) -> String {
+    let mut line = format!(
+        "{}/{} files, {:.1}/{:.1} MB",
+        progress.files,
+        progress.total_files,
+        progress.bytes as f64 / 1e6,
+        progress.total_bytes as f64 / 1e6
+    );
+    if let Some(fraction) = progress.fraction() {
+        line += &format!(" ({:.0}%)", fraction * 100.0);
+    }
+    if let Some(eta) = progress.eta().filter(|_| !progress.done) {
+        line += &format!(", about {}s left", eta.as_secs());
+    }
+    if progress.errors > 0 {
+        line += &format!(", {} errors", progress.errors);
+    }
+    line
+}
+
+fn verify(files: Vec<FileDiff>) -> Verification {
+    let mut verification = Verification {
+        verified: 0,
+        mismatches: Vec::new(),
+        errors: Vec::new(),
+    };
+    for file in files {
+        if let Some(error) = file.error {
+            verification.errors.push(FileError {
+                path: file.path,
+                phase: None,
+                error,
+            });
+        } else if file.changes.iter().any(TagChange::is_changed) {
+            verification.mismatches.push(Mismatch {
+                path: file.path,
+                changes: file
+                    .changes
+                    .into_iter()
+                    .filter(TagChange::is_changed)
+                    .collect(),
+            });
+        } else if !file.changes.is_empty() {
+            verification.verified += 1;
+        }
+    }
+    verification
+}
+
+fn failed_files(errors: Vec<FileError>) -> (String, ExitCode) {
+    let code = if errors.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(EXIT_FILES_FAILED)
+    };
+    (to_json(&FailedFiles { errors }), code)
+}
+
+fn to_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string_pretty(value).expect("Output can always be serialized")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archive_format_replaces_extension() {
+        let cli = Cli::parse_from([
+            "gpt",
+            "apply",
+            "takeout.zip",
+            "--archive",
+            "library.zip",
+            "--format",
+            "tgz",
+        ]);
+        let Some(Command::Apply { output, .. }) = cli.command else {
+            panic!("Expected apply command");
+        };
+
+        let OutputMode::Archive(options) = output.output_mode() else {
+            panic!("Expected archive output");
+        };
+        assert_eq!(options.path, PathBuf::from("library.tgz"));
+        assert_eq!(options.format, ArchiveFormat::TarGz);
+    }
+
+    #[test]
+    fn no_subcommand_opens_gui() {
+        assert!(Cli::parse_from(["gpt"]).subcommand().is_none());
+        assert!(Cli::parse_from(["gpt", "--gui"]).subcommand().is_none());
+        assert!(matches!(
+            Cli::parse_from(["gpt", "scan", "takeout.zip"]).subcommand(),
+            Some(Command::Scan { .. })
+        ));
+        assert!(Cli::try_parse_from(["gpt", "--gui", "scan", "takeout.zip"]).is_err());
+    }
+
+    #[test]
+    fn verify_checks_the_files_apply_wrote() {
+        let dir = Path::new("./test-assets/verify_checks_the_files_apply_wrote");
+        fs::create_dir_all(dir).unwrap();
+        let zip = crate::test_utils::takeout_zip(dir.join("takeout.zip"));
+        let out_dir = dir.join("out");
+        let result = dir.join("result.json");
+        let run_cli = |args: &[&str]| {
+            let cli = Cli::parse_from(["gpt"].iter().chain(args));
+            run(cli.command.unwrap(), Some(&result));
+            serde_json::from_str::<serde_json::Value>(&fs::read_to_string(&result).unwrap())
+                .unwrap()
+        };
+        let zip = zip.to_str().unwrap();
+        let out_dir = out_dir.to_str().unwrap();
+
+        let taken = ["--output-dir", out_dir, "--date-source", "photo-taken"];
+        run_cli(&[&["apply", zip], &taken[..]].concat());
+        let verified = run_cli(&[&["verify", zip], &taken[..]].concat());
+        let uploaded = run_cli(&["verify", zip, "--output-dir", out_dir]);
+        let in_place = run_cli(&["verify", zip, "--date-source", "photo-taken"]);
+
+        // assert
+        assert!(verified["verified"].as_u64().unwrap() > 0);
+        assert_eq!(verified["mismatches"], serde_json::json!([]));
+        assert_ne!(uploaded["mismatches"], serde_json::json!([]));
+        // nothing was extracted and written in place
+        assert!(in_place["error"].is_string());
+
+        // cleanup
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn time_zone_takes_offsets_in_hours() {
+        let cli = Cli::parse_from(["gpt", "report", "takeout.zip", "--time-zone", "-5.5"]);
+        let Some(Command::Report { metadata, .. }) = cli.command else {
+            panic!("Expected report command");
+        };
+
+        assert_eq!(metadata.options().time_zone, TimeZoneMode::Offset(-330));
+        assert!(
+            Cli::try_parse_from(["gpt", "report", "takeout.zip", "--time-zone", "+15"]).is_err()
+        );
+    }
+
+    #[test]
+    fn link_requires_output_dir() {
+        assert!(
+            Cli::try_parse_from(["gpt", "apply", "takeout.zip", "--link", "hardlink"]).is_err()
+        );
+    }
+
+    #[test]
+    fn progress_line_shows_counts() {
+        let mut progress = Progress::default();
+        for event in [
+            Event::Total {
+                files: 4,
+                bytes: 2_000_000,
+            },
+            Event::Stage(services::Stage::Writing),
+            Event::FileDone {
+                path: PathBuf::from("a.jpg"),
+                bytes: 500_000,
+            },
+        ] {
+            progress.update(&event);
+        }
+
+        assert!(progress_line(&progress).starts_with("1/4 files, 0.5/2.0 MB (25%)"));
+    }
+}
@@ -0,0 +1,25 @@
+//! Apply the metadata Google Photos keeps in the json files of a Takeout to the photos themselves.
+//!
+//! A Takeout is either an archive (zip or tgz, any part of a multi-part set) or a folder it was extracted to. The
+//! window and the command line interface of this crate are thin layers over [`services`]:
+//!
+//! - [`services::TakeoutLibrary`] opens a Takeout and groups its files into [`services::MediaGroup`]s, an image, its
+//!   edited version and the json file they share.
+//! - [`services::TakeoutExif`] is the parsed metadata of a json file.
+//! - [`services::extract_and_apply_metadata`] runs the whole process with [`services::RunOptions`], writing in place,
+//!   into a separate folder or into a new archive, see [`services::OutputMode`].
+//! - [`services::dry_run`], [`services::scan`] and [`services::undo`] preview, summarize and revert a run.
+//!
+//! ```no_run
+//! use std::path::Path;
+//! use google_photos_takeout_util::services::TakeoutLibrary;
+//!
+//! let mut library = TakeoutLibrary::open(Path::new("takeout-20250101T000000Z-001.zip")).unwrap();
+//! for group in library.media_groups().to_vec() {
+//!     if let Some(Ok(metadata)) = library.read_metadata(&group) {
+//!         println!("{:?}: {:?}", group.img, metadata.photo_taken_time());
+//!     }
+//! }
+//! ```
+
+pub mod services;
@@ -1,15 +1,60 @@
-#![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
+// hide console window on Windows in release, see `attach_console`
+#![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 #![allow(rustdoc::missing_crate_level_docs)] // it's an example
 
-use std::{cell::RefCell, path::PathBuf, rc::Rc};
+use std::{
+    cell::RefCell,
+    path::PathBuf,
+    process::ExitCode,
+    rc::{Rc, Weak},
+};
 
+use clap::Parser;
 use eframe::egui;
-use views::{View, ViewNavigation};
+use google_photos_takeout_util::services::{self, DryRunReport, ErrorLog, RunOptions, RunSummary};
+use views::{SharedLibrary, View, ViewNavigation, Viewable};
 
-mod services;
+mod cli;
+#[cfg(test)]
+#[path = "services/test_utils.rs"]
+#[allow(dead_code)] // not every fixture is used by the command line tests
+mod test_utils;
 mod views;
 
-fn main() -> eframe::Result {
+fn main() -> ExitCode {
+    attach_console();
+    let mut cli = cli::Cli::parse();
+    match cli.subcommand() {
+        Some(command) => cli::run(command, cli.result.as_deref()),
+        None => match run_gui() {
+            Ok(_) => ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("{}", err);
+                ExitCode::FAILURE
+            }
+        },
+    }
+}
+
+/// Release builds on Windows don't get a console of their own, so that the window opens without one. The command line
+/// interface writes to the console of the terminal it was started from instead, if there is one.
+#[cfg(windows)]
+fn attach_console() {
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn AttachConsole(process_id: u32) -> i32;
+    }
+    // SAFETY: fails without side effects if there is no console to attach to, or the process already has one
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(windows))]
+fn attach_console() {}
+
+fn run_gui() -> eframe::Result {
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_inner_size([640.0, 240.0])
@@ -25,35 +70,97 @@ fn main() -> eframe::Result {
     )
 }
 
-#[derive(Default)]
 struct MyApp {
+    /// Owns the list of views, views only hold weak references to their previous one
+    _first_view: Rc<RefCell<View>>,
     current_view: Rc<RefCell<View>>,
+    /// A view that is shown instead of the current one until it is left, e.g. the gallery
+    side_view: Option<Box<dyn Viewable>>,
     app_state: AppState,
 }
+impl Default for MyApp {
+    fn default() -> Self {
+        let first_view = View::wizard();
+        Self {
+            current_view: first_view.clone(),
+            _first_view: first_view,
+            side_view: None,
+            app_state: AppState::default(),
+        }
+    }
+}
 
 #[derive(Default)]
 struct AppState {
     picked_path: Option<PathBuf>,
+    options: RunOptions,
+    /// Only compute what a run would change instead of modifying any files
+    dry_run: bool,
+    report: Option<DryRunReport>,
+    /// The run was cancelled before all files were written
+    cancelled: bool,
+    /// Errors of all runs, including retries
+    errors: ErrorLog,
+    /// What the last run and its retries did, `None` if it couldn't get that far
+    summary: Option<RunSummary>,
+    /// The picked Takeout, opened once for all views that read it
+    library: Option<SharedLibrary>,
+}
+impl AppState {
+    /// The picked Takeout, opened when the first view asks for it and again once another Takeout is picked.
+    fn library(&mut self, ctx: &egui::Context) -> &mut SharedLibrary {
+        let source = self
+            .picked_path
+            .as_ref()
+            .expect("Did not save file path correctly. Please report this unexpected bug.");
+        if self.library.as_ref().is_none_or(|l| l.source() != source) {
+            self.library = Some(SharedLibrary::open(ctx, source.clone()));
+        }
+        self.library.as_mut().unwrap()
+    }
 }
 
 impl eframe::App for MyApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        let nav = egui::CentralPanel::default().show(ctx, |ui| {
-            let mut cur_view = self.current_view.borrow_mut();
-            cur_view.item.show(&mut self.app_state, ctx, ui)
+        let nav = egui::CentralPanel::default().show(ctx, |ui| match self.side_view.as_mut() {
+            Some(view) => view.show(&mut self.app_state, ctx, ui),
+            None => {
+                let mut cur_view = self.current_view.borrow_mut();
+                cur_view.item.show(&mut self.app_state, ctx, ui)
+            }
         });
 
         if let Some(nav) = nav.inner {
             match nav {
+                ViewNavigation::Open(new_view) => self.side_view = Some(new_view()),
+                // leaving a side view returns to the view it was opened from
+                _ if self.side_view.is_some() => self.side_view = None,
                 ViewNavigation::Prev => {
-                    let prev = self.current_view.borrow().prev.as_ref().unwrap().clone();
+                    self.current_view.borrow_mut().leave();
+                    let mut prev = self.prev_view();
+                    while prev.borrow().item.skip_on_back() {
+                        self.current_view = prev;
+                        prev = self.prev_view();
+                    }
                     self.current_view = prev;
                 }
                 ViewNavigation::Next => {
+                    self.current_view.borrow_mut().leave();
                     let next = self.current_view.borrow().next.as_ref().unwrap().clone();
                     self.current_view = next;
                 }
+                ViewNavigation::Restart => *self = Self::default(),
             };
         }
     }
 }
+impl MyApp {
+    fn prev_view(&self) -> Rc<RefCell<View>> {
+        self.current_view
+            .borrow()
+            .prev
+            .as_ref()
+            .and_then(Weak::upgrade)
+            .expect("The first view has no previous view. Please report this unexpected bug.")
+    }
+}
@@ -0,0 +1,88 @@
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Lets whoever started a run pause, resume or cancel it. Clones share the same state, so one clone can be handed to
+/// the run while another one is kept to control it.
+///
+/// Runs only check the control between files, before starting on the next archive entry or pair, so the files that are
+/// already being worked on are always finished and recorded before a run stops.
+#[derive(Debug, Clone, Default)]
+pub struct RunControl {
+    state: Arc<(Mutex<State>, Condvar)>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    paused: bool,
+    cancelled: bool,
+}
+
+impl RunControl {
+    pub fn pause(&self) {
+        self.update(|s| s.paused = true);
+    }
+
+    pub fn resume(&self) {
+        self.update(|s| s.paused = false);
+    }
+
+    /// Stop the run at its next checkpoint. A paused run is woken up to stop.
+    pub fn cancel(&self) {
+        self.update(|s| s.cancelled = true);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.0.lock().unwrap().paused
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.0.lock().unwrap().cancelled
+    }
+
+    /// Block while the run is paused. Returns `false` if the run was cancelled and must not start on anything new.
+    pub fn checkpoint(&self) -> bool {
+        let (lock, condvar) = &*self.state;
+        let state = condvar
+            .wait_while(lock.lock().unwrap(), |s| s.paused && !s.cancelled)
+            .unwrap();
+        !state.cancelled
+    }
+
+    fn update(&self, f: impl FnOnce(&mut State)) {
+        let (lock, condvar) = &*self.state;
+        f(&mut lock.lock().unwrap());
+        condvar.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn checkpoint_waits_while_paused() {
+        let control = RunControl::default();
+        control.pause();
+
+        let run = control.clone();
+        let handle = thread::spawn(move || run.checkpoint());
+        thread::sleep(Duration::from_millis(50));
+        assert!(!handle.is_finished());
+        control.resume();
+
+        assert!(handle.join().unwrap());
+    }
+
+    #[test]
+    fn cancel_wakes_paused_run() {
+        let control = RunControl::default();
+        control.pause();
+
+        let run = control.clone();
+        let handle = thread::spawn(move || run.checkpoint());
+        control.cancel();
+
+        assert!(!handle.join().unwrap());
+        assert!(control.is_cancelled());
+    }
+}
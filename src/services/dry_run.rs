@@ -0,0 +1,232 @@
+use std::{
+    fmt::Write as _,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+pub use super::exif_data::TagChange;
+use super::{
+    TakeoutLibrary,
+    exif_data::{MetadataOptions, TakeoutExif},
+    pipeline,
+};
+
+/// What a run would do to a single image.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileDiff {
+    /// Path of the image, relative to the root of the Takeout
+    pub path: PathBuf,
+    /// The json file the metadata is taken from, `None` if the image has none and is left untouched
+    pub json: Option<PathBuf>,
+    /// Every tag the image would have, including the ones that keep their value. Empty if it is left untouched.
+    pub changes: Vec<TagChange>,
+    /// Why the metadata couldn't be compared, the run would fail for this file as well
+    pub error: Option<String>,
+}
+impl FileDiff {
+    pub fn is_changed(&self) -> bool {
+        self.changes.iter().any(TagChange::is_changed)
+    }
+}
+
+/// The diff of every image of a Takeout, ordered by path.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DryRunReport {
+    pub files: Vec<FileDiff>,
+}
+impl DryRunReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Report can always be serialized")
+    }
+
+    /// One row per tag a file would have, whether it changes or not. Files that are left untouched get a single row
+    /// with empty tag columns.
+    pub fn to_csv(&self) -> String {
+        let mut csv = "path,json,tag,before,after,error\n".to_owned();
+        for file in &self.files {
+            let path = file.path.to_string_lossy();
+            let json = file
+                .json
+                .as_ref()
+                .map(|j| j.to_string_lossy())
+                .unwrap_or_default();
+            let error = file.error.as_deref().unwrap_or_default();
+            if file.changes.is_empty() {
+                let row = [&*path, &*json, "", "", "", error].map(csv_field);
+                writeln!(csv, "{}", row.join(",")).unwrap();
+            }
+            for change in &file.changes {
+                let before = change.before.as_deref().unwrap_or_default();
+                let row =
+                    [&*path, &*json, &change.tag, before, &change.after, error].map(csv_field);
+                writeln!(csv, "{}", row.join(",")).unwrap();
+            }
+        }
+        csv
+    }
+
+    /// Write the report to `path`, as CSV if it has a csv extension and as json otherwise.
+    pub fn export(&self, path: &Path) -> io::Result<()> {
+        let is_csv = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("csv"));
+        let contents = if is_csv {
+            self.to_csv()
+        } else {
+            self.to_json()
+        };
+        fs::write(path, contents)
+    }
+}
+
+/// Quote a CSV field if necessary.
+pub(super) fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Pair the files of `source` and compute which tags would change for every image, without modifying any file.
+/// Archives are read the same way as for a real run, so tgz archives are extracted.
+pub fn dry_run(
+    source: &Path,
+    parallelism: pipeline::Parallelism,
+    metadata: &MetadataOptions,
+) -> io::Result<DryRunReport> {
+    let (mut files, groups) = TakeoutLibrary::open(source)?.into_parts();
+
+    let jobs = groups.into_iter().map(|pair| {
+        let json = pair.json.map(|p| {
+            let contents = files.read(&p);
+            (p, contents)
+        });
+        let imgs: Vec<_> = [pair.img, pair.img_edited]
+            .into_iter()
+            .flatten()
+            .map(|p| {
+                let contents = files.read(&p);
+                (p, contents)
+            })
+            .collect();
+        (json, imgs)
+    });
+
+    let mut report = DryRunReport::default();
+    pipeline::run_ordered(
+        jobs,
+        parallelism,
+        |job| diff_pair(job, metadata),
+        |diffs| report.files.extend(diffs),
+    );
+    report.files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(report)
+}
+
+type ReadFile = (PathBuf, io::Result<Vec<u8>>);
+
+/// Runs on a worker thread.
+fn diff_pair(
+    (json, imgs): (Option<ReadFile>, Vec<ReadFile>),
+    metadata: &MetadataOptions,
+) -> Vec<FileDiff> {
+    let json_path = json.as_ref().map(|(p, _)| p.clone());
+    let exif = json.map(|(_, contents)| {
+        let contents = contents.map_err(|e| e.to_string())?;
+        let contents = String::from_utf8(contents).map_err(|e| e.to_string())?;
+        TakeoutExif::from_json(&contents).map_err(|e| e.to_string())
+    });
+
+    imgs.into_iter()
+        .map(|(path, contents)| {
+            let diff = match (&exif, contents) {
+                (_, Err(err)) => Err(err.to_string()),
+                (None, Ok(_)) => Ok(Vec::new()),
+                (_, Ok(_)) if !metadata.applies_to(&path) => Ok(Vec::new()),
+                (Some(Err(err)), Ok(_)) => Err(err.clone()),
+                (Some(Ok(exif)), Ok(contents)) => exif
+                    .diff(&path, &contents, metadata)
+                    .map_err(|e| e.to_string()),
+            };
+            let (changes, error) = match diff {
+                Ok(changes) => (changes, None),
+                Err(err) => (Vec::new(), Some(err)),
+            };
+            FileDiff {
+                path,
+                json: json_path.clone(),
+                changes,
+                error,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dry_run_does_not_modify_files() {
+        let source = Path::new("./test-assets/takeout-unzipped");
+        let before: Vec<_> = crate::services::utils::recursively_collect_filenames(source)
+            .unwrap()
+            .into_iter()
+            .map(|p| (fs::read(&p).unwrap(), p))
+            .collect();
+
+        let report = dry_run(
+            source,
+            pipeline::Parallelism::new(2),
+            &MetadataOptions::default(),
+        )
+        .unwrap();
+
+        // assert
+        for (contents, p) in before {
+            assert_eq!(fs::read(&p).unwrap(), contents);
+        }
+        let jpg = report
+            .files
+            .iter()
+            .find(|f| f.path == Path::new("takeout/TEST_JPG.jpg"))
+            .unwrap();
+        assert!(jpg.is_changed());
+        // tags that keep their value are part of the report and its exports
+        let make = jpg.changes.iter().find(|c| c.tag == "Make").unwrap();
+        assert!(!make.is_changed());
+        assert!(report.to_csv().contains(",Make,Apple,Apple,"));
+        assert!(report.to_json().contains(r#""tag": "Make""#));
+        let heic = report
+            .files
+            .iter()
+            .find(|f| f.path == Path::new("takeout/TEST_HEIC.HEIC"))
+            .unwrap();
+        assert!(heic.error.is_some());
+    }
+
+    #[test]
+    fn csv_fields_are_quoted() {
+        let report = DryRunReport {
+            files: vec![FileDiff {
+                path: PathBuf::from("a/1.jpg"),
+                json: None,
+                changes: vec![TagChange {
+                    tag: "ImageDescription".to_owned(),
+                    before: Some("say \"hi\", please".to_owned()),
+                    after: String::new(),
+                }],
+                error: None,
+            }],
+        };
+
+        assert_eq!(
+            report.to_csv(),
+            "path,json,tag,before,after,error\na/1.jpg,,ImageDescription,\"say \"\"hi\"\", please\",,\n"
+        );
+    }
+}
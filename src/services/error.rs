@@ -0,0 +1,276 @@
+use std::{
+    fmt::{self, Write as _},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use super::{dry_run::csv_field, pair::PairError};
+
+/// The step of a run in which an error happened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Phase {
+    Extract,
+    Read,
+    Json,
+    Metadata,
+    Write,
+}
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let phase = match self {
+            Phase::Extract => "Extracting",
+            Phase::Read => "Reading",
+            Phase::Json => "Reading metadata",
+            Phase::Metadata => "Applying metadata",
+            Phase::Write => "Writing",
+        };
+        f.write_str(phase)
+    }
+}
+
+/// Why a single file, or an archive part, couldn't be processed. A run reports these and continues with the next
+/// file instead of aborting.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// An archive part or one of its entries couldn't be extracted
+    Extract { path: PathBuf, source: io::Error },
+    /// A file or directory couldn't be read
+    Read { path: PathBuf, source: io::Error },
+    /// A json file couldn't be read or isn't valid Takeout metadata, the media it belongs to is left untouched
+    Json { path: PathBuf, source: PairError },
+    /// The metadata couldn't be applied to an image
+    Metadata { path: PathBuf, source: io::Error },
+    /// A file couldn't be written to the output, or it couldn't be recorded as processed
+    Write { path: PathBuf, source: io::Error },
+}
+impl Error {
+    /// The file the error belongs to. Files inside zip archives are given as path of the archive part joined with
+    /// their path inside it.
+    pub fn path(&self) -> &Path {
+        match self {
+            Error::Extract { path, .. }
+            | Error::Read { path, .. }
+            | Error::Json { path, .. }
+            | Error::Metadata { path, .. }
+            | Error::Write { path, .. } => path,
+        }
+    }
+
+    /// What went wrong, without details that differ between files, so errors can be grouped by it.
+    pub fn reason(&self) -> String {
+        match self {
+            Error::Json {
+                source: PairError::ParseError(_),
+                ..
+            } => "Json file is not valid Takeout metadata".to_owned(),
+            Error::Json {
+                source: PairError::Utf8ParsingError(_),
+                ..
+            } => "Json file is not valid UTF-8".to_owned(),
+            Error::Json { source, .. } => source.to_string(),
+            Error::Extract { source, .. }
+            | Error::Read { source, .. }
+            | Error::Metadata { source, .. }
+            | Error::Write { source, .. } => source.to_string(),
+        }
+    }
+
+    pub fn phase(&self) -> Phase {
+        match self {
+            Error::Extract { .. } => Phase::Extract,
+            Error::Read { .. } => Phase::Read,
+            Error::Json { .. } => Phase::Json,
+            Error::Metadata { .. } => Phase::Metadata,
+            Error::Write { .. } => Phase::Write,
+        }
+    }
+}
+impl fmt::Display for Error {
+    /// The path is left out, as it is shown separately.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Extract { source, .. } => write!(f, "Failed to extract: {}", source),
+            Error::Read { source, .. } => write!(f, "Failed to read: {}", source),
+            Error::Json { source, .. } => write!(f, "{}", source),
+            Error::Metadata { source, .. } => write!(f, "Failed to apply metadata: {}", source),
+            Error::Write { source, .. } => write!(f, "Failed to write: {}", source),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Json { source, .. } => Some(source),
+            Error::Extract { source, .. }
+            | Error::Read { source, .. }
+            | Error::Metadata { source, .. }
+            | Error::Write { source, .. } => Some(source),
+        }
+    }
+}
+impl From<Error> for io::Error {
+    fn from(value: Error) -> Self {
+        let message = format!("{}: {}", value.path().display(), value);
+        match value {
+            Error::Extract { source, .. }
+            | Error::Read { source, .. }
+            | Error::Metadata { source, .. }
+            | Error::Write { source, .. } => io::Error::new(source.kind(), message),
+            Error::Json { .. } => io::Error::new(io::ErrorKind::InvalidData, message),
+        }
+    }
+}
+
+/// Errors of the same phase and reason.
+#[derive(Debug)]
+pub struct ErrorGroup {
+    pub phase: Phase,
+    pub reason: String,
+    pub errors: Vec<Error>,
+}
+impl ErrorGroup {
+    /// Files that failed, e.g. to retry them with [`super::RunOptions::only`].
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.errors.iter().map(Error::path)
+    }
+}
+
+/// The errors of a run, grouped by what went wrong. Groups are in the order their first error happened in.
+#[derive(Debug, Default)]
+pub struct ErrorLog {
+    groups: Vec<ErrorGroup>,
+}
+impl ErrorLog {
+    pub fn push(&mut self, err: Error) {
+        let (phase, reason) = (err.phase(), err.reason());
+        match self
+            .groups
+            .iter_mut()
+            .find(|g| g.phase == phase && g.reason == reason)
+        {
+            Some(group) => group.errors.push(err),
+            None => self.groups.push(ErrorGroup {
+                phase,
+                reason,
+                errors: vec![err],
+            }),
+        }
+    }
+
+    pub fn groups(&self) -> &[ErrorGroup] {
+        &self.groups
+    }
+
+    /// Remove a group, e.g. because its files are retried.
+    pub fn take_group(&mut self, index: usize) -> ErrorGroup {
+        self.groups.remove(index)
+    }
+
+    /// Number of errors in all groups
+    pub fn len(&self) -> usize {
+        self.groups.iter().map(|g| g.errors.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self.rows().collect::<Vec<_>>())
+            .expect("Errors can always be serialized")
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = "path,phase,reason,error\n".to_owned();
+        for row in self.rows() {
+            let fields = [
+                &*row.path.to_string_lossy(),
+                &row.phase.to_string(),
+                row.reason,
+                &row.error,
+            ]
+            .map(csv_field);
+            writeln!(csv, "{}", fields.join(",")).unwrap();
+        }
+        csv
+    }
+
+    /// Write the errors to `path`, as CSV if it has a csv extension and as json otherwise.
+    pub fn export(&self, path: &Path) -> io::Result<()> {
+        let is_csv = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("csv"));
+        let contents = if is_csv {
+            self.to_csv()
+        } else {
+            self.to_json()
+        };
+        fs::write(path, contents)
+    }
+
+    pub(super) fn rows(&self) -> impl Iterator<Item = ErrorRow<'_>> {
+        self.groups.iter().flat_map(|g| {
+            g.errors.iter().map(|err| ErrorRow {
+                path: err.path(),
+                phase: g.phase,
+                reason: &g.reason,
+                error: err.to_string(),
+            })
+        })
+    }
+}
+
+/// An error as it is exported.
+#[derive(Serialize)]
+pub(super) struct ErrorRow<'a> {
+    path: &'a Path,
+    phase: Phase,
+    reason: &'a str,
+    error: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unsupported(path: &str) -> Error {
+        Error::Metadata {
+            path: PathBuf::from(path),
+            source: io::Error::other("Unsupported file type"),
+        }
+    }
+
+    #[test]
+    fn errors_are_grouped_by_phase_and_reason() {
+        let mut log = ErrorLog::default();
+        log.push(unsupported("a.mp4"));
+        log.push(Error::Write {
+            path: PathBuf::from("b.jpg"),
+            source: io::Error::other("Unsupported file type"),
+        });
+        log.push(unsupported("c.mp4"));
+
+        assert_eq!(log.len(), 3);
+        assert_eq!(log.groups().len(), 2);
+        let paths: Vec<_> = log.groups()[0].paths().collect();
+        assert_eq!(paths, [Path::new("a.mp4"), Path::new("c.mp4")]);
+    }
+
+    #[test]
+    fn errors_are_exported_as_csv() {
+        let mut log = ErrorLog::default();
+        log.push(unsupported("a.mp4"));
+
+        assert_eq!(
+            log.to_csv(),
+            concat!(
+                "path,phase,reason,error\n",
+                "a.mp4,Applying metadata,Unsupported file type,Failed to apply metadata: Unsupported file type\n",
+            )
+        );
+    }
+}
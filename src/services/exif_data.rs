@@ -1,10 +1,154 @@
-use std::{io, path::Path};
+use std::{
+    fs, io,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    str::FromStr,
+};
 
-use little_exif::{exif_tag::ExifTag, metadata::Metadata};
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, Utc};
+use little_exif::{
+    endian::Endian, exif_tag::ExifTag, filetype::FileExtension, ifd::ExifTagGroup,
+    metadata::Metadata, rational::uR64,
+};
 use serde::{Deserialize, Serialize};
 
 static EXIF_TIMESTAMP_FMT: &str = "%Y:%m:%d %H:%M:%S%z";
 
+/// Windows tags for a title and keywords, holding UCS-2 text. They aren't known to little_exif.
+const XP_TITLE: u16 = 0x9c9b;
+const XP_KEYWORDS: u16 = 0x9c9e;
+/// Star rating from 0 to 5, also not known to little_exif
+const RATING: u16 = 0x4746;
+
+/// Which metadata is written to the images and how.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataOptions {
+    pub dates: bool,
+    pub gps: bool,
+    pub description: bool,
+    /// Google Photos uses the file name as title unless it was changed, so it isn't written by default
+    pub title: bool,
+    /// Names of the people tagged in the photo, written as keywords
+    pub people: bool,
+    /// Favorites get a five star rating
+    pub favorites: bool,
+    pub date_source: DateSource,
+    pub time_zone: TimeZoneMode,
+    pub edited: EditedMode,
+}
+impl Default for MetadataOptions {
+    fn default() -> Self {
+        Self {
+            dates: true,
+            gps: true,
+            description: true,
+            title: false,
+            people: true,
+            favorites: true,
+            date_source: DateSource::default(),
+            time_zone: TimeZoneMode::default(),
+            edited: EditedMode::default(),
+        }
+    }
+}
+impl MetadataOptions {
+    /// Whether metadata is applied to `img` at all. Images it isn't applied to are placed as they are.
+    pub fn applies_to(&self, img: &Path) -> bool {
+        let is_edited = super::pair::is_edited(img);
+        match self.edited {
+            EditedMode::Both => true,
+            EditedMode::OriginalOnly => !is_edited,
+            EditedMode::EditedOnly => is_edited,
+        }
+    }
+}
+
+/// Which of the timestamps of a json file is written as the date of an image.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DateSource {
+    /// When the file was uploaded to Google Photos
+    #[default]
+    Upload,
+    /// When the photo was taken, or when it was uploaded if the json file doesn't say
+    PhotoTaken,
+}
+impl DateSource {
+    pub const ALL: [DateSource; 2] = [DateSource::Upload, DateSource::PhotoTaken];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            DateSource::Upload => "Upload date",
+            DateSource::PhotoTaken => "Date taken, upload date if missing",
+        }
+    }
+}
+
+/// The time zone dates are written in. Takeout only has UTC timestamps, so the zone a photo was taken in is unknown.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimeZoneMode {
+    #[default]
+    Utc,
+    /// The zone of this computer, at the time of the date
+    Local,
+    /// A fixed offset from UTC, in minutes
+    Offset(i32),
+}
+impl TimeZoneMode {
+    /// `date` as it is written in this zone.
+    pub fn to_local(&self, date: DateTime<Utc>) -> NaiveDateTime {
+        match self {
+            TimeZoneMode::Utc => date.naive_utc(),
+            TimeZoneMode::Local => date.with_timezone(&Local).naive_local(),
+            TimeZoneMode::Offset(minutes) => {
+                let offset = FixedOffset::east_opt(minutes * 60)
+                    .unwrap_or(FixedOffset::east_opt(0).unwrap());
+                date.with_timezone(&offset).naive_local()
+            }
+        }
+    }
+
+    fn format(&self, date: DateTime<Utc>) -> String {
+        match self {
+            TimeZoneMode::Utc => date.format(EXIF_TIMESTAMP_FMT).to_string(),
+            TimeZoneMode::Local => date
+                .with_timezone(&Local)
+                .format(EXIF_TIMESTAMP_FMT)
+                .to_string(),
+            TimeZoneMode::Offset(minutes) => {
+                let offset = FixedOffset::east_opt(minutes * 60)
+                    .unwrap_or(FixedOffset::east_opt(0).unwrap());
+                date.with_timezone(&offset)
+                    .format(EXIF_TIMESTAMP_FMT)
+                    .to_string()
+            }
+        }
+    }
+}
+
+/// Which versions of an image metadata is applied to, if a photo was edited in Google Photos.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EditedMode {
+    #[default]
+    Both,
+    OriginalOnly,
+    EditedOnly,
+}
+impl EditedMode {
+    pub const ALL: [EditedMode; 3] = [
+        EditedMode::Both,
+        EditedMode::OriginalOnly,
+        EditedMode::EditedOnly,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            EditedMode::Both => "Original and edited",
+            EditedMode::OriginalOnly => "Only the original",
+            EditedMode::EditedOnly => "Only the edited version",
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TakeoutExif {
@@ -15,31 +159,341 @@ pub struct TakeoutExif {
     geo_data: Option<GeoData>,
     people: Option<Vec<Person>>,
     url: Option<String>,
+    favorited: Option<bool>,
 }
 impl TakeoutExif {
-    pub fn apply_to_image(&self, path: &Path) -> io::Result<()> {
+    /// Write the tags `options` select to the image at `path`. Every other tag it already has is kept. The metadata is
+    /// written in memory first, so the file is left as it was if that fails.
+    pub fn apply_to_image(&self, path: &Path, options: &MetadataOptions) -> io::Result<()> {
+        let mut bytes = fs::read(path)?;
+        self.apply_to_bytes(path, &mut bytes, options)?;
+        fs::write(path, bytes)
+    }
+
+    /// Same as [`Self::apply_to_image`], but for an image that is held in memory. `path` is only used to determine the
+    /// file type.
+    pub fn apply_to_bytes(
+        &self,
+        path: &Path,
+        bytes: &mut Vec<u8>,
+        options: &MetadataOptions,
+    ) -> io::Result<()> {
+        let metadata = self.metadata(path, bytes, options)?;
+        let file_type = file_type(path)?;
+        catch_panic(|| metadata.write_to_vec(bytes, file_type))
+    }
+
+    /// Compare every tag `bytes` would have after [`Self::apply_to_bytes`] with the value it has now, without
+    /// modifying anything. Tags that aren't written are listed with the value they keep. `path` is only used to
+    /// determine the file type.
+    pub fn diff(
+        &self,
+        path: &Path,
+        bytes: &[u8],
+        options: &MetadataOptions,
+    ) -> io::Result<Vec<TagChange>> {
+        let current = read_metadata(path, bytes)?;
+        let mut merged = current.clone();
+        self.set_tags(&mut merged, options);
+        let endian = &merged.get_endian();
+        let changes = merged.get_ifds().iter().flat_map(|ifd| {
+            let before = current.get_ifd(ifd.get_ifd_type(), ifd.get_generic_ifd_nr());
+            ifd.get_tags().iter().map(move |tag| {
+                let before = before
+                    .and_then(|ifd| ifd.get_tags().iter().find(|t| t.as_u16() == tag.as_u16()));
+                TagChange {
+                    tag: tag_name(tag),
+                    before: before.map(|t| tag_value(t, endian)),
+                    after: tag_value(tag, endian),
+                }
+            })
+        });
+        Ok(changes.collect())
+    }
+
+    /// Names of the tags that [`Self::apply_to_image`] writes.
+    pub fn tag_names(&self, options: &MetadataOptions) -> Vec<&'static str> {
+        self.tags(options)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    /// The metadata `bytes` already has, with the tags `options` select set on it. little_exif replaces all metadata of
+    /// a file when writing, so the tags that aren't written have to be part of it to be kept.
+    fn metadata(
+        &self,
+        path: &Path,
+        bytes: &[u8],
+        options: &MetadataOptions,
+    ) -> io::Result<Metadata> {
+        let mut metadata = read_metadata(path, bytes)?;
+        self.set_tags(&mut metadata, options);
+        Ok(metadata)
+    }
+
+    fn set_tags(&self, metadata: &mut Metadata, options: &MetadataOptions) {
+        for (_, t) in self.tags(options) {
+            metadata.set_tag(t);
+        }
+    }
+
+    /// The tags that are written, along with their names.
+    fn tags(&self, options: &MetadataOptions) -> Vec<(&'static str, ExifTag)> {
         let mut tags = Vec::new();
-        if let Some(description) = self.description.clone() {
-            tags.push(ExifTag::ImageDescription(description));
+        if options.title
+            && let Some(title) = self.title.as_deref().filter(|t| !t.is_empty())
+        {
+            tags.push(("XPTitle", xp_tag(XP_TITLE, title)));
         }
-        if let Some(timestamp) = self.creation_time.as_ref().and_then(|t| t.to_datetime()) {
-            let timestamp_formatted = timestamp.format(EXIF_TIMESTAMP_FMT).to_string();
-            tags.push(ExifTag::DateTimeOriginal(timestamp_formatted.clone()));
-            tags.push(ExifTag::CreateDate(timestamp_formatted.clone()));
-            tags.push(ExifTag::ModifyDate(timestamp_formatted));
+        if options.description
+            && let Some(description) = self.description.clone()
+        {
+            tags.push(("ImageDescription", ExifTag::ImageDescription(description)));
         }
-
-        let mut metadata = Metadata::new();
-        for t in tags {
-            metadata.set_tag(t);
+        if options.people {
+            let people: Vec<_> = self.people().collect();
+            if !people.is_empty() {
+                tags.push(("XPKeywords", xp_tag(XP_KEYWORDS, &people.join(";"))));
+            }
+        }
+        if options.favorites && self.favorited == Some(true) {
+            tags.push((
+                "Rating",
+                ExifTag::UnknownINT16U(vec![5], RATING, ExifTagGroup::GENERIC),
+            ));
+        }
+        if options.gps
+            && let Some(geo_data) = self.geo_data.as_ref()
+        {
+            tags.extend(geo_data.tags());
+        }
+        if options.dates
+            && let Some(timestamp) = self.date(options.date_source)
+        {
+            let timestamp_formatted = options.time_zone.format(timestamp);
+            tags.push((
+                "DateTimeOriginal",
+                ExifTag::DateTimeOriginal(timestamp_formatted.clone()),
+            ));
+            tags.push((
+                "CreateDate",
+                ExifTag::CreateDate(timestamp_formatted.clone()),
+            ));
+            tags.push(("ModifyDate", ExifTag::ModifyDate(timestamp_formatted)));
         }
-        metadata.write_to_file(path)?;
-        Ok(())
+        tags
     }
 
     pub fn from_json(value: &str) -> Result<Self, JsonParseError> {
         serde_json::from_str(value).map_err(JsonParseError::from)
     }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// When the file was uploaded to Google Photos
+    pub fn creation_time(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        self.creation_time.as_ref().and_then(TimeStamp::to_datetime)
+    }
+
+    /// The date that is written according to `source`.
+    pub fn date(&self, source: DateSource) -> Option<chrono::DateTime<chrono::Utc>> {
+        match source {
+            DateSource::Upload => self.creation_time(),
+            DateSource::PhotoTaken => self.photo_taken_time().or_else(|| self.creation_time()),
+        }
+    }
+
+    pub fn photo_taken_time(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        self.photo_taken_time
+            .as_ref()
+            .and_then(TimeStamp::to_datetime)
+    }
+
+    pub fn geo_data(&self) -> Option<&GeoData> {
+        self.geo_data.as_ref()
+    }
+
+    /// Names of the people that were tagged
+    pub fn people(&self) -> impl Iterator<Item = &str> {
+        self.people.iter().flatten().map(|p| p.name.as_str())
+    }
+
+    pub fn url(&self) -> Option<&str> {
+        self.url.as_deref()
+    }
+
+    pub fn is_favorite(&self) -> bool {
+        self.favorited == Some(true)
+    }
+}
+
+/// A Windows XP tag with `text` encoded as null terminated UCS-2.
+fn xp_tag(hex: u16, text: &str) -> ExifTag {
+    let bytes = text
+        .encode_utf16()
+        .chain([0])
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    ExifTag::UnknownINT8U(bytes, hex, ExifTagGroup::GENERIC)
+}
+
+/// The value of a tag as it is shown to the user. Long values, e.g. maker notes, are cut off.
+fn tag_value(tag: &ExifTag, endian: &Endian) -> String {
+    const MAX_VALUE_LEN: usize = 200;
+    let mut value = full_tag_value(tag, endian);
+    if let Some((i, _)) = value.char_indices().nth(MAX_VALUE_LEN) {
+        value.truncate(i);
+        value.push('…');
+    }
+    value
+}
+
+fn full_tag_value(tag: &ExifTag, endian: &Endian) -> String {
+    let numbers = |n: &[f64]| {
+        n.iter()
+            .map(|n| n.to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let rationals = |r: &[uR64]| {
+        numbers(
+            &r.iter()
+                .map(|r| r.nominator as f64 / r.denominator.max(1) as f64)
+                .collect::<Vec<_>>(),
+        )
+    };
+    match tag {
+        ExifTag::GPSLatitude(r) | ExifTag::GPSLongitude(r) | ExifTag::GPSAltitude(r) => {
+            rationals(r)
+        }
+        ExifTag::GPSAltitudeRef(n) => numbers(&n.iter().map(|&n| n as f64).collect::<Vec<_>>()),
+        ExifTag::UnknownINT16U(n, ..) => numbers(&n.iter().map(|&n| n as f64).collect::<Vec<_>>()),
+        ExifTag::UnknownINT8U(bytes, XP_TITLE | XP_KEYWORDS, _) => {
+            let text: Vec<_> = bytes
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .collect();
+            String::from_utf16_lossy(&text)
+                .trim_end_matches('\0')
+                .to_owned()
+        }
+        _ => String::from_utf8_lossy(&tag.value_as_u8_vec(endian))
+            .trim_end_matches('\0')
+            .to_owned(),
+    }
+}
+
+/// A tag an image has.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Tag {
+    pub name: String,
+    pub value: String,
+}
+
+/// All EXIF tags `bytes` has, in the order they are stored in. `path` is only used to determine the file type.
+pub fn read_tags(path: &Path, bytes: &[u8]) -> io::Result<Vec<Tag>> {
+    let metadata = read_metadata(path, bytes)?;
+    let endian = metadata.get_endian();
+    Ok((&metadata)
+        .into_iter()
+        .map(|tag| Tag {
+            name: tag_name(tag),
+            value: tag_value(tag, &endian),
+        })
+        .collect())
+}
+
+/// When the photo in `bytes` was taken according to its `DateTimeOriginal` tag, which has no time zone. `path` is only
+/// used to determine the file type.
+pub fn read_date_taken(path: &Path, bytes: &[u8]) -> Option<NaiveDateTime> {
+    let metadata = read_metadata(path, bytes).ok()?;
+    let tag = metadata
+        .get_tag(&ExifTag::DateTimeOriginal(String::new()))
+        .next()?;
+    match tag {
+        ExifTag::DateTimeOriginal(date) => {
+            NaiveDateTime::parse_from_str(date.trim_end_matches('\0'), "%Y:%m:%d %H:%M:%S").ok()
+        }
+        _ => None,
+    }
+}
+
+/// The XMP packet embedded in `bytes`, if there is one. It is found by its markers, so it works for any file type.
+pub fn read_xmp(bytes: &[u8]) -> Option<String> {
+    const START: &[u8] = b"<x:xmpmeta";
+    const END: &[u8] = b"</x:xmpmeta>";
+    let start = bytes.windows(START.len()).position(|w| w == START)?;
+    let len = bytes[start..].windows(END.len()).position(|w| w == END)?;
+    Some(String::from_utf8_lossy(&bytes[start..start + len + END.len()]).into_owned())
+}
+
+/// The name little_exif knows a tag by, or its hex value if it doesn't know it.
+fn tag_name(tag: &ExifTag) -> String {
+    match tag.as_u16() {
+        XP_TITLE => return "XPTitle".to_owned(),
+        XP_KEYWORDS => return "XPKeywords".to_owned(),
+        RATING => return "Rating".to_owned(),
+        _ => {}
+    }
+    let debug = format!("{:?}", tag);
+    let name = debug.split('(').next().unwrap_or_default();
+    if name.starts_with("Unknown") {
+        format!("{:#06x}", tag.as_u16())
+    } else {
+        name.to_owned()
+    }
+}
+
+/// The value a tag has and the value it would get.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TagChange {
+    pub tag: String,
+    /// `None` if the tag isn't set
+    pub before: Option<String>,
+    pub after: String,
+}
+impl TagChange {
+    pub fn is_changed(&self) -> bool {
+        self.before.as_ref() != Some(&self.after)
+    }
+}
+
+/// The metadata of the file `bytes`, whose type is determined by the extension of `path`.
+fn read_metadata(path: &Path, bytes: &[u8]) -> io::Result<Metadata> {
+    let file_type = file_type(path)?;
+    catch_panic(|| Metadata::new_from_vec(&bytes.to_vec(), file_type))
+}
+
+/// Run a call into little_exif, turning a panic into an error. It panics on some files it doesn't support yet, e.g.
+/// lossy webp images, and a single such file must not end a whole run.
+fn catch_panic<T>(call: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+    panic::catch_unwind(AssertUnwindSafe(call)).unwrap_or_else(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        Err(io::Error::other(format!(
+            "The file isn't supported: {}",
+            message
+        )))
+    })
+}
+
+/// Determine the file type little_exif needs from the extension of `path`.
+fn file_type(path: &Path) -> io::Result<FileExtension> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .ok_or_else(|| io::Error::other("Can't get extension from given path!"))?;
+    FileExtension::from_str(extension.to_lowercase().as_str())
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -51,7 +505,7 @@ impl TimeStamp {
     /// Convert to [`chrono::DateTime`] if the necessary fields are present and parsing is successful.
     /// Otherwise, return `None`.
     // TODO: Maybe return `Result` instead for better communication about why parsing failed.
-    fn to_datetime(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+    pub fn to_datetime(&self) -> Option<chrono::DateTime<chrono::Utc>> {
         self.timestamp
             .as_ref()
             .and_then(|t| t.parse().ok())
@@ -62,11 +516,55 @@ impl TimeStamp {
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GeoData {
-    latitude: Option<f64>,
-    longitude: Option<f64>,
-    altitude: Option<f64>,
-    latitude_span: Option<f64>,
-    longitude_span: Option<f64>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub altitude: Option<f64>,
+    pub latitude_span: Option<f64>,
+    pub longitude_span: Option<f64>,
+}
+impl GeoData {
+    /// Takeout sets all coordinates to 0 if a photo has no location.
+    pub fn is_known(&self) -> bool {
+        matches!((self.latitude, self.longitude), (Some(lat), Some(lon)) if lat != 0.0 || lon != 0.0)
+    }
+
+    fn tags(&self) -> Vec<(&'static str, ExifTag)> {
+        let (Some(latitude), Some(longitude)) = (self.latitude, self.longitude) else {
+            return Vec::new();
+        };
+        if !self.is_known() {
+            return Vec::new();
+        }
+        let ns = if latitude < 0.0 { "S" } else { "N" };
+        let ew = if longitude < 0.0 { "W" } else { "E" };
+        let mut tags = vec![
+            ("GPSLatitudeRef", ExifTag::GPSLatitudeRef(ns.to_owned())),
+            ("GPSLatitude", ExifTag::GPSLatitude(degrees(latitude))),
+            ("GPSLongitudeRef", ExifTag::GPSLongitudeRef(ew.to_owned())),
+            ("GPSLongitude", ExifTag::GPSLongitude(degrees(longitude))),
+        ];
+        if let Some(altitude) = self.altitude {
+            let below_sea_level = u8::from(altitude < 0.0);
+            tags.push((
+                "GPSAltitudeRef",
+                ExifTag::GPSAltitudeRef(vec![below_sea_level]),
+            ));
+            tags.push((
+                "GPSAltitude",
+                ExifTag::GPSAltitude(vec![altitude.abs().into()]),
+            ));
+        }
+        tags
+    }
+}
+
+/// Degrees, minutes and seconds of a coordinate, without its sign.
+fn degrees(coordinate: f64) -> Vec<uR64> {
+    let coordinate = coordinate.abs();
+    let degrees = coordinate.trunc();
+    let minutes = ((coordinate - degrees) * 60.0).trunc();
+    let seconds = ((coordinate - degrees) * 60.0 - minutes) * 60.0;
+    vec![degrees.into(), minutes.into(), seconds.into()]
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -74,13 +572,20 @@ pub struct Person {
     name: String,
 }
 
+/// A json file that isn't valid Takeout metadata.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JsonParseError(String);
-impl<S: ToString> From<S> for JsonParseError {
-    fn from(value: S) -> Self {
+impl From<serde_json::Error> for JsonParseError {
+    fn from(value: serde_json::Error) -> Self {
         Self(value.to_string())
     }
 }
+impl std::fmt::Display for JsonParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to parse json file: {}", self.0)
+    }
+}
+impl std::error::Error for JsonParseError {}
 
 #[cfg(test)]
 mod tests {
@@ -174,6 +679,136 @@ mod tests {
         assert!(exif.creation_time.is_none());
     }
 
+    #[test]
+    fn apply_to_bytes_writes_tags() {
+        let path = Path::new("./test-assets/takeout-unzipped/takeout/TEST_JPG.jpg");
+        let exif = TakeoutExif::from_json(TEST_FULL_JSON).unwrap();
+
+        let mut bytes = std::fs::read(path).unwrap();
+        exif.apply_to_bytes(path, &mut bytes, &MetadataOptions::default())
+            .unwrap();
+
+        let metadata = Metadata::new_from_vec(&bytes, FileExtension::JPEG).unwrap();
+        let tag = metadata
+            .get_tag(&ExifTag::DateTimeOriginal(String::new()))
+            .next()
+            .unwrap();
+        assert_eq!(
+            tag,
+            &ExifTag::DateTimeOriginal("2019:07:18 22:55:29+0000".to_string())
+        );
+    }
+
+    #[test]
+    fn tags_that_are_not_written_are_kept() {
+        let path = Path::new("./test-assets/takeout-unzipped/takeout/TEST_JPG.jpg");
+        let exif = TakeoutExif::from_json(TEST_FULL_JSON).unwrap();
+        let mut bytes = std::fs::read(path).unwrap();
+        exif.apply_to_bytes(path, &mut bytes, &MetadataOptions::default())
+            .unwrap();
+        let without_gps = MetadataOptions {
+            gps: false,
+            ..Default::default()
+        };
+
+        TakeoutExif::from_json(TEST_EMPTY_JSON)
+            .unwrap()
+            .apply_to_bytes(path, &mut bytes, &without_gps)
+            .unwrap();
+
+        // the camera's tags and the location written before are still there
+        let tags = read_tags(path, &bytes).unwrap();
+        let value = |name: &str| {
+            tags.iter()
+                .find(|t| t.name == name)
+                .map(|t| t.value.as_str())
+        };
+        assert_eq!(value("Make"), Some("Apple"));
+        assert_eq!(value("Model"), Some("iPhone 7"));
+        assert_eq!(value("GPSLatitudeRef"), Some("N"));
+    }
+
+    #[test]
+    fn diff_shows_tags_that_would_change() {
+        let path = Path::new("./test-assets/takeout-unzipped/takeout/TEST_JPG.jpg");
+        let exif = TakeoutExif::from_json(TEST_FULL_JSON).unwrap();
+        let mut bytes = std::fs::read(path).unwrap();
+
+        let before = exif
+            .diff(path, &bytes, &MetadataOptions::default())
+            .unwrap();
+        exif.apply_to_bytes(path, &mut bytes, &MetadataOptions::default())
+            .unwrap();
+        let after = exif
+            .diff(path, &bytes, &MetadataOptions::default())
+            .unwrap();
+
+        let date = before.iter().find(|c| c.tag == "DateTimeOriginal").unwrap();
+        assert_eq!(date.after, "2019:07:18 22:55:29+0000");
+        assert!(date.is_changed());
+        // tags that aren't written are listed as they stay
+        let make = before.iter().find(|c| c.tag == "Make").unwrap();
+        assert_eq!(make.before.as_deref(), Some("Apple"));
+        assert!(!make.is_changed());
+        assert_eq!(after.len(), before.len());
+        assert!(after.iter().all(|c| !c.is_changed()));
+    }
+
+    #[test]
+    fn gps_and_people_are_written() {
+        let path = Path::new("./test-assets/takeout-unzipped/takeout/TEST_JPG.jpg");
+        let exif = TakeoutExif::from_json(TEST_FULL_JSON).unwrap();
+        let bytes = std::fs::read(path).unwrap();
+
+        let changes = exif
+            .diff(path, &bytes, &MetadataOptions::default())
+            .unwrap();
+
+        let after = |tag: &str| &changes.iter().find(|c| c.tag == tag).unwrap().after;
+        assert_eq!(after("GPSLatitudeRef"), "N");
+        assert_eq!(after("GPSLongitude"), "92 54 0");
+        assert_eq!(after("XPKeywords"), "Bonnie LaBauve;Ryleigh Peterson");
+    }
+
+    #[test]
+    fn options_select_fields_and_date() {
+        let exif = TakeoutExif::from_json(TEST_FULL_JSON).unwrap();
+        let options = MetadataOptions {
+            gps: false,
+            people: false,
+            date_source: DateSource::PhotoTaken,
+            time_zone: TimeZoneMode::Offset(120),
+            ..Default::default()
+        };
+
+        let tags = exif.tags(&options);
+
+        assert!(tags.iter().all(|(name, _)| !name.starts_with("GPS")));
+        assert!(tags.iter().all(|(name, _)| *name != "XPKeywords"));
+        let (_, date) = tags
+            .into_iter()
+            .find(|(name, _)| *name == "DateTimeOriginal")
+            .unwrap();
+        assert_eq!(
+            date,
+            ExifTag::DateTimeOriginal("2019:07:17 22:28:49+0200".to_string())
+        );
+    }
+
+    #[test]
+    fn apply_to_bytes_fails_for_unsupported_type() {
+        let exif = TakeoutExif::from_json(TEST_FULL_JSON).unwrap();
+        let mut bytes = Vec::new();
+        assert!(
+            exif.apply_to_bytes(
+                Path::new("my_img.HEIC"),
+                &mut bytes,
+                &MetadataOptions::default()
+            )
+            .is_err()
+        );
+    }
+
     #[test]
     fn fails_for_invalid_json() {
         let exif = TakeoutExif::from_json(TEST_INVALID_JSON);
@@ -0,0 +1,276 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Size and modification time of a file, used to detect whether it changed since it was journaled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileState {
+    size: u64,
+    modified_nanos: u128,
+}
+impl FileState {
+    pub fn of(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        let modified_nanos = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        Ok(Self {
+            size: metadata.len(),
+            modified_nanos,
+        })
+    }
+}
+
+/// Hex encoded SHA-256 hash of `bytes`.
+pub fn content_hash(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// A single line of the journal file.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum Entry {
+    /// An archive part was completely extracted
+    Extracted { path: PathBuf, state: FileState },
+    /// A file was completely written
+    Processed {
+        path: PathBuf,
+        state: FileState,
+        /// Hash of the json file whose metadata was applied and of the options it was applied with, missing for files
+        /// without a json file
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        json_hash: Option<String>,
+    },
+}
+
+/// Records which work of a run is done, so a restarted run can skip it. The journal is an append-only file with one
+/// json entry per line, so a run that dies while writing loses at most the entry it was writing.
+#[derive(Debug, Default)]
+pub struct Journal {
+    file: Option<fs::File>,
+    extracted: HashMap<PathBuf, FileState>,
+    processed: HashMap<PathBuf, FileState>,
+    json_hashes: HashMap<PathBuf, String>,
+}
+impl Journal {
+    /// A journal that is not persisted. Every run starts from scratch.
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// Open the journal of the given working directory, creating it if necessary. The journal is kept next to the
+    /// directory, so it never shows up among the files of the Takeout. A journal is discarded if its directory doesn't
+    /// exist anymore.
+    pub fn for_dir(dir: &Path) -> io::Result<Self> {
+        let path = Self::path_for_dir(dir);
+        if !dir.exists() && path.exists() {
+            fs::remove_file(&path)?;
+        }
+        Self::open(&path)
+    }
+
+    pub fn path_for_dir(dir: &Path) -> PathBuf {
+        match dir.file_name() {
+            Some(name) => dir.with_file_name(format!("{}.journal.jsonl", name.to_string_lossy())),
+            None => dir.join(".journal.jsonl"),
+        }
+    }
+
+    fn open(path: &Path) -> io::Result<Self> {
+        let mut journal = Self::in_memory();
+        if path.exists() {
+            for line in io::BufReader::new(fs::File::open(path)?).lines() {
+                // the last line is incomplete if a run died while writing it
+                match serde_json::from_str(&line?) {
+                    Ok(Entry::Extracted { path, state }) => {
+                        journal.extracted.insert(path, state);
+                    }
+                    Ok(Entry::Processed {
+                        path,
+                        state,
+                        json_hash,
+                    }) => {
+                        journal.record_json_hash(&path, json_hash);
+                        journal.processed.insert(path, state);
+                    }
+                    Err(_) => continue,
+                }
+            }
+        }
+        if let Some(p) = path.parent()
+            && !p.as_os_str().is_empty()
+        {
+            fs::create_dir_all(p)?;
+        }
+        journal.file = Some(
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?,
+        );
+        Ok(journal)
+    }
+
+    fn append(&mut self, entry: &Entry) -> io::Result<()> {
+        if let Some(file) = self.file.as_mut() {
+            let mut line = serde_json::to_vec(entry)?;
+            line.push(b'\n');
+            file.write_all(&line)?;
+            file.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Whether the archive part was completely extracted by a previous run and hasn't been replaced since.
+    pub fn is_extracted(&self, part: &Path) -> bool {
+        is_unchanged(&self.extracted, part)
+    }
+
+    pub fn record_extracted(&mut self, part: &Path) -> io::Result<()> {
+        let state = FileState::of(part)?;
+        self.extracted.insert(part.to_owned(), state);
+        self.append(&Entry::Extracted {
+            path: part.to_owned(),
+            state,
+        })
+    }
+
+    /// Whether the file was written by a previous run, hasn't been modified since and got its metadata from a json
+    /// file and options with the given hash. A file is processed again once its json file or the options change.
+    pub fn is_processed(&self, path: &Path, json_hash: Option<&str>) -> bool {
+        is_unchanged(&self.processed, path)
+            && self.json_hashes.get(path).map(String::as_str) == json_hash
+    }
+
+    pub fn record_processed(&mut self, path: &Path, json_hash: Option<&str>) -> io::Result<()> {
+        let state = FileState::of(path)?;
+        self.processed.insert(path.to_owned(), state);
+        self.record_json_hash(path, json_hash.map(str::to_owned));
+        self.append(&Entry::Processed {
+            path: path.to_owned(),
+            state,
+            json_hash: json_hash.map(str::to_owned),
+        })
+    }
+
+    fn record_json_hash(&mut self, path: &Path, json_hash: Option<String>) {
+        match json_hash {
+            Some(hash) => self.json_hashes.insert(path.to_owned(), hash),
+            None => self.json_hashes.remove(path),
+        };
+    }
+}
+
+fn is_unchanged(entries: &HashMap<PathBuf, FileState>, path: &Path) -> bool {
+    entries
+        .get(path)
+        .is_some_and(|state| FileState::of(path).is_ok_and(|s| &s == state))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_is_next_to_dir() {
+        assert_eq!(
+            Journal::path_for_dir(Path::new("some/dir/takeout")),
+            PathBuf::from("some/dir/takeout.journal.jsonl")
+        );
+    }
+
+    #[test]
+    fn processed_files_survive_reopening() {
+        let dir = PathBuf::from("./test-assets/processed_files_survive_reopening");
+        let file = dir.join("my_img.jpg");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&file, b"jpg").unwrap();
+
+        let mut journal = Journal::for_dir(&dir).unwrap();
+        assert!(!journal.is_processed(&file, None));
+        journal.record_processed(&file, None).unwrap();
+        drop(journal);
+
+        // a run that died while writing leaves an incomplete line behind
+        let mut f = fs::OpenOptions::new()
+            .append(true)
+            .open(Journal::path_for_dir(&dir))
+            .unwrap();
+        f.write_all(b"{\"processed\":{\"pa").unwrap();
+
+        let journal = Journal::for_dir(&dir).unwrap();
+        assert!(journal.is_processed(&file, None));
+
+        // cleanup
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_file(Journal::path_for_dir(&dir)).unwrap();
+    }
+
+    #[test]
+    fn modified_files_are_detected() {
+        let dir = PathBuf::from("./test-assets/modified_files_are_detected");
+        let file = dir.join("my_img.jpg");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&file, b"jpg").unwrap();
+
+        let mut journal = Journal::for_dir(&dir).unwrap();
+        journal.record_processed(&file, None).unwrap();
+        fs::write(&file, b"modified jpg").unwrap();
+
+        assert!(!journal.is_processed(&file, None));
+
+        // cleanup
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_file(Journal::path_for_dir(&dir)).unwrap();
+    }
+
+    #[test]
+    fn changed_json_is_detected() {
+        let dir = PathBuf::from("./test-assets/changed_json_is_detected");
+        let file = dir.join("my_img.jpg");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&file, b"jpg").unwrap();
+        let json_hash = content_hash(b"{}");
+
+        let mut journal = Journal::for_dir(&dir).unwrap();
+        journal.record_processed(&file, Some(&json_hash)).unwrap();
+        drop(journal);
+        let journal = Journal::for_dir(&dir).unwrap();
+
+        assert!(journal.is_processed(&file, Some(&json_hash)));
+        assert!(!journal.is_processed(&file, Some(&content_hash(b"{\"title\":\"\"}"))));
+        assert!(!journal.is_processed(&file, None));
+
+        // cleanup
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_file(Journal::path_for_dir(&dir)).unwrap();
+    }
+
+    #[test]
+    fn journal_of_deleted_dir_is_discarded() {
+        let dir = PathBuf::from("./test-assets/journal_of_deleted_dir_is_discarded");
+        let file = dir.join("my_img.jpg");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&file, b"jpg").unwrap();
+        Journal::for_dir(&dir)
+            .unwrap()
+            .record_processed(&file, None)
+            .unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+        let journal = Journal::for_dir(&dir).unwrap();
+
+        assert!(journal.processed.is_empty());
+
+        // cleanup
+        fs::remove_file(Journal::path_for_dir(&dir)).unwrap();
+    }
+}
@@ -0,0 +1,520 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use chrono::Datelike;
+
+use super::{
+    RunControl, ScanSummary,
+    exif_data::{self, MetadataOptions, Tag, TagChange, TakeoutExif},
+    links::{ManualLinks, OrphanImage, OrphanJson},
+    pair::{self, Pair},
+    stream::{self, TakeoutFiles},
+    thumbnail::{self, Thumbnail, ThumbnailCache},
+    timeline::Timeline,
+};
+
+/// An image, its edited version and the json file with the metadata they share. Paths are relative to the root of
+/// the Takeout.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MediaGroup {
+    pub img: Option<PathBuf>,
+    /// The version edited in Google Photos, its name ends with "-edited"
+    pub img_edited: Option<PathBuf>,
+    pub json: Option<PathBuf>,
+}
+impl MediaGroup {
+    /// The files of the group that are present: image, edited image and json file.
+    pub fn files(&self) -> impl Iterator<Item = &PathBuf> {
+        [&self.img, &self.img_edited, &self.json]
+            .into_iter()
+            .flatten()
+    }
+
+    fn from_pair(pair: Pair) -> Self {
+        Self {
+            img: pair.img,
+            img_edited: pair.img_edited,
+            json: pair.json,
+        }
+    }
+}
+
+/// The metadata of a group as it is now and as a run would leave it, for checking it before running.
+#[derive(Debug)]
+pub struct GroupPreview {
+    /// `None` if the group has no json file
+    pub metadata: Option<io::Result<TakeoutExif>>,
+    pub images: Vec<ImagePreview>,
+}
+
+/// An image of a [`GroupPreview`].
+#[derive(Debug)]
+pub struct ImagePreview {
+    pub path: PathBuf,
+    /// `None` if the file type can't be shown
+    pub thumbnail: Option<Thumbnail>,
+    /// The EXIF tags the image has now
+    pub tags: Vec<Tag>,
+    pub xmp: Option<String>,
+    /// Every tag the image would have after a run, next to its current value. Tags that aren't written keep their
+    /// value. Empty if a run leaves the image untouched.
+    pub changes: Vec<TagChange>,
+    /// Why the image or its tags couldn't be read
+    pub error: Option<String>,
+}
+
+/// An image of the Takeout as it is listed in the gallery, with what it can be filtered by.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GalleryItem {
+    /// The image that is shown, the original one if the group has both
+    pub path: PathBuf,
+    pub group: MediaGroup,
+    /// Name of the folder the image is in, Google puts every album into its own folder
+    pub album: String,
+    /// When the photo was taken, or uploaded if that isn't known
+    pub year: Option<i32>,
+    pub people: Vec<String>,
+    pub has_gps: bool,
+    /// Why the json file couldn't be read
+    pub error: Option<String>,
+}
+
+/// A Takeout that was opened for reading. Its files are never modified: folders and zip archives are read in place,
+/// other archives are extracted next to them first, into the same folder a run writing in place uses.
+pub struct TakeoutLibrary {
+    source: PathBuf,
+    files: Box<dyn TakeoutFiles + Send>,
+    groups: Vec<MediaGroup>,
+}
+impl TakeoutLibrary {
+    /// Open `source` with the links that were saved for it, see [`ManualLinks`].
+    pub fn open(source: &Path) -> io::Result<Self> {
+        Self::open_with(source, &ManualLinks::load(source)?, &RunControl::default())
+    }
+
+    /// Open `source`, pairing files by their names and `links` only. Without links, it shows which files still need to
+    /// be paired by hand. Extracting an archive stops once `control` is cancelled, which fails with
+    /// [`io::ErrorKind::Interrupted`].
+    pub fn open_with(source: &Path, links: &ManualLinks, control: &RunControl) -> io::Result<Self> {
+        let mut library = Self {
+            source: source.to_owned(),
+            files: stream::open_takeout(source, control)?,
+            groups: Vec::new(),
+        };
+        library.relink(links);
+        Ok(library)
+    }
+
+    /// Pair the files again by their names and `links` only, e.g. after links were made by hand.
+    pub fn relink(&mut self, links: &ManualLinks) {
+        let mut groups = pair::create_pairs(self.files.names());
+        links.apply(&mut groups, Path::new(""));
+        let mut groups: Vec<_> = groups.into_iter().collect();
+        groups.sort_by(|(a, _), (b, _)| a.cmp(b));
+        self.groups = groups
+            .into_iter()
+            .map(|(_, pair)| MediaGroup::from_pair(pair))
+            .collect();
+    }
+
+    pub fn source(&self) -> &Path {
+        &self.source
+    }
+
+    /// All files of the Takeout, grouped by the image they belong to and in a stable order.
+    pub fn media_groups(&self) -> &[MediaGroup] {
+        &self.groups
+    }
+
+    /// Read a file of the Takeout, `name` is relative to its root.
+    pub fn read(&mut self, name: &Path) -> io::Result<Vec<u8>> {
+        self.files.read(name)
+    }
+
+    /// Read and parse the json file of a group. Returns `None` if the group has no json file.
+    pub fn read_metadata(&mut self, group: &MediaGroup) -> Option<io::Result<TakeoutExif>> {
+        let json = group.json.as_ref()?;
+        Some(self.read(json).and_then(|contents| {
+            let contents = String::from_utf8(contents)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            TakeoutExif::from_json(&contents)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        }))
+    }
+
+    /// Read everything about the metadata of `group` that a run would use or change. Thumbnails fit into a square of
+    /// `thumbnail_size` pixels.
+    pub fn preview(
+        &mut self,
+        group: &MediaGroup,
+        options: &MetadataOptions,
+        thumbnail_size: u32,
+    ) -> GroupPreview {
+        let metadata = self.read_metadata(group);
+        let images = [&group.img, &group.img_edited]
+            .into_iter()
+            .flatten()
+            .map(|path| {
+                let mut preview = ImagePreview {
+                    path: path.clone(),
+                    thumbnail: None,
+                    tags: Vec::new(),
+                    xmp: None,
+                    changes: Vec::new(),
+                    error: None,
+                };
+                let bytes = match self.read(path) {
+                    Ok(bytes) => bytes,
+                    Err(err) => {
+                        preview.error = Some(err.to_string());
+                        return preview;
+                    }
+                };
+                preview.thumbnail = thumbnail::thumbnail(path, &bytes, thumbnail_size).ok();
+                preview.xmp = exif_data::read_xmp(&bytes);
+                let tags = exif_data::read_tags(path, &bytes).and_then(|tags| {
+                    let changes = match metadata.as_ref() {
+                        Some(Ok(exif)) if options.applies_to(path) => {
+                            exif.diff(path, &bytes, options)?
+                        }
+                        _ => Vec::new(),
+                    };
+                    Ok((tags, changes))
+                });
+                match tags {
+                    Ok((tags, changes)) => {
+                        preview.tags = tags;
+                        preview.changes = changes;
+                    }
+                    Err(err) => preview.error = Some(err.to_string()),
+                }
+                preview
+            })
+            .collect();
+        GroupPreview { metadata, images }
+    }
+
+    /// Every image of the Takeout with the metadata of its json file, in the order of [`Self::media_groups`]. Groups
+    /// without an image aren't listed.
+    pub fn gallery(&mut self) -> Vec<GalleryItem> {
+        let groups = self.groups.clone();
+        groups
+            .into_iter()
+            .filter_map(|group| {
+                let path = group.img.clone().or_else(|| group.img_edited.clone())?;
+                let album = path
+                    .parent()
+                    .and_then(Path::file_name)
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                let mut item = GalleryItem {
+                    path,
+                    group,
+                    album,
+                    year: None,
+                    people: Vec::new(),
+                    has_gps: false,
+                    error: None,
+                };
+                match self.read_metadata(&item.group) {
+                    Some(Ok(metadata)) => {
+                        item.year = metadata
+                            .photo_taken_time()
+                            .or_else(|| metadata.creation_time())
+                            .map(|t| t.year());
+                        item.people = metadata.people().map(str::to_owned).collect();
+                        item.has_gps = metadata.geo_data().is_some_and(|g| g.is_known());
+                    }
+                    Some(Err(err)) => item.error = Some(err.to_string()),
+                    None => {}
+                }
+                Some(item)
+            })
+            .collect()
+    }
+
+    /// When each image was taken according to `options`, as its date would be written by a run.
+    pub fn timeline(&mut self, options: &MetadataOptions) -> Timeline {
+        let groups = self.groups.clone();
+        let mut dates = Vec::new();
+        for group in groups {
+            let date = self
+                .read_metadata(&group)
+                .and_then(Result::ok)
+                .and_then(|metadata| metadata.date(options.date_source))
+                .map(|date| options.time_zone.to_local(date));
+            for img in [group.img, group.img_edited].into_iter().flatten() {
+                dates.push((img, date));
+            }
+        }
+        Timeline::new(dates)
+    }
+
+    /// The thumbnail of the image `path` from `cache`, decoding it if it isn't cached yet. Thumbnails fit into a square
+    /// of `size` pixels.
+    pub fn cached_thumbnail(
+        &mut self,
+        path: &Path,
+        size: u32,
+        cache: &ThumbnailCache,
+    ) -> io::Result<Thumbnail> {
+        // unsupported files aren't read at all, they may be large videos
+        if !thumbnail::is_supported(path) {
+            return Err(io::Error::other("Can't show a preview of this file type"));
+        }
+        let file_size = self.files.size(path);
+        cache.get_or_insert(path, file_size, size, || {
+            let bytes = self.read(path)?;
+            thumbnail::thumbnail(path, &bytes, size)
+        })
+    }
+
+    /// Read what is needed to pair an image without json file by hand. Thumbnails fit into a square of
+    /// `thumbnail_size` pixels.
+    pub fn orphan_image(&mut self, path: &Path, thumbnail_size: u32) -> OrphanImage {
+        let bytes = self.read(path).unwrap_or_default();
+        OrphanImage {
+            path: path.to_owned(),
+            thumbnail: thumbnail::thumbnail(path, &bytes, thumbnail_size).ok(),
+            taken: exif_data::read_date_taken(path, &bytes),
+        }
+    }
+
+    /// Read what is needed to pair a json file without image by hand. Unreadable json files are shown by their name
+    /// only.
+    pub fn orphan_json(&mut self, path: &Path) -> OrphanJson {
+        let group = MediaGroup {
+            json: Some(path.to_owned()),
+            ..Default::default()
+        };
+        let metadata = self.read_metadata(&group).and_then(Result::ok);
+        OrphanJson {
+            path: path.to_owned(),
+            title: metadata.as_ref().and_then(|m| m.title()).map(str::to_owned),
+            taken: metadata.as_ref().and_then(|m| m.photo_taken_time()),
+        }
+    }
+
+    /// Count how the files pair up.
+    pub fn summary(&self) -> ScanSummary {
+        let mut summary = ScanSummary::default();
+        for group in &self.groups {
+            let files = [&group.json, &group.img, &group.img_edited];
+            summary.files += files.into_iter().flatten().count();
+            summary.pairs += 1;
+            summary.images += group.img.iter().count();
+            summary.edited_images += group.img_edited.iter().count();
+            summary.json_files += group.json.iter().count();
+            match group {
+                MediaGroup {
+                    json: None,
+                    img,
+                    img_edited,
+                } => summary
+                    .images_without_json
+                    .extend(img.iter().chain(img_edited).cloned()),
+                MediaGroup {
+                    json: Some(json),
+                    img: None,
+                    img_edited: None,
+                } => summary.json_without_image.push(json.clone()),
+                _ => {}
+            }
+        }
+        summary.images_without_json.sort();
+        summary.json_without_image.sort();
+        summary
+    }
+
+    /// Hand the files to the dry run, which reads them on its own.
+    pub(super) fn into_parts(self) -> (Box<dyn TakeoutFiles + Send>, Vec<MediaGroup>) {
+        (self.files, self.groups)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::{
+        journal::Journal,
+        test_utils::{takeout_tgz, takeout_zip},
+        utils,
+    };
+
+    #[test]
+    fn zip_and_folder_have_same_groups() {
+        let source = takeout_zip("./test-assets/zip_and_folder_have_same_groups.zip");
+        let zip = TakeoutLibrary::open(&source).unwrap();
+        let dir = TakeoutLibrary::open(Path::new("./test-assets/takeout")).unwrap();
+
+        assert_eq!(zip.media_groups(), dir.media_groups());
+        assert_eq!(zip.summary(), dir.summary());
+
+        // cleanup
+        std::fs::remove_file(source).unwrap();
+    }
+
+    #[test]
+    fn gallery_lists_images_with_metadata() {
+        let source = takeout_zip("./test-assets/gallery_lists_images_with_metadata.zip");
+        let mut library = TakeoutLibrary::open(&source).unwrap();
+
+        let items = library.gallery();
+
+        assert_eq!(items.len(), 5);
+        let jpg = items
+            .iter()
+            .find(|i| i.path == Path::new("takeout/TEST_JPG.jpg"))
+            .unwrap();
+        assert_eq!(jpg.album, "takeout");
+        assert_eq!(jpg.year, Some(2019));
+        assert!(jpg.error.is_none());
+        let edited = items
+            .iter()
+            .find(|i| i.path == Path::new("takeout/edited/TEST_JPG-edited.jpg"))
+            .unwrap();
+        assert_eq!(edited.album, "edited");
+        assert_eq!(edited.year, None);
+
+        // cleanup
+        std::fs::remove_file(source).unwrap();
+    }
+
+    #[test]
+    fn timeline_uses_date_source() {
+        let source = takeout_zip("./test-assets/timeline_uses_date_source.zip");
+        let mut library = TakeoutLibrary::open(&source).unwrap();
+        let options = MetadataOptions {
+            date_source: exif_data::DateSource::PhotoTaken,
+            ..Default::default()
+        };
+
+        let timeline = library.timeline(&options);
+
+        let july_2019 = timeline
+            .months
+            .iter()
+            .find(|m| (m.year, m.month) == (2019, 7))
+            .unwrap();
+        assert!(
+            july_2019
+                .files
+                .contains(&PathBuf::from("takeout/TEST_JPG.jpg"))
+        );
+        // the edited images and the image whose json file isn't paired by its name
+        assert_eq!(timeline.undated.len(), 3);
+
+        // cleanup
+        std::fs::remove_file(source).unwrap();
+    }
+
+    #[test]
+    fn saved_links_pair_orphans() {
+        let source = Path::new("./test-assets/saved_links_pair_orphans.zip");
+        takeout_zip(source);
+        let img =
+            PathBuf::from("takeout/other/319580_10102651624550024_127913296_n_1010265162.jpg");
+        let json =
+            PathBuf::from("takeout/other/319580_10102651624550024_127913296_n_101026516.json");
+        let mut links = ManualLinks::default();
+        links.link(img.clone(), json.clone());
+        links.save(source).unwrap();
+
+        let linked = TakeoutLibrary::open(source).unwrap().summary();
+        let mut unlinked =
+            TakeoutLibrary::open_with(source, &ManualLinks::default(), &RunControl::default())
+                .unwrap();
+
+        assert!(!linked.images_without_json.contains(&img));
+        assert!(linked.json_without_image.is_empty());
+        assert_eq!(unlinked.summary().json_without_image, vec![json.clone()]);
+        let orphan = unlinked.orphan_json(&json);
+        assert!(orphan.taken.is_some());
+        unlinked.relink(&links);
+        assert_eq!(unlinked.summary(), linked);
+
+        // cleanup
+        std::fs::remove_file(source).unwrap();
+        std::fs::remove_file(ManualLinks::path_for(source)).unwrap();
+    }
+
+    #[test]
+    fn metadata_is_parsed() {
+        let source = takeout_zip("./test-assets/metadata_is_parsed.zip");
+        let mut library = TakeoutLibrary::open(&source).unwrap();
+        let group = library
+            .media_groups()
+            .iter()
+            .find(|g| g.img.as_deref() == Some(Path::new("takeout/TEST_JPG.jpg")))
+            .cloned()
+            .unwrap();
+
+        let metadata = library.read_metadata(&group).unwrap().unwrap();
+
+        assert_eq!(metadata.title(), Some("IMG_0701-EFFECTS.jpg"));
+        assert_eq!(metadata.photo_taken_time().unwrap().timestamp(), 1562782285);
+
+        // cleanup
+        std::fs::remove_file(source).unwrap();
+    }
+
+    #[test]
+    fn tgz_is_extracted_once_and_can_be_cancelled() {
+        let source = takeout_tgz("./test-assets/tgz_is_extracted_once.tgz");
+        let extracted = utils::working_dir(&source);
+        let control = RunControl::default();
+        control.cancel();
+
+        let cancelled = TakeoutLibrary::open_with(&source, &ManualLinks::default(), &control);
+        let extracted_by_cancelled = extracted.exists();
+        let opened = TakeoutLibrary::open(&source).unwrap();
+        let img = extracted.join("takeout/TEST_JPG.jpg");
+        std::fs::write(&img, b"modified").unwrap();
+        let reopened = TakeoutLibrary::open(&source).unwrap();
+
+        // assert
+        assert_eq!(
+            cancelled.err().map(|e| e.kind()),
+            Some(io::ErrorKind::Interrupted)
+        );
+        assert!(!extracted_by_cancelled);
+        assert_eq!(opened.media_groups(), reopened.media_groups());
+        // the extracted files are not overwritten, e.g. after a run wrote to them
+        assert_eq!(std::fs::read(&img).unwrap(), b"modified");
+
+        // cleanup
+        std::fs::remove_file(&source).unwrap();
+        std::fs::remove_dir_all(&extracted).unwrap();
+        std::fs::remove_file(Journal::path_for_dir(&extracted)).unwrap();
+    }
+
+    #[test]
+    fn preview_shows_current_and_proposed_tags() {
+        let source = takeout_zip("./test-assets/preview_shows_current_and_proposed_tags.zip");
+        let mut library = TakeoutLibrary::open(&source).unwrap();
+        let group = library
+            .media_groups()
+            .iter()
+            .find(|g| g.img.as_deref() == Some(Path::new("takeout/TEST_JPG.jpg")))
+            .cloned()
+            .unwrap();
+
+        let preview = library.preview(&group, &MetadataOptions::default(), 64);
+
+        assert!(matches!(preview.metadata, Some(Ok(_))));
+        let image = &preview.images[0];
+        assert!(image.error.is_none());
+        assert!(image.thumbnail.is_some());
+        assert!(!image.tags.is_empty());
+        assert!(image.changes.iter().any(|c| c.tag == "DateTimeOriginal"));
+        // the proposed tags include the current ones that aren't written
+        for tag in &image.tags {
+            assert!(image.changes.iter().any(|c| c.tag == tag.name));
+        }
+
+        // cleanup
+        std::fs::remove_file(source).unwrap();
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{pair::Pair, thumbnail::Thumbnail, utils};
+
+/// Images and json files the user paired by hand, because their names don't match the way Google usually names them.
+/// Links are saved next to the Takeout and used by every run and preview of it. Paths are relative to the root of the
+/// Takeout.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManualLinks {
+    /// The json file of each image
+    links: BTreeMap<PathBuf, PathBuf>,
+}
+impl ManualLinks {
+    /// Where the links of `source` are saved. All parts of a multi-part archive share the same links.
+    pub fn path_for(source: &Path) -> PathBuf {
+        utils::next_to_source(source, "links.json")
+    }
+
+    /// Read the links of `source`. A Takeout without saved links has none.
+    pub fn load(source: &Path) -> io::Result<Self> {
+        let path = Self::path_for(source);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        serde_json::from_slice(&fs::read(path)?).map_err(io::Error::other)
+    }
+
+    /// Save the links of `source`, so runs on it use them.
+    pub fn save(&self, source: &Path) -> io::Result<()> {
+        let path = Self::path_for(source);
+        if self.links.is_empty() {
+            return match fs::remove_file(path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+                _ => Ok(()),
+            };
+        }
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+
+    /// Pair `img` with `json`. A json file only belongs to one image, so a previous link of it is replaced.
+    pub fn link(&mut self, img: PathBuf, json: PathBuf) {
+        self.links.retain(|_, j| *j != json);
+        self.links.insert(img, json);
+    }
+
+    pub fn unlink(&mut self, img: &Path) {
+        self.links.remove(img);
+    }
+
+    /// The json file `img` was linked to.
+    pub fn json_for(&self, img: &Path) -> Option<&Path> {
+        self.links.get(img).map(PathBuf::as_path)
+    }
+
+    /// Whether `json` was linked to an image.
+    pub fn is_linked(&self, json: &Path) -> bool {
+        self.links.values().any(|j| j == json)
+    }
+
+    /// Images and the json files they were linked to.
+    pub fn iter(&self) -> impl Iterator<Item = (&Path, &Path)> {
+        self.links.iter().map(|(i, j)| (i.as_path(), j.as_path()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+
+    /// Move linked json files into the pairs of their images. Paths of `pairs` are the ones of the links joined to
+    /// `root`. Links are skipped if their image already has a json file or their json file already has an image, e.g.
+    /// because the files were renamed since.
+    pub(super) fn apply(&self, pairs: &mut HashMap<String, Pair>, root: &Path) {
+        let mut json_only = HashMap::new();
+        let mut without_json = HashMap::new();
+        for (key, pair) in pairs.iter() {
+            match pair {
+                Pair {
+                    json: Some(json),
+                    img: None,
+                    img_edited: None,
+                } => {
+                    json_only.insert(json.clone(), key.clone());
+                }
+                Pair {
+                    json: None,
+                    img,
+                    img_edited,
+                } => {
+                    for p in img.iter().chain(img_edited) {
+                        without_json.insert(p.clone(), key.clone());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for (img, json) in &self.links {
+            let json = root.join(json);
+            let (Some(img_key), Some(json_key)) =
+                (without_json.get(&root.join(img)), json_only.get(&json))
+            else {
+                continue;
+            };
+            // the image may have gotten a json file through a link of its edited version
+            if let Some(pair) = pairs.get_mut(img_key)
+                && pair.json.is_none()
+            {
+                pair.json = Some(json);
+                pairs.remove(json_key);
+            }
+        }
+    }
+}
+
+/// An image no json file belongs to.
+#[derive(Debug, Clone)]
+pub struct OrphanImage {
+    pub path: PathBuf,
+    /// `None` if the file type can't be shown
+    pub thumbnail: Option<Thumbnail>,
+    /// When the photo was taken according to its EXIF data, in the time zone of the camera
+    pub taken: Option<NaiveDateTime>,
+}
+impl OrphanImage {
+    /// Rank json files by how likely they belong to this image, most likely first. Returns indices into `jsons` with
+    /// a score from 0 to 2: up to 1 for how similar the names are and up to 1 for how close the dates are.
+    pub fn suggest<'a>(
+        &self,
+        jsons: impl IntoIterator<Item = &'a OrphanJson>,
+    ) -> Vec<(usize, f64)> {
+        let img_name = self.path.to_string_lossy();
+        let img_name = stem(&img_name);
+        let mut ranked: Vec<_> = jsons
+            .into_iter()
+            .enumerate()
+            .map(|(i, json)| {
+                // Google cuts off long names of json files, the title is the full name
+                let json_name = json.path.to_string_lossy();
+                let json_name = stem(json_name.strip_suffix(".json").unwrap_or(&json_name));
+                let title = json.title.as_deref().map(stem).unwrap_or_default();
+                let name = similarity(img_name, json_name).max(similarity(img_name, title));
+                // the EXIF date has no time zone, so dates that are hours apart may still be the same
+                let date = match (self.taken, json.taken) {
+                    (Some(a), Some(b)) => {
+                        let hours = (a - b.naive_utc()).num_minutes().abs() as f64 / 60.0;
+                        1.0 / (1.0 + hours)
+                    }
+                    _ => 0.0,
+                };
+                (i, name + date)
+            })
+            .collect();
+        ranked.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        ranked
+    }
+}
+
+/// A json file no image belongs to.
+#[derive(Debug, Clone)]
+pub struct OrphanJson {
+    pub path: PathBuf,
+    /// File name of the image it was uploaded as
+    pub title: Option<String>,
+    pub taken: Option<DateTime<Utc>>,
+}
+
+/// File name without directories and extension.
+fn stem(path: &str) -> &str {
+    let name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    name.rsplit_once('.').map_or(name, |(stem, _)| stem)
+}
+
+/// How many pairs of adjacent characters two strings share, from 0 for none to 1 for the same strings. Case is
+/// ignored.
+fn similarity(a: &str, b: &str) -> f64 {
+    let bigrams = |s: &str| {
+        let chars: Vec<_> = s.to_lowercase().chars().collect();
+        chars.windows(2).map(|w| (w[0], w[1])).collect::<Vec<_>>()
+    };
+    let a = bigrams(a);
+    let mut b = bigrams(b);
+    let total = a.len() + b.len();
+    if total == 0 {
+        return 0.0;
+    }
+    let mut shared = 0;
+    for bigram in a {
+        if let Some(i) = b.iter().position(|b| *b == bigram) {
+            b.swap_remove(i);
+            shared += 1;
+        }
+    }
+    2.0 * shared as f64 / total as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::services::pair::create_pairs;
+
+    #[test]
+    fn linked_json_is_moved_into_image_pair() {
+        let img = PathBuf::from("takeout/IMG_1.jpg");
+        let json = PathBuf::from("takeout/photo.json");
+        let mut pairs = create_pairs(HashSet::from([
+            Path::new("root").join(&img),
+            Path::new("root").join(&json),
+        ]));
+        let mut links = ManualLinks::default();
+        links.link(img.clone(), json.clone());
+
+        links.apply(&mut pairs, Path::new("root"));
+
+        assert_eq!(pairs.len(), 1);
+        let pair = pairs.values().next().unwrap();
+        assert_eq!(pair.img, Some(Path::new("root").join(img)));
+        assert_eq!(pair.json, Some(Path::new("root").join(json)));
+    }
+
+    #[test]
+    fn json_is_only_linked_once() {
+        let mut links = ManualLinks::default();
+        links.link("a.jpg".into(), "a.json".into());
+        links.link("b.jpg".into(), "a.json".into());
+
+        assert_eq!(links.json_for(Path::new("a.jpg")), None);
+        assert_eq!(
+            links.json_for(Path::new("b.jpg")),
+            Some(Path::new("a.json"))
+        );
+    }
+
+    #[test]
+    fn links_are_saved_next_to_takeout() {
+        let source = Path::new("./test-assets/links_are_saved_next_to_takeout");
+        fs::create_dir_all(source).unwrap();
+        let mut links = ManualLinks::default();
+        links.link("a.jpg".into(), "b.json".into());
+
+        links.save(source).unwrap();
+        let loaded = ManualLinks::load(source).unwrap();
+        ManualLinks::default().save(source).unwrap();
+
+        assert_eq!(loaded, links);
+        assert!(!ManualLinks::path_for(source).exists());
+
+        // cleanup
+        fs::remove_dir_all(source).unwrap();
+    }
+
+    #[test]
+    fn similar_names_and_dates_rank_first() {
+        let taken = DateTime::from_timestamp(1562782285, 0).unwrap();
+        let img = OrphanImage {
+            path: "takeout/319580_10102651624550024_127913296_n_1010265162.jpg".into(),
+            thumbnail: None,
+            taken: Some(taken.naive_utc()),
+        };
+        let jsons = [
+            OrphanJson {
+                path: "takeout/IMG_0701.jpg.json".into(),
+                title: Some("IMG_0701.jpg".to_owned()),
+                taken: None,
+            },
+            OrphanJson {
+                path: "takeout/319580_10102651624550024_127913296_n_101026516.json".into(),
+                title: None,
+                taken: None,
+            },
+            OrphanJson {
+                path: "takeout/IMG_0702.jpg.json".into(),
+                title: Some("IMG_0702.jpg".to_owned()),
+                // a camera in another time zone
+                taken: Some(taken + chrono::Duration::hours(3)),
+            },
+        ];
+
+        let ranked = img.suggest(&jsons);
+
+        assert_eq!(ranked[0].0, 1);
+        assert_eq!(ranked[1].0, 2);
+    }
+}
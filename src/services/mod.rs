@@ -1,39 +1,1135 @@
 use std::{
-    io,
+    collections::HashSet,
+    fs, io,
     path::{Path, PathBuf},
     sync::mpsc,
+    thread,
 };
 
+mod control;
+mod dry_run;
+mod error;
 mod exif_data;
+mod journal;
+mod library;
+mod links;
+mod output;
 mod pair;
+mod pipeline;
+mod progress;
+mod stream;
+mod summary;
+#[cfg(test)]
+mod test_utils;
+mod thumbnail;
+mod timeline;
+mod undo;
 mod utils;
 
+pub use control::RunControl;
+pub use dry_run::{DryRunReport, FileDiff, dry_run};
+pub use error::{Error, ErrorGroup, ErrorLog, Phase};
+pub use exif_data::{
+    DateSource, EditedMode, GeoData, JsonParseError, MetadataOptions, Tag, TagChange, TakeoutExif,
+    TimeZoneMode,
+};
+pub use library::{GalleryItem, GroupPreview, ImagePreview, MediaGroup, TakeoutLibrary};
+pub use links::{ManualLinks, OrphanImage, OrphanJson};
+pub use output::{ArchiveFormat, ArchiveOptions, LinkMode, OutputMode};
+pub use pair::PairError;
+pub use pipeline::Parallelism;
+pub use progress::{Event, Progress, Stage};
+pub use summary::RunSummary;
+pub use thumbnail::{Thumbnail, ThumbnailCache};
+pub use timeline::{Month, Timeline};
+
+use journal::Journal;
+use output::{ArchiveSink, DirSink, MediaSink, OutputTree};
+use undo::UndoStore;
+
+/// Settings of a run of [`extract_and_apply_metadata`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunOptions {
+    /// Where the results are written to
+    pub output: OutputMode,
+    /// How many files are worked on at the same time
+    pub parallelism: Parallelism,
+    /// Process files again even if a previous run already wrote them with metadata from the same json file and the
+    /// same metadata options
+    pub force: bool,
+    /// Only process the pairs one of these files belongs to, e.g. to retry the files that failed. Paths are the ones
+    /// errors are reported with. Packaging into an archive always starts from scratch, so the archive would only
+    /// contain these files.
+    pub only: Option<HashSet<PathBuf>>,
+    /// Which metadata is written and how
+    pub metadata: exif_data::MetadataOptions,
+}
+impl RunOptions {
+    /// Whether the pair with these files is part of the run according to [`Self::only`].
+    fn includes<'a>(&self, mut files: impl Iterator<Item = &'a PathBuf>) -> bool {
+        self.only
+            .as_ref()
+            .is_none_or(|only| files.any(|p| only.contains(p)))
+    }
+}
+
+/// Apply the metadata from the Takeout json files to their media. `source` is either a Takeout archive (zip or tgz, any
+/// part of a multi-part set), which is extracted first, or a directory containing an already extracted Takeout.
+///
+/// When writing to a separate output directory or a new archive, zip archives are processed while streaming out of the
+/// archive instead, so the raw extraction never has to fit on the disk.
+///
+/// Progress is journaled next to the directory that is written to. If a run is restarted or repeated, completely
+/// extracted archive parts and files that were written and haven't been modified since are skipped, unless their json
+/// file or the metadata options changed or `force` is set. This keeps metadata from being applied twice to the same
+/// file. Archives can't be appended to, so packaging into a new archive always starts from scratch.
+///
+/// When writing in place, the original bytes of every modified file are kept in an undo store next to the directory,
+/// see [`undo`].
+///
+/// Json files are parsed and metadata is written on a pool of worker threads. Errors are reported in the same order for
+/// every run.
+///
+/// Progress and errors are sent through `tx` as they happen, a failing file never stops the run. [`Event::Done`] is
+/// always sent last. The run can be paused and cancelled through `control`, it then stops after finishing the files it
+/// is working on. Cancelled runs continue where they stopped when they are started again, except for packaging into an
+/// archive: the parts written so far are deleted, since the archive would always start from scratch anyway.
 pub fn extract_and_apply_metadata(
-    zip_path: &Path,
-    rx: &mpsc::Receiver<()>,
-    tx: &mpsc::Sender<Option<(PathBuf, io::Error)>>,
+    source: &Path,
+    options: &RunOptions,
+    control: &RunControl,
+    tx: &mpsc::Sender<Event>,
 ) {
-    let working_dir = utils::unzip(zip_path);
-    let file_names = utils::recursively_collect_filenames(&working_dir).unwrap();
-    let pairs = pair::create_pairs(file_names);
-    for pair in pairs.values() {
-        let json = if let Some(json) = pair.read_json() {
-            json.unwrap()
-        } else {
-            continue;
+    let (run_tx, run_rx) = mpsc::channel();
+    let summary = thread::scope(|s| {
+        let forwarded = s.spawn(|| forward_events(run_rx, tx));
+        run(source, options, control, &run_tx);
+        drop(run_tx);
+        forwarded
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    });
+    if let Some(summary) = summary {
+        send(tx, Event::Summary(summary));
+    }
+    if control.is_cancelled() {
+        send(tx, Event::Cancelled);
+    }
+    send(tx, Event::Done);
+}
+
+/// Pass the events of a run on to `tx` as they come in, except for its summary. It's returned once the run is done
+/// instead, with every error of the run counted as failed, including the ones that were sent after it.
+fn forward_events(rx: mpsc::Receiver<Event>, tx: &mpsc::Sender<Event>) -> Option<RunSummary> {
+    let mut summary = None;
+    let mut errors = 0;
+    for event in rx {
+        match event {
+            Event::Summary(run_summary) => summary = Some(run_summary),
+            event => {
+                if matches!(event, Event::Error(_)) {
+                    errors += 1;
+                }
+                send(tx, event);
+            }
+        }
+    }
+    summary.map(|summary| RunSummary {
+        failed: errors,
+        ..summary
+    })
+}
+
+fn run(source: &Path, options: &RunOptions, control: &RunControl, tx: &mpsc::Sender<Event>) {
+    let output = &options.output;
+    // files are still paired by their names if the links can't be read
+    let links = ManualLinks::load(source).unwrap_or_else(|err| {
+        send(
+            tx,
+            Event::Error(Error::Read {
+                path: ManualLinks::path_for(source),
+                source: err,
+            }),
+        );
+        ManualLinks::default()
+    });
+    let is_zip = |p: &PathBuf| {
+        matches!(
+            utils::ArchiveKind::detect(p),
+            Ok(Some(utils::ArchiveKind::Zip))
+        )
+    };
+    let open_journal = |dir: &Path| {
+        Journal::for_dir(dir).map_err(|source| Error::Write {
+            path: Journal::path_for_dir(dir),
+            source,
+        })
+    };
+    match output {
+        OutputMode::Tree { dir, .. } if !source.is_dir() => {
+            let parts = utils::archive_parts(source);
+            if parts.iter().all(is_zip) {
+                // every file is written out of the archive, so the link mode doesn't apply
+                send(tx, Event::Stage(Stage::Indexing));
+                let files = stream::ZipSet::open(&parts).map_err(|err| Error::Read {
+                    path: source.to_owned(),
+                    source: err,
+                });
+                match files.and_then(|files| Ok((files, open_journal(dir)?))) {
+                    Ok((mut files, journal)) => {
+                        let mut sink = DirSink::new(dir.clone(), journal);
+                        stream::stream(&mut files, &mut sink, &links, options, control, tx);
+                    }
+                    Err(err) => send(tx, Event::Error(err)),
+                }
+                return;
+            }
+        }
+        OutputMode::Archive(archive) => {
+            send(tx, Event::Stage(Stage::Indexing));
+            let files = stream::open_takeout(source, control).map_err(|err| Error::Read {
+                path: source.to_owned(),
+                source: err,
+            });
+            // nothing was written yet
+            if control.is_cancelled() {
+                return;
+            }
+            let sink = ArchiveSink::new(archive.clone()).map_err(|err| Error::Write {
+                path: archive.path.clone(),
+                source: err,
+            });
+            match files.and_then(|files| Ok((files, sink?))) {
+                Ok((mut files, mut sink)) => {
+                    stream::stream(files.as_mut(), &mut sink, &links, options, control, tx);
+                    let finished = if control.is_cancelled() {
+                        sink.discard()
+                    } else {
+                        sink.finish()
+                    };
+                    if let Err(err) = finished {
+                        let path = archive.path.clone();
+                        send(tx, Event::Error(Error::Write { path, source: err }));
+                    }
+                }
+                Err(err) => send(tx, Event::Error(err)),
+            }
+            return;
+        }
+        _ => {}
+    }
+
+    let setup = match (source.is_dir(), output) {
+        (true, OutputMode::InPlace) => {
+            open_journal(source).map(|journal| (OutputTree::in_place(source.to_owned()), journal))
+        }
+        (true, OutputMode::Tree { dir, link }) => open_journal(dir).map(|journal| {
+            let tree = OutputTree::new(source.to_owned(), dir.clone(), *link);
+            (tree, journal)
+        }),
+        // the archive itself is never modified, so the output directory doubles as the extraction directory and is
+        // written in place. The link mode doesn't apply, every extracted file is a new file anyway.
+        (false, OutputMode::InPlace | OutputMode::Tree { .. }) => {
+            let dir = match output {
+                OutputMode::Tree { dir, .. } => dir.clone(),
+                _ => utils::working_dir(source),
+            };
+            open_journal(&dir).map(|mut journal| {
+                send(tx, Event::Stage(Stage::Extracting));
+                let (working_dir, errors) = utils::extract_to(source, &dir, &mut journal, control);
+                // whatever could be extracted is still processed
+                for err in errors {
+                    send(tx, Event::Error(err));
+                }
+                (OutputTree::in_place(working_dir), journal)
+            })
+        }
+        (_, OutputMode::Archive(_)) => unreachable!("Archives are always written while streaming"),
+    };
+    let undo = setup.and_then(|setup| {
+        let undo = match output {
+            OutputMode::InPlace => {
+                let root = setup.0.source_root();
+                UndoStore::for_dir(root).map_err(|source| Error::Write {
+                    path: UndoStore::path_for_dir(root),
+                    source,
+                })?
+            }
+            _ => UndoStore::disabled(),
         };
-        let exif = exif_data::TakeoutExif::from_json(json.as_str()).unwrap();
-        for img in [&pair.img, &pair.img_edited]
+        Ok((setup, undo))
+    });
+    let ((tree, mut journal), mut undo) = match undo {
+        Ok(setup) => setup,
+        Err(err) => {
+            send(tx, Event::Error(err));
+            return;
+        }
+    };
+    if control.is_cancelled() {
+        return;
+    }
+    apply_metadata(&tree, &mut journal, &mut undo, &links, options, control, tx);
+}
+
+/// Restore the files that runs writing in place to `source` modified. Files are only restored if they haven't been
+/// modified since, and are verified against the hash of their original bytes. Returns the files that couldn't be
+/// restored.
+pub fn undo(source: &Path) -> io::Result<Vec<(PathBuf, io::Error)>> {
+    if source.is_dir() {
+        undo::restore_dir(source)
+    } else {
+        undo::restore_dir(&utils::working_dir(source))
+    }
+}
+
+/// The directory a run on `source` writes into, or the one that contains the archive it writes.
+pub fn output_dir(source: &Path, output: &OutputMode) -> PathBuf {
+    match output {
+        OutputMode::InPlace if source.is_dir() => source.to_owned(),
+        OutputMode::InPlace => utils::working_dir(source),
+        OutputMode::Tree { dir, .. } => dir.clone(),
+        OutputMode::Archive(options) => match options.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_owned(),
+            _ => PathBuf::from("."),
+        },
+    }
+}
+
+/// Whether reading `source`, e.g. for a dry run, a run writing an archive or the [`TakeoutLibrary`], extracts it into
+/// the same folder a run writing in place extracts it to. Only zip archives and folders are read where they are.
+pub fn is_extracted_for_reading(source: &Path) -> bool {
+    // an archive that can't be read fails anyway, whichever way it's read
+    stream::is_extracted(source).unwrap_or(true)
+}
+
+/// How the files of a Takeout pair up.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanSummary {
+    pub files: usize,
+    pub pairs: usize,
+    pub images: usize,
+    pub edited_images: usize,
+    pub json_files: usize,
+    /// Images that no json file belongs to, they are left untouched
+    pub images_without_json: Vec<PathBuf>,
+    /// Json files that no image belongs to
+    pub json_without_image: Vec<PathBuf>,
+}
+
+/// Pair the files of `source` without modifying anything. Zip archives are read in place, other archives are
+/// extracted.
+pub fn scan(source: &Path) -> io::Result<ScanSummary> {
+    TakeoutLibrary::open(source).map(|library| library.summary())
+}
+
+/// Whether `path` can be used as the source of [`extract_and_apply_metadata`].
+pub fn is_supported_source(path: &Path) -> bool {
+    path.is_dir() || matches!(utils::ArchiveKind::detect(path), Ok(Some(_)))
+}
+
+/// Pick the source of a run from files the user selected, e.g. by dropping them onto the window. These have to be
+/// either one directory, or one or more parts of the same archive. Parts that weren't selected are found next to them
+/// anyway. Fails if the files are anything else or don't look like a Takeout.
+pub fn source_from_paths(paths: &[PathBuf]) -> io::Result<PathBuf> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidInput, message);
+    let Some(first) = paths.first() else {
+        return Err(invalid("Nothing was selected"));
+    };
+    if paths.iter().any(|p| !is_supported_source(p)) {
+        return Err(invalid(
+            "Only Takeout archives (zip or tgz) and folders are supported",
+        ));
+    }
+    if paths.iter().any(|p| p.is_dir()) && paths.len() > 1 {
+        return Err(invalid(
+            "Select either one folder or the parts of one archive",
+        ));
+    }
+    if !first.is_dir() {
+        let parts = utils::archive_parts(first);
+        if paths.iter().any(|p| !parts.contains(p)) {
+            return Err(invalid(
+                "The archives belong to different Takeouts, select one at a time",
+            ));
+        }
+    }
+    if !utils::looks_like_takeout(first)? {
+        return Err(invalid("This doesn't look like a Google Takeout"));
+    }
+    Ok(first.clone())
+}
+
+/// Send progress and errors to the main thread. The run never waits for them to be handled, so it doesn't matter if
+/// nobody listens anymore.
+fn send(tx: &mpsc::Sender<Event>, event: Event) {
+    let _ = tx.send(event);
+}
+
+fn apply_metadata(
+    tree: &OutputTree,
+    journal: &mut Journal,
+    undo: &mut UndoStore,
+    links: &ManualLinks,
+    options: &RunOptions,
+    control: &RunControl,
+    tx: &mpsc::Sender<Event>,
+) {
+    send(tx, Event::Stage(Stage::Indexing));
+    let (file_names, errors) = utils::collect_filenames(tree.source_root());
+    for err in errors {
+        send(tx, Event::Error(err));
+    }
+
+    send(tx, Event::Stage(Stage::Pairing));
+    let mut pairs = pair::create_pairs(file_names);
+    links.apply(&mut pairs, tree.source_root());
+    let mut pairs: Vec<_> = pairs.into_iter().collect();
+    pairs.sort_by(|(a, _), (b, _)| a.cmp(b));
+    // decided up front, the journal is written while the pipeline runs
+    send(tx, Event::Stage(Stage::Resuming));
+    let mut summary = RunSummary::default();
+    let jobs: Vec<_> = pairs
+        .into_iter()
+        .filter(|(_, pair)| options.includes(pair.files()))
+        .map(|(_, pair)| {
+            summary.count_unmatched(&pair);
+            // read only once, for its hash and later for its metadata. An unreadable json file is reported once its
+            // pair is processed.
+            let json = pair.json.as_ref().map(fs::read);
+            let json_hash = json
+                .as_ref()
+                .and_then(|c| c.as_ref().ok())
+                .map(|c| metadata_hash(c, &options.metadata));
+            let is_pending = |p: &Option<PathBuf>| {
+                p.as_ref().is_some_and(|p| {
+                    options.force
+                        || !journal.is_processed(&tree.destination(p), json_hash.as_deref())
+                })
+            };
+            let pending = [
+                is_pending(&pair.img),
+                is_pending(&pair.img_edited),
+                is_pending(&pair.json),
+            ];
+            summary.skipped += pair.files().count() - pending.iter().filter(|p| **p).count();
+            (pair, json, pending, json_hash)
+        })
+        .filter(|(_, _, pending, _)| pending.contains(&true))
+        .collect();
+
+    let pending_files = jobs.iter().flat_map(|(pair, _, pending, _)| {
+        [&pair.img, &pair.img_edited, &pair.json]
             .into_iter()
-            .filter_map(|i| i.clone())
-        {
-            if let Err(err) = exif.apply_to_image(&img) {
-                tx.send(Some((img, err)))
-                    .expect("Failed to send error to main thread");
-                if let Err(err) = rx.recv() {
-                    panic!("Failed to receive confirmation message: {}", err);
+            .zip(pending)
+            .filter_map(|(p, pending)| p.as_ref().filter(|_| *pending))
+    });
+    let (files, bytes) = pending_files.fold((0, 0), |(files, bytes), p| {
+        (files + 1, bytes + file_size(p))
+    });
+    send(tx, Event::Total { files, bytes });
+    send(tx, Event::Stage(Stage::Writing));
+
+    let backup = undo.is_enabled();
+    pipeline::run_ordered(
+        jobs.into_iter().take_while(|_| control.checkpoint()),
+        options.parallelism,
+        |(pair, json, pending, json_hash)| {
+            let results = process_pair(&pair, json, pending, tree, &options.metadata, backup);
+            (results, json_hash)
+        },
+        |((json_error, results), json_hash)| {
+            if let Some(err) = json_error {
+                send(tx, Event::Error(err));
+            }
+            for (src, bytes, result) in results {
+                send(
+                    tx,
+                    Event::FileDone {
+                        path: src.clone(),
+                        bytes,
+                    },
+                );
+                // the backup is kept before the file counts as processed, so a restarted run can't lose it
+                let recorded = result.and_then(|written| {
+                    let recorded = match written.backup.as_ref() {
+                        Some(backup) => undo.record(backup),
+                        None => Ok(()),
+                    };
+                    recorded
+                        .and_then(|_| journal.record_processed(&written.dest, json_hash.as_deref()))
+                        .map(|_| written.tags)
+                        .map_err(|source| Error::Write {
+                            path: src.clone(),
+                            source,
+                        })
+                });
+                match recorded {
+                    Ok(tags) => summary.written(&src, &tags),
+                    Err(err) => send(tx, Event::Error(err)),
                 }
             }
+        },
+    );
+    send(tx, Event::Summary(summary));
+}
+
+/// What the journal compares besides the state of a processed file: its json file and the options its metadata was
+/// applied with. Changing either, e.g. the date source, processes the file again.
+fn metadata_hash(json: &[u8], metadata: &exif_data::MetadataOptions) -> String {
+    let mut contents = json.to_vec();
+    contents.extend(
+        serde_json::to_vec(&HashedOptions::new(metadata))
+            .expect("Options can always be serialized"),
+    );
+    journal::content_hash(&contents)
+}
+
+/// The metadata options as they are hashed into the journal. Spelled out instead of serializing
+/// [`exif_data::MetadataOptions`] itself, so refactoring it doesn't change the hash of every processed file.
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HashedOptions {
+    /// Increased when the same options lead to different metadata being written
+    version: u32,
+    dates: bool,
+    gps: bool,
+    description: bool,
+    title: bool,
+    people: bool,
+    favorites: bool,
+    date_source: &'static str,
+    time_zone: String,
+    edited: &'static str,
+}
+impl HashedOptions {
+    const VERSION: u32 = 1;
+
+    fn new(metadata: &exif_data::MetadataOptions) -> Self {
+        Self {
+            version: Self::VERSION,
+            dates: metadata.dates,
+            gps: metadata.gps,
+            description: metadata.description,
+            title: metadata.title,
+            people: metadata.people,
+            favorites: metadata.favorites,
+            date_source: match metadata.date_source {
+                DateSource::Upload => "upload",
+                DateSource::PhotoTaken => "photoTaken",
+            },
+            time_zone: match metadata.time_zone {
+                TimeZoneMode::Utc => "utc".to_owned(),
+                TimeZoneMode::Local => "local".to_owned(),
+                TimeZoneMode::Offset(minutes) => format!("{:+}", minutes),
+            },
+            edited: match metadata.edited {
+                EditedMode::Both => "both",
+                EditedMode::OriginalOnly => "originalOnly",
+                EditedMode::EditedOnly => "editedOnly",
+            },
+        }
+    }
+}
+
+/// Size of a file for reporting progress, unreadable files count as empty.
+fn file_size(path: &Path) -> u64 {
+    fs::metadata(path).map(|m| m.len()).unwrap_or_default()
+}
+
+/// A file that was written.
+struct Written {
+    dest: PathBuf,
+    /// What is needed to undo the changes, if backups are kept
+    backup: Option<undo::Backup>,
+    /// Names of the tags that were written to it
+    tags: Vec<&'static str>,
+}
+
+impl Written {
+    /// A file that was placed as it is.
+    fn untouched(dest: PathBuf) -> Self {
+        Self {
+            dest,
+            backup: None,
+            tags: Vec::new(),
         }
     }
 }
+
+/// Source of a file, its size before it was written, and either where it was written, or why it couldn't be written.
+type FileResult = (PathBuf, u64, Result<Written, Error>);
+
+/// Write the files of a pair whose `pending` flags are set (image, edited image, json), with `json` being the contents
+/// of its json file as they were read. Runs on a worker thread. Returns why the json file couldn't be read or parsed,
+/// along with the destination of every file and, if `backup` is set, what is needed to undo its changes. Or why it
+/// couldn't be written. Images whose json file can't be read or parsed, or that `metadata` doesn't apply to, are placed
+/// without modifying them.
+fn process_pair(
+    pair: &pair::Pair,
+    json: Option<io::Result<Vec<u8>>>,
+    pending: [bool; 3],
+    tree: &OutputTree,
+    metadata: &exif_data::MetadataOptions,
+    backup: bool,
+) -> (Option<Error>, Vec<FileResult>) {
+    let mut json_error = None;
+    let mut results = Vec::new();
+    let exif = json.map(pair::parse_json);
+    let exif = match (exif, pair.json.as_ref()) {
+        (Some(Ok(exif)), _) => Some(exif),
+        (Some(Err(source)), Some(json)) => {
+            let path = json.clone();
+            json_error = Some(Error::Json { path, source });
+            None
+        }
+        _ => None,
+    };
+
+    for (img, pending) in [&pair.img, &pair.img_edited].into_iter().zip(pending) {
+        let Some(img) = img.as_ref().filter(|_| pending) else {
+            continue;
+        };
+        let size = file_size(img);
+        let result = match exif.as_ref().filter(|_| metadata.applies_to(img)) {
+            Some(exif) => apply_to_image(exif, img, tree, metadata, backup),
+            None => tree
+                .place_untouched(img)
+                .map(Written::untouched)
+                .map_err(|source| Error::Write {
+                    path: img.clone(),
+                    source,
+                }),
+        };
+        results.push((img.clone(), size, result));
+    }
+    if let Some(json) = pair.json.as_ref().filter(|_| pending[2]) {
+        let result = tree
+            .place_untouched(json)
+            .map(Written::untouched)
+            .map_err(|source| Error::Write {
+                path: json.clone(),
+                source,
+            });
+        results.push((json.clone(), file_size(json), result));
+    }
+    (json_error, results)
+}
+
+/// Stage `img` for writing and apply the metadata to it, keeping a backup of its original bytes if `backup` is set.
+fn apply_to_image(
+    exif: &exif_data::TakeoutExif,
+    img: &Path,
+    tree: &OutputTree,
+    metadata: &exif_data::MetadataOptions,
+    backup: bool,
+) -> Result<Written, Error> {
+    let path = || img.to_owned();
+    let dest = tree.stage_for_writing(img).map_err(|source| Error::Write {
+        path: path(),
+        source,
+    })?;
+    let read = |p: &Path| {
+        fs::read(p).map_err(|source| Error::Read {
+            path: path(),
+            source,
+        })
+    };
+    let original = if backup { Some(read(&dest)?) } else { None };
+    exif.apply_to_image(&dest, metadata)
+        .map_err(|source| Error::Metadata {
+            path: path(),
+            source,
+        })?;
+    let backup = match original {
+        Some(original) => Some(undo::Backup::new(&dest, &original, &read(&dest)?)),
+        None => None,
+    };
+    Ok(Written {
+        dest,
+        backup,
+        tags: exif.tag_names(metadata),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use output::LinkMode;
+    use std::fs;
+
+    #[test]
+    fn scan_pairs_files() {
+        let summary = scan(Path::new("./test-assets/takeout-unzipped")).unwrap();
+
+        assert_eq!(summary.files, 8);
+        assert_eq!(summary.json_files, 3);
+        assert_eq!(summary.images + summary.edited_images, 5);
+    }
+
+    /// Run on `source` until the end and collect every event that was sent.
+    fn run_events(source: &Path, options: &RunOptions, control: &RunControl) -> Vec<Event> {
+        let (tx, rx) = mpsc::channel();
+        extract_and_apply_metadata(source, options, control, &tx);
+        drop(tx);
+        rx.iter().collect()
+    }
+
+    fn summary_of(events: &[Event]) -> &RunSummary {
+        events
+            .iter()
+            .find_map(|e| match e {
+                Event::Summary(summary) => Some(summary),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    /// Copy the extracted Takeout fixture, so tests can modify it.
+    fn copy_unzipped_takeout(dest: &Path) {
+        let fixture = Path::new("./test-assets/takeout-unzipped");
+        for p in utils::recursively_collect_filenames(fixture).unwrap() {
+            let copy = dest.join(p.strip_prefix(fixture).unwrap());
+            fs::create_dir_all(copy.parent().unwrap()).unwrap();
+            fs::copy(&p, &copy).unwrap();
+        }
+    }
+
+    #[test]
+    fn source_is_picked_from_selected_paths() {
+        let zip = test_utils::takeout_zip("./test-assets/source_is_picked_from_selected_paths.zip");
+        let dir = PathBuf::from("./test-assets/takeout-unzipped");
+
+        assert_eq!(source_from_paths(std::slice::from_ref(&zip)).unwrap(), zip);
+        assert_eq!(source_from_paths(std::slice::from_ref(&dir)).unwrap(), dir);
+        assert!(source_from_paths(&[]).is_err());
+        assert!(source_from_paths(&[zip.clone(), dir]).is_err());
+        let tgz = test_utils::takeout_tgz("./test-assets/source_is_picked_from_selected_paths.tgz");
+        assert!(source_from_paths(&[zip.clone(), tgz.clone()]).is_err());
+        assert!(source_from_paths(&[PathBuf::from("./src")]).is_err());
+
+        // cleanup
+        fs::remove_file(zip).unwrap();
+        fs::remove_file(tgz).unwrap();
+    }
+
+    #[test]
+    fn only_tgz_sources_are_extracted_for_reading() {
+        let zip = test_utils::takeout_zip("./test-assets/only_tgz_sources_are_extracted.zip");
+        let tgz = test_utils::takeout_tgz("./test-assets/only_tgz_sources_are_extracted.tgz");
+
+        assert!(!is_extracted_for_reading(&zip));
+        assert!(is_extracted_for_reading(&tgz));
+        assert!(!is_extracted_for_reading(Path::new(
+            "./test-assets/takeout-unzipped"
+        )));
+
+        // cleanup
+        fs::remove_file(zip).unwrap();
+        fs::remove_file(tgz).unwrap();
+    }
+
+    #[test]
+    fn directory_source_is_not_modified_in_tree_mode() {
+        let source = Path::new("./test-assets/takeout-unzipped");
+        let out_dir = "./test-assets/directory_source_is_not_modified_in_tree_mode";
+        let before: Vec<_> = utils::recursively_collect_filenames(source)
+            .unwrap()
+            .into_iter()
+            .map(|p| (fs::read(&p).unwrap(), p))
+            .collect();
+
+        let options = RunOptions {
+            output: OutputMode::Tree {
+                dir: PathBuf::from(out_dir),
+                link: LinkMode::Copy,
+            },
+            ..Default::default()
+        };
+        let events = run_events(source, &options, &RunControl::default());
+
+        // assert
+        for (contents, p) in before {
+            assert_eq!(fs::read(&p).unwrap(), contents);
+        }
+        let written = utils::recursively_collect_filenames(Path::new(out_dir)).unwrap();
+        assert_eq!(written.len(), 8);
+        let total = events.iter().find_map(|e| match e {
+            Event::Total { files, .. } => Some(*files),
+            _ => None,
+        });
+        let done = events
+            .iter()
+            .filter(|e| matches!(e, Event::FileDone { .. }))
+            .count();
+        assert_eq!(total, Some(8));
+        assert_eq!(done, 8);
+        assert!(matches!(events.last(), Some(Event::Done)));
+        let summary = summary_of(&events);
+        assert_eq!(summary.processed + summary.failed, 8);
+        let errors = events
+            .iter()
+            .filter(|e| matches!(e, Event::Error(_)))
+            .count();
+        assert_eq!(summary.failed, errors);
+        assert_eq!(summary.images_without_json, 2);
+        assert!(summary.dates_written > 0);
+        // the tags the camera wrote are kept
+        let jpg = Path::new(out_dir).join("takeout/TEST_JPG.jpg");
+        let tags = exif_data::read_tags(&jpg, &fs::read(&jpg).unwrap()).unwrap();
+        assert!(tags.iter().any(|t| t.name == "Make" && t.value == "Apple"));
+
+        // cleanup
+        fs::remove_dir_all(out_dir).unwrap();
+        fs::remove_file(Journal::path_for_dir(Path::new(out_dir))).unwrap();
+    }
+
+    #[test]
+    fn run_uses_saved_links() {
+        let source = Path::new("./test-assets/run_uses_saved_links");
+        copy_unzipped_takeout(source);
+        let other = source.join("takeout/other");
+        fs::rename(
+            other.join("319580_10102651624550024_127913296_n_101026516.json"),
+            other.join("photo.json"),
+        )
+        .unwrap();
+        let mut links = ManualLinks::default();
+        links.link(
+            PathBuf::from("takeout/other/319580_10102651624550024_127913296_n_101026516.jpg"),
+            PathBuf::from("takeout/other/photo.json"),
+        );
+        links.save(source).unwrap();
+
+        let events = run_events(source, &RunOptions::default(), &RunControl::default());
+
+        // assert
+        let summary = summary_of(&events);
+        assert_eq!(summary.json_without_image, 0);
+        assert_eq!(summary.images_without_json, 2);
+
+        // cleanup
+        fs::remove_dir_all(source).unwrap();
+        fs::remove_file(Journal::path_for_dir(source)).unwrap();
+        fs::remove_file(UndoStore::path_for_dir(source)).unwrap();
+        fs::remove_file(ManualLinks::path_for(source)).unwrap();
+    }
+
+    #[test]
+    fn cancelled_run_stops_before_next_file() {
+        let source = Path::new("./test-assets/takeout-unzipped");
+        let out_dir = "./test-assets/cancelled_run_stops_before_next_file";
+        let control = RunControl::default();
+        control.cancel();
+
+        let options = RunOptions {
+            output: OutputMode::Tree {
+                dir: PathBuf::from(out_dir),
+                link: LinkMode::Copy,
+            },
+            ..Default::default()
+        };
+        let events = run_events(source, &options, &control);
+
+        // assert
+        assert!(!events.iter().any(|e| matches!(e, Event::FileDone { .. })));
+        assert!(matches!(
+            events.as_slice(),
+            [.., Event::Cancelled, Event::Done]
+        ));
+
+        // cleanup
+        fs::remove_file(Journal::path_for_dir(Path::new(out_dir))).unwrap();
+        let _ = fs::remove_dir_all(out_dir);
+    }
+
+    #[test]
+    fn cancelled_archive_run_leaves_no_archive() {
+        let source = Path::new("./test-assets/takeout-unzipped");
+        let out_dir = "./test-assets/cancelled_archive_is_deleted";
+        let archive = PathBuf::from(out_dir).join("library.zip");
+        let control = RunControl::default();
+        control.cancel();
+
+        let options = RunOptions {
+            output: OutputMode::Archive(output::ArchiveOptions::new(archive.clone())),
+            ..Default::default()
+        };
+        let events = run_events(source, &options, &control);
+
+        // assert
+        assert!(matches!(
+            events.as_slice(),
+            [.., Event::Cancelled, Event::Done]
+        ));
+        assert!(!archive.exists());
+
+        // cleanup
+        let _ = fs::remove_dir_all(out_dir);
+    }
+
+    #[test]
+    fn restarted_run_skips_completed_files() {
+        let source = Path::new("./test-assets/takeout-unzipped");
+        let out_dir = "./test-assets/restarted_run_skips_completed_files";
+        let options = RunOptions {
+            output: OutputMode::Tree {
+                dir: PathBuf::from(out_dir),
+                link: LinkMode::Copy,
+            },
+            ..Default::default()
+        };
+        let run = || {
+            run_events(source, &options, &RunControl::default())
+                .into_iter()
+                .filter_map(Event::into_error)
+                .map(|e| e.path().to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        run();
+        // a file that was modified since the last run is processed again
+        let modified = PathBuf::from(out_dir).join("takeout/TEST_JPG.jpg.json");
+        fs::write(&modified, "{}").unwrap();
+        let untouched = PathBuf::from(out_dir).join("takeout/edited/TEST_JPG-edited.jpg");
+        let untouched_state = journal::FileState::of(&untouched).unwrap();
+        let failed = run();
+
+        // assert
+        assert_ne!(fs::read_to_string(&modified).unwrap(), "{}");
+        assert_eq!(journal::FileState::of(&untouched).unwrap(), untouched_state);
+        // files that failed are retried
+        assert_eq!(failed.len(), 1);
+
+        // cleanup
+        fs::remove_dir_all(out_dir).unwrap();
+        fs::remove_file(Journal::path_for_dir(Path::new(out_dir))).unwrap();
+    }
+
+    #[test]
+    fn processed_files_are_only_processed_again_if_json_or_options_changed() {
+        let source = PathBuf::from("./test-assets/processed_files_are_only_processed_again");
+        copy_unzipped_takeout(&source);
+        let img = source.join("takeout/TEST_JPG.jpg");
+        let json = source.join("takeout/TEST_JPG.jpg.json");
+        let run = |force: bool, date_source: DateSource| {
+            let options = RunOptions {
+                force,
+                metadata: MetadataOptions {
+                    date_source,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            run_events(&source, &options, &RunControl::default());
+            journal::FileState::of(&img).unwrap()
+        };
+
+        let first = run(false, DateSource::Upload);
+        let repeated = run(false, DateSource::Upload);
+        let contents = fs::read_to_string(&json).unwrap();
+        fs::write(&json, contents.replace("1562782285", "1262304000")).unwrap();
+        let json_changed = run(false, DateSource::Upload);
+        let forced = run(true, DateSource::Upload);
+        let options_changed = run(false, DateSource::PhotoTaken);
+        let repeated_options = run(false, DateSource::PhotoTaken);
+
+        // assert
+        assert_eq!(first, repeated);
+        assert_ne!(repeated, json_changed);
+        assert_ne!(json_changed, forced);
+        assert_ne!(forced, options_changed);
+        assert_eq!(options_changed, repeated_options);
+
+        // cleanup
+        fs::remove_dir_all(&source).unwrap();
+        fs::remove_file(Journal::path_for_dir(&source)).unwrap();
+        fs::remove_file(UndoStore::path_for_dir(&source)).unwrap();
+    }
+
+    #[test]
+    fn metadata_hash_only_changes_with_json_or_options() {
+        let options = MetadataOptions::default();
+        let hash = metadata_hash(b"{}", &options);
+
+        // journals of earlier runs must stay valid
+        assert_eq!(
+            hash,
+            "48c8c5e460ae93d8abf255119e5147a891fcaf653a324a485f9599c2e2f7bb7e"
+        );
+        assert_ne!(metadata_hash(b"{ }", &options), hash);
+        let offset = MetadataOptions {
+            time_zone: TimeZoneMode::Offset(60),
+            ..Default::default()
+        };
+        assert_ne!(metadata_hash(b"{}", &offset), hash);
+    }
+
+    #[test]
+    fn run_is_restricted_to_only() {
+        let source = PathBuf::from("./test-assets/run_is_restricted_to_only");
+        copy_unzipped_takeout(&source);
+        let img = source.join("takeout/TEST_JPG.jpg");
+        let json = source.join("takeout/TEST_JPG.jpg.json");
+
+        let options = RunOptions {
+            only: Some(HashSet::from([img.clone()])),
+            ..Default::default()
+        };
+        let events = run_events(&source, &options, &RunControl::default());
+
+        // assert
+        let done: Vec<_> = events
+            .iter()
+            .filter_map(|e| match e {
+                Event::FileDone { path, .. } => Some(path),
+                _ => None,
+            })
+            .collect();
+        assert!(!done.is_empty());
+        assert!(done.iter().all(|p| **p == img || **p == json));
+
+        // cleanup
+        fs::remove_dir_all(&source).unwrap();
+        fs::remove_file(Journal::path_for_dir(&source)).unwrap();
+        fs::remove_file(UndoStore::path_for_dir(&source)).unwrap();
+    }
+
+    #[test]
+    fn invalid_json_is_reported_and_run_completes() {
+        let source = PathBuf::from("./test-assets/invalid_json_is_reported_and_run_completes");
+        let out_dir = "./test-assets/invalid_json_is_reported_and_run_completes_out";
+        copy_unzipped_takeout(&source);
+        let json = source.join("takeout/TEST_JPG.jpg.json");
+        fs::write(&json, "{ not json").unwrap();
+
+        let options = RunOptions {
+            output: OutputMode::Tree {
+                dir: PathBuf::from(out_dir),
+                link: LinkMode::Copy,
+            },
+            ..Default::default()
+        };
+        let events = run_events(&source, &options, &RunControl::default());
+
+        // assert
+        let errors: Vec<_> = events.into_iter().filter_map(Event::into_error).collect();
+        let json_error = errors.iter().find(|e| e.path() == json).unwrap();
+        assert_eq!(json_error.phase(), error::Phase::Json);
+        let written = utils::recursively_collect_filenames(Path::new(out_dir)).unwrap();
+        assert_eq!(written.len(), 8);
+        assert_eq!(
+            fs::read(Path::new(out_dir).join("takeout/TEST_JPG.jpg")).unwrap(),
+            fs::read(source.join("takeout/TEST_JPG.jpg")).unwrap()
+        );
+
+        // cleanup
+        fs::remove_dir_all(&source).unwrap();
+        fs::remove_dir_all(out_dir).unwrap();
+        fs::remove_file(Journal::path_for_dir(Path::new(out_dir))).unwrap();
+    }
+
+    #[test]
+    fn lossy_webp_is_reported_and_run_completes() {
+        // a 1x1 lossy webp, little_exif panics on these
+        const VP8_WEBP: [u8; 42] = [
+            0x52, 0x49, 0x46, 0x46, 0x22, 0x00, 0x00, 0x00, 0x57, 0x45, 0x42, 0x50, 0x56, 0x50,
+            0x38, 0x20, 0x16, 0x00, 0x00, 0x00, 0x30, 0x01, 0x00, 0x9d, 0x01, 0x2a, 0x01, 0x00,
+            0x01, 0x00, 0x0e, 0xc0, 0xfe, 0x25, 0xa4, 0x00, 0x03, 0x70, 0x00, 0x00, 0x00, 0x00,
+        ];
+        let source = PathBuf::from("./test-assets/lossy_webp_is_reported_and_run_completes");
+        let out_dir = "./test-assets/lossy_webp_is_reported_and_run_completes_out";
+        copy_unzipped_takeout(&source);
+        let webp = source.join("takeout/lossy.webp");
+        fs::write(&webp, VP8_WEBP).unwrap();
+        fs::copy(
+            source.join("takeout/TEST_JPG.jpg.json"),
+            source.join("takeout/lossy.webp.json"),
+        )
+        .unwrap();
+
+        let options = RunOptions {
+            output: OutputMode::Tree {
+                dir: PathBuf::from(out_dir),
+                link: LinkMode::Copy,
+            },
+            ..Default::default()
+        };
+        let events = run_events(&source, &options, &RunControl::default());
+
+        // assert
+        assert!(matches!(events.last(), Some(Event::Done)));
+        let errors: Vec<_> = events.into_iter().filter_map(Event::into_error).collect();
+        let webp_error = errors.iter().find(|e| e.path() == webp).unwrap();
+        assert_eq!(webp_error.phase(), error::Phase::Metadata);
+        let jpg = Path::new(out_dir).join("takeout/TEST_JPG.jpg");
+        assert_ne!(
+            fs::read(jpg).unwrap(),
+            fs::read(source.join("takeout/TEST_JPG.jpg")).unwrap()
+        );
+
+        // cleanup
+        fs::remove_dir_all(&source).unwrap();
+        fs::remove_dir_all(out_dir).unwrap();
+        fs::remove_file(Journal::path_for_dir(Path::new(out_dir))).unwrap();
+    }
+
+    #[test]
+    fn undo_restores_files_modified_in_place() {
+        let source = PathBuf::from("./test-assets/undo_restores_files_modified_in_place");
+        copy_unzipped_takeout(&source);
+        let before: Vec<_> = utils::recursively_collect_filenames(&source)
+            .unwrap()
+            .into_iter()
+            .map(|p| (fs::read(&p).unwrap(), p))
+            .collect();
+
+        run_events(&source, &RunOptions::default(), &RunControl::default());
+        let img = source.join("takeout/TEST_JPG.jpg");
+        let modified = before
+            .iter()
+            .any(|(c, p)| p == &img && &fs::read(p).unwrap() != c);
+        let failed = undo(&source).unwrap();
+
+        // assert
+        assert!(modified);
+        assert!(failed.is_empty());
+        for (contents, p) in before {
+            assert_eq!(fs::read(&p).unwrap(), contents);
+        }
+        assert!(!UndoStore::path_for_dir(&source).exists());
+
+        // cleanup
+        fs::remove_dir_all(&source).unwrap();
+        fs::remove_file(Journal::path_for_dir(&source)).unwrap();
+    }
+
+    #[test]
+    fn directory_source_can_be_packaged_into_archive() {
+        let source = Path::new("./test-assets/takeout-unzipped");
+        let out_dir = "./test-assets/directory_source_can_be_packaged_into_archive";
+        let archive = PathBuf::from(out_dir).join("library.tgz");
+
+        let options = RunOptions {
+            output: OutputMode::Archive(output::ArchiveOptions::new(archive.clone())),
+            ..Default::default()
+        };
+        run_events(source, &options, &RunControl::default());
+
+        // assert
+        let extracted = utils::working_dir(&archive);
+        utils::extract_to(
+            &archive,
+            &extracted,
+            &mut Journal::in_memory(),
+            &RunControl::default(),
+        );
+        let written = utils::recursively_collect_filenames(&extracted).unwrap();
+        assert_eq!(written.len(), 9);
+        assert!(extracted.join("manifest.json").exists());
+
+        // cleanup
+        fs::remove_dir_all(out_dir).unwrap();
+    }
+}
@@ -0,0 +1,601 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use super::journal::Journal;
+
+/// Where processed media is written to.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum OutputMode {
+    /// Rewrite the extracted files directly. This leaves no pristine copy of the extracted media behind.
+    #[default]
+    InPlace,
+    /// Never touch the source. Processed media is written into a mirror of the source tree rooted at `dir`, and files
+    /// that are not modified are placed there according to `link`. `link` only applies to folder sources, files coming
+    /// out of an archive have nothing to link to and are always written as new files.
+    Tree { dir: PathBuf, link: LinkMode },
+    /// Never touch the source. Processed media is packaged into a new archive.
+    Archive(ArchiveOptions),
+}
+
+/// How files that don't need any changes are placed into the output tree.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LinkMode {
+    /// Plain copy. Works everywhere, but uses the most disk space.
+    #[default]
+    Copy,
+    /// Hard link to the source file. Source and output must be on the same file system.
+    Hardlink,
+    /// Copy-on-write clone of the source file. Falls back to a plain copy when the file system does not support it.
+    Reflink,
+}
+impl LinkMode {
+    pub const ALL: [LinkMode; 3] = [LinkMode::Copy, LinkMode::Hardlink, LinkMode::Reflink];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            LinkMode::Copy => "Copy",
+            LinkMode::Hardlink => "Hard link",
+            LinkMode::Reflink => "Reflink (copy-on-write)",
+        }
+    }
+
+    /// Place `src` at `dest`, replacing whatever is already at `dest`.
+    fn place(&self, src: &Path, dest: &Path) -> io::Result<()> {
+        if dest.exists() {
+            fs::remove_file(dest)?;
+        }
+        match self {
+            LinkMode::Copy => fs::copy(src, dest).map(|_| ()),
+            LinkMode::Hardlink => fs::hard_link(src, dest),
+            LinkMode::Reflink => reflink_copy::reflink_or_copy(src, dest).map(|_| ()),
+        }
+    }
+}
+
+/// Maps files from a source tree onto the output tree. When both roots are the same directory, every operation works
+/// in place.
+#[derive(Debug)]
+pub struct OutputTree {
+    source_root: PathBuf,
+    dest_root: PathBuf,
+    link: LinkMode,
+}
+impl OutputTree {
+    pub fn new(source_root: PathBuf, dest_root: PathBuf, link: LinkMode) -> Self {
+        Self {
+            source_root,
+            dest_root,
+            link,
+        }
+    }
+
+    pub fn in_place(root: PathBuf) -> Self {
+        Self::new(root.clone(), root, LinkMode::default())
+    }
+
+    pub fn source_root(&self) -> &Path {
+        &self.source_root
+    }
+
+    fn is_in_place(&self) -> bool {
+        self.source_root == self.dest_root
+    }
+
+    /// Path that `src` maps to in the output tree. Paths outside of the source root are placed directly into the
+    /// output root.
+    pub fn destination(&self, src: &Path) -> PathBuf {
+        match src.strip_prefix(&self.source_root) {
+            Ok(relative) => self.dest_root.join(relative),
+            Err(_) => self
+                .dest_root
+                .join(src.file_name().unwrap_or(src.as_os_str())),
+        }
+    }
+
+    /// Prepare the destination of a file that is about to be modified and return its path. The source file itself is
+    /// never modified, unless working in place.
+    pub fn stage_for_writing(&self, src: &Path) -> io::Result<PathBuf> {
+        let dest = self.destination(src);
+        if self.is_in_place() {
+            return Ok(dest);
+        }
+        create_parent_dir(&dest)?;
+        // always a real copy, a link would let the upcoming write modify the source
+        if dest.exists() {
+            fs::remove_file(&dest)?;
+        }
+        fs::copy(src, &dest)?;
+        Ok(dest)
+    }
+
+    /// Place a file that needs no changes into the output tree.
+    pub fn place_untouched(&self, src: &Path) -> io::Result<PathBuf> {
+        let dest = self.destination(src);
+        if self.is_in_place() {
+            return Ok(dest);
+        }
+        create_parent_dir(&dest)?;
+        self.link.place(src, &dest)?;
+        Ok(dest)
+    }
+}
+
+/// Destination for files that are processed in memory.
+pub trait MediaSink {
+    /// Write `contents` to `path`, which is relative to the root of the Takeout. `json_hash` is the hash of the json
+    /// file whose metadata was applied and of the options it was applied with.
+    fn write(&mut self, path: &Path, contents: &[u8], json_hash: Option<&str>) -> io::Result<()>;
+
+    /// Whether `path` was written by a previous run with metadata from the same json file and the same options and can
+    /// be skipped.
+    fn is_written(&self, _path: &Path, _json_hash: Option<&str>) -> bool {
+        false
+    }
+
+    /// Called once after the last file was written.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes files into a directory, keeping their relative structure.
+#[derive(Debug)]
+pub struct DirSink {
+    root: PathBuf,
+    journal: Journal,
+}
+impl DirSink {
+    pub fn new(root: PathBuf, journal: Journal) -> Self {
+        Self { root, journal }
+    }
+}
+impl MediaSink for DirSink {
+    fn write(&mut self, path: &Path, contents: &[u8], json_hash: Option<&str>) -> io::Result<()> {
+        let dest = self.root.join(path);
+        create_parent_dir(&dest)?;
+        fs::write(&dest, contents)?;
+        self.journal.record_processed(&dest, json_hash)
+    }
+
+    fn is_written(&self, path: &Path, json_hash: Option<&str>) -> bool {
+        self.journal.is_processed(&self.root.join(path), json_hash)
+    }
+}
+
+/// Archive formats the processed library can be packaged into.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    #[default]
+    Zip,
+    Tar,
+    TarGz,
+}
+impl ArchiveFormat {
+    pub const ALL: [ArchiveFormat; 3] =
+        [ArchiveFormat::Zip, ArchiveFormat::Tar, ArchiveFormat::TarGz];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::Tar => "tar",
+            ArchiveFormat::TarGz => "tgz",
+        }
+    }
+
+    /// Guess the format from the extension of `path`, if it is one we know.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_ascii_lowercase();
+        if name.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveFormat::Tar)
+        } else if name.ends_with(".tgz") || name.ends_with(".tar.gz") {
+            Some(ArchiveFormat::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveOptions {
+    /// Path of the archive. When splitting, a part number is added before the extension of every part.
+    pub path: PathBuf,
+    pub format: ArchiveFormat,
+    /// 0 stores files without compression, 1 to 9 trade speed for size. Ignored for plain tar files.
+    pub compression_level: u32,
+    /// Start a new part before the uncompressed contents of the current part exceed this many bytes. Files are never
+    /// split between parts.
+    pub split_size: Option<u64>,
+    /// Also package the Takeout json files
+    pub include_json: bool,
+    /// Add a `manifest.json` listing every packaged file and the part it is in
+    pub include_manifest: bool,
+}
+impl ArchiveOptions {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            format: ArchiveFormat::from_path(&path).unwrap_or_default(),
+            path,
+            compression_level: 6,
+            split_size: None,
+            include_json: true,
+            include_manifest: true,
+        }
+    }
+
+    /// Change the format, replacing the extension of `path` to match.
+    pub fn set_format(&mut self, format: ArchiveFormat) {
+        let (stem, _) = self.split_file_name();
+        self.path = self
+            .path
+            .with_file_name(format!("{stem}.{}", format.extension()));
+        self.format = format;
+    }
+
+    /// Path of the given part, starting at 1.
+    pub fn part_path(&self, part: usize) -> PathBuf {
+        if self.split_size.is_none() {
+            return self.path.clone();
+        }
+        let (stem, ext) = self.split_file_name();
+        self.path.with_file_name(format!("{stem}-{part:03}{ext}"))
+    }
+
+    /// Splits the file name into stem and archive extension (including the dot).
+    fn split_file_name(&self) -> (String, String) {
+        let name = self.path.file_name().unwrap_or_default().to_string_lossy();
+        let lower = name.to_ascii_lowercase();
+        let ext_len = [".tar.gz", ".tgz", ".tar", ".zip"]
+            .into_iter()
+            .find(|ext| lower.ends_with(ext))
+            .map_or(0, str::len);
+        let (stem, ext) = name.split_at(name.len() - ext_len);
+        (stem.to_string(), ext.to_string())
+    }
+}
+
+enum ArchiveWriter {
+    Zip(Box<zip::ZipWriter<fs::File>>),
+    Tar(tar::Builder<fs::File>),
+    TarGz(tar::Builder<flate2::write::GzEncoder<fs::File>>),
+}
+impl ArchiveWriter {
+    fn create(path: &Path, options: &ArchiveOptions) -> io::Result<Self> {
+        create_parent_dir(path)?;
+        let file = fs::File::create(path)?;
+        Ok(match options.format {
+            ArchiveFormat::Zip => ArchiveWriter::Zip(Box::new(zip::ZipWriter::new(file))),
+            ArchiveFormat::Tar => ArchiveWriter::Tar(tar::Builder::new(file)),
+            ArchiveFormat::TarGz => {
+                ArchiveWriter::TarGz(tar::Builder::new(flate2::write::GzEncoder::new(
+                    file,
+                    flate2::Compression::new(options.compression_level.min(9)),
+                )))
+            }
+        })
+    }
+
+    fn append(&mut self, path: &Path, contents: &[u8], compression_level: u32) -> io::Result<()> {
+        // archives always use forward slashes
+        let name = path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        match self {
+            ArchiveWriter::Zip(writer) => {
+                let options = match compression_level {
+                    0 => zip::write::SimpleFileOptions::default()
+                        .compression_method(zip::CompressionMethod::Stored),
+                    level => zip::write::SimpleFileOptions::default()
+                        .compression_method(zip::CompressionMethod::Deflated)
+                        .compression_level(Some(level.min(9) as i64)),
+                };
+                writer.start_file(
+                    name,
+                    options.large_file(contents.len() as u64 >= u32::MAX as u64),
+                )?;
+                writer.write_all(contents)
+            }
+            ArchiveWriter::Tar(builder) => append_tar(builder, &name, contents),
+            ArchiveWriter::TarGz(builder) => append_tar(builder, &name, contents),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            ArchiveWriter::Zip(writer) => writer.finish().map(|_| ())?,
+            ArchiveWriter::Tar(builder) => builder.into_inner().map(|_| ())?,
+            ArchiveWriter::TarGz(builder) => builder.into_inner()?.finish().map(|_| ())?,
+        }
+        Ok(())
+    }
+}
+
+fn append_tar<W: Write>(
+    builder: &mut tar::Builder<W>,
+    name: &str,
+    contents: &[u8],
+) -> io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+    );
+    builder.append_data(&mut header, name, contents)
+}
+
+#[derive(Debug, Serialize)]
+struct ManifestEntry {
+    path: PathBuf,
+    size: u64,
+    part: usize,
+}
+
+/// Packages files into a new archive, optionally split into multiple parts.
+pub struct ArchiveSink {
+    options: ArchiveOptions,
+    writer: Option<ArchiveWriter>,
+    part: usize,
+    part_size: u64,
+    manifest: Vec<ManifestEntry>,
+}
+impl ArchiveSink {
+    pub fn new(options: ArchiveOptions) -> io::Result<Self> {
+        let writer = ArchiveWriter::create(&options.part_path(1), &options)?;
+        Ok(Self {
+            options,
+            writer: Some(writer),
+            part: 1,
+            part_size: 0,
+            manifest: Vec::new(),
+        })
+    }
+
+    fn writer(&mut self) -> &mut ArchiveWriter {
+        self.writer.as_mut().expect("Archive was already finished")
+    }
+
+    /// Delete every part that was written so far, instead of finishing the archive. Archives can't be continued, so an
+    /// unfinished one would only be a truncated copy of the Takeout.
+    pub fn discard(mut self) -> io::Result<()> {
+        drop(self.writer.take());
+        for part in 1..=self.part {
+            match fs::remove_file(self.options.part_path(part)) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+impl MediaSink for ArchiveSink {
+    fn write(&mut self, path: &Path, contents: &[u8], _json_hash: Option<&str>) -> io::Result<()> {
+        let is_json = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("json"));
+        if is_json && !self.options.include_json {
+            return Ok(());
+        }
+
+        let size = contents.len() as u64;
+        if let Some(split_size) = self.options.split_size
+            && self.part_size > 0
+            && self.part_size + size > split_size
+        {
+            self.writer.take().map(ArchiveWriter::finish).transpose()?;
+            self.part += 1;
+            self.part_size = 0;
+            self.writer = Some(ArchiveWriter::create(
+                &self.options.part_path(self.part),
+                &self.options,
+            )?);
+        }
+
+        let level = self.options.compression_level;
+        self.writer().append(path, contents, level)?;
+        self.part_size += size;
+        self.manifest.push(ManifestEntry {
+            path: path.to_owned(),
+            size,
+            part: self.part,
+        });
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.options.include_manifest {
+            let manifest = serde_json::to_vec_pretty(&self.manifest)?;
+            let level = self.options.compression_level;
+            self.writer()
+                .append(Path::new("manifest.json"), &manifest, level)?;
+        }
+        self.writer.take().map(ArchiveWriter::finish).transpose()?;
+        Ok(())
+    }
+}
+
+fn create_parent_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(p) if !p.exists() => fs::create_dir_all(p),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "./test-assets/takeout-unzipped";
+
+    #[test]
+    fn destination_keeps_relative_structure() {
+        let tree = OutputTree::new(PathBuf::from(SOURCE), PathBuf::from("/out"), LinkMode::Copy);
+        let dest = tree.destination(&PathBuf::from(SOURCE).join("takeout/edited/a.jpg"));
+        assert_eq!(dest, PathBuf::from("/out/takeout/edited/a.jpg"));
+    }
+
+    #[test]
+    fn in_place_does_not_touch_files() {
+        let tree = OutputTree::in_place(PathBuf::from(SOURCE));
+        let src = PathBuf::from(SOURCE).join("takeout/TEST_JPG.jpg");
+        assert_eq!(tree.stage_for_writing(&src).unwrap(), src);
+        assert_eq!(tree.place_untouched(&src).unwrap(), src);
+    }
+
+    #[test]
+    fn stage_for_writing_copies_source() {
+        let out = "./test-assets/stage_for_writing_copies_source";
+        let tree = OutputTree::new(
+            PathBuf::from(SOURCE),
+            PathBuf::from(out),
+            LinkMode::Hardlink,
+        );
+        let src = PathBuf::from(SOURCE).join("takeout/edited/TEST_JPG-edited.jpg");
+
+        let dest = tree.stage_for_writing(&src).unwrap();
+        fs::write(&dest, b"modified").unwrap();
+
+        assert_ne!(fs::read(&src).unwrap(), b"modified");
+
+        // cleanup
+        fs::remove_dir_all(out).unwrap();
+    }
+
+    #[test]
+    fn part_path_adds_part_number() {
+        let mut options = ArchiveOptions::new(PathBuf::from("out/library.tar.gz"));
+        assert_eq!(options.format, ArchiveFormat::TarGz);
+        assert_eq!(options.part_path(1), PathBuf::from("out/library.tar.gz"));
+
+        options.split_size = Some(1);
+        assert_eq!(
+            options.part_path(2),
+            PathBuf::from("out/library-002.tar.gz")
+        );
+
+        options.set_format(ArchiveFormat::Zip);
+        assert_eq!(options.part_path(1), PathBuf::from("out/library-001.zip"));
+    }
+
+    #[test]
+    fn archive_sink_splits_into_parts() {
+        let out = "./test-assets/archive_sink_splits_into_parts";
+        let mut options = ArchiveOptions::new(PathBuf::from(out).join("library.zip"));
+        options.split_size = Some(15);
+        let mut sink = ArchiveSink::new(options.clone()).unwrap();
+
+        for name in ["a/1.jpg", "a/2.jpg", "b/3.jpg"] {
+            sink.write(Path::new(name), &[0; 10], None).unwrap();
+        }
+        sink.finish().unwrap();
+
+        // assert
+        assert!(options.part_path(3).exists());
+        assert!(!options.part_path(4).exists());
+        let extracted = PathBuf::from(out).join("extracted");
+        let mut journal = crate::services::journal::Journal::in_memory();
+        for part in 1..=3 {
+            let (_, errors) = crate::services::utils::extract_to(
+                &options.part_path(part),
+                &extracted,
+                &mut journal,
+                &crate::services::RunControl::default(),
+            );
+            assert!(errors.is_empty());
+        }
+        let files = crate::services::utils::recursively_collect_filenames(&extracted).unwrap();
+        assert_eq!(files.len(), 4);
+        assert!(extracted.join("manifest.json").exists());
+
+        // cleanup
+        fs::remove_dir_all(out).unwrap();
+    }
+
+    #[test]
+    fn discarded_archive_is_deleted() {
+        let out = "./test-assets/discarded_archive_is_deleted";
+        let mut options = ArchiveOptions::new(PathBuf::from(out).join("library.zip"));
+        options.split_size = Some(15);
+        let mut sink = ArchiveSink::new(options.clone()).unwrap();
+
+        for name in ["a/1.jpg", "a/2.jpg"] {
+            sink.write(Path::new(name), &[0; 10], None).unwrap();
+        }
+        sink.discard().unwrap();
+
+        // assert
+        assert_eq!(fs::read_dir(out).unwrap().count(), 0);
+
+        // cleanup
+        fs::remove_dir_all(out).unwrap();
+    }
+
+    #[test]
+    fn archive_sink_can_skip_json() {
+        let out = "./test-assets/archive_sink_can_skip_json";
+        for format in ArchiveFormat::ALL {
+            let mut options = ArchiveOptions::new(
+                PathBuf::from(out).join(format!("library.{}", format.extension())),
+            );
+            options.include_json = false;
+            options.include_manifest = false;
+            let mut sink = ArchiveSink::new(options.clone()).unwrap();
+
+            sink.write(Path::new("a/1.jpg"), b"jpg", None).unwrap();
+            sink.write(Path::new("a/1.jpg.json"), b"{}", None).unwrap();
+            sink.finish().unwrap();
+
+            // assert
+            let archive = &options.path;
+            let mut names = Vec::new();
+            match format {
+                ArchiveFormat::Zip => {
+                    let zip = zip::ZipArchive::new(fs::File::open(archive).unwrap()).unwrap();
+                    names.extend(zip.file_names().map(str::to_string));
+                }
+                ArchiveFormat::Tar | ArchiveFormat::TarGz => {
+                    let file = fs::File::open(archive).unwrap();
+                    let reader: Box<dyn io::Read> = match format {
+                        ArchiveFormat::TarGz => Box::new(flate2::read::GzDecoder::new(file)),
+                        _ => Box::new(file),
+                    };
+                    for entry in tar::Archive::new(reader).entries().unwrap() {
+                        names.push(entry.unwrap().path().unwrap().to_string_lossy().to_string());
+                    }
+                }
+            }
+            assert_eq!(names, vec!["a/1.jpg".to_string()]);
+        }
+
+        // cleanup
+        fs::remove_dir_all(out).unwrap();
+    }
+
+    #[test]
+    fn place_untouched_with_every_link_mode() {
+        for (i, link) in LinkMode::ALL.into_iter().enumerate() {
+            let out = format!("./test-assets/place_untouched_with_every_link_mode_{i}");
+            let tree = OutputTree::new(PathBuf::from(SOURCE), PathBuf::from(&out), link);
+            let src = PathBuf::from(SOURCE).join("takeout/TEST_JPG.jpg.json");
+
+            let dest = tree.place_untouched(&src).unwrap();
+            assert_eq!(fs::read(&src).unwrap(), fs::read(&dest).unwrap());
+
+            // placing twice replaces the previous file
+            tree.place_untouched(&src).unwrap();
+
+            // cleanup
+            fs::remove_dir_all(out).unwrap();
+        }
+    }
+}
@@ -1,13 +1,15 @@
 use std::{
     collections::{HashMap, HashSet},
-    fs,
-    io::Read,
+    ffi::OsString,
+    io,
     path::{Path, PathBuf},
 };
 
+use super::exif_data::{JsonParseError, TakeoutExif};
+
 /// A struct for holding the paths of json files and their corresponding images. It is possible for a json file to be
 /// linked to up to 2 images, despite the implication of the word "pair".
-#[derive(Debug)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct Pair {
     /// Json file with metadata for corresponding images
     pub json: Option<PathBuf>,
@@ -26,29 +28,51 @@ impl Pair {
         }
     }
 
-    /// Read json file, if present. Returns `None` if `self.json` is `None`. Returns error if there was an error
-    /// reading the json file.
-    pub fn read_json(&self) -> Option<Result<String, PairError>> {
-        if let Some(path) = self.json.as_ref() {
-            let mut file = fs::File::open(path).unwrap();
-            let mut contents = Vec::new();
-            if let Err(e) = file.read_to_end(&mut contents) {
-                return Some(Err(PairError::IoError(e)));
-            }
-            match String::from_utf8(contents) {
-                Ok(c) => Some(Ok(c)),
-                Err(e) => Some(Err(PairError::Utf8ParsingError(e))),
-            }
-        } else {
-            None
-        }
+    /// The files of the pair that are present: image, edited image and json file.
+    pub fn files(&self) -> impl Iterator<Item = &PathBuf> {
+        [&self.img, &self.img_edited, &self.json]
+            .into_iter()
+            .flatten()
     }
 }
 
+/// Parse the contents of a json file as they were read.
+pub fn parse_json(contents: io::Result<Vec<u8>>) -> Result<TakeoutExif, PairError> {
+    let contents = String::from_utf8(contents.map_err(PairError::IoError)?)
+        .map_err(PairError::Utf8ParsingError)?;
+    TakeoutExif::from_json(&contents).map_err(PairError::ParseError)
+}
+
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum PairError {
     IoError(std::io::Error),
     Utf8ParsingError(std::string::FromUtf8Error),
+    ParseError(JsonParseError),
+}
+impl std::fmt::Display for PairError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PairError::IoError(e) => write!(f, "Failed to read json file: {}", e),
+            PairError::Utf8ParsingError(e) => write!(f, "Json file is not valid UTF-8: {}", e),
+            PairError::ParseError(e) => write!(f, "{}", e),
+        }
+    }
+}
+impl std::error::Error for PairError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PairError::IoError(e) => Some(e),
+            PairError::Utf8ParsingError(e) => Some(e),
+            PairError::ParseError(e) => Some(e),
+        }
+    }
+}
+
+/// Whether `path` is the version of an image that was edited in Google Photos.
+pub fn is_edited(path: &Path) -> bool {
+    path.file_stem()
+        .is_some_and(|s| s.to_string_lossy().ends_with("-edited"))
 }
 
 pub enum PairComponent {
@@ -57,6 +81,8 @@ pub enum PairComponent {
     ImgEdited,
 }
 
+/// Group files by the image they belong to. Paths that aren't valid UTF-8 are keyed by their lossy conversion, so they
+/// are still paired.
 pub fn create_pairs(set: HashSet<PathBuf>) -> HashMap<String, Pair> {
     let mut pairs = HashMap::new();
 
@@ -64,29 +90,19 @@ pub fn create_pairs(set: HashSet<PathBuf>) -> HashMap<String, Pair> {
         if p.is_dir() {
             continue;
         }
-        let stem = p.file_stem().unwrap().to_str().unwrap();
-        let (key, component) = if stem.len() >= 7 && &stem[stem.len() - 7..] == "-edited" {
-            let edited_idx = p.to_str().unwrap().find("-edited").unwrap();
-            (
-                p.to_str().unwrap()[0..edited_idx].to_string(),
-                PairComponent::ImgEdited,
-            )
-        } else if p.extension().unwrap().to_str().unwrap() == "json" {
-            let img_name = Path::new(p.file_stem().unwrap());
-            let naked_key = img_name.file_stem().unwrap();
-            let dir = p.parent().unwrap_or(Path::new(""));
-            (
-                dir.join(naked_key).to_str().unwrap().to_string(),
-                PairComponent::Json,
-            )
-        } else {
-            let naked_key = p.file_stem().unwrap();
-            let dir = p.parent().unwrap_or(Path::new(""));
-            (
-                dir.join(naked_key).to_str().unwrap().to_string(),
-                PairComponent::Img,
-            )
-        };
+        let stem = p.file_stem().unwrap_or_default();
+        let dir = p.parent().unwrap_or(Path::new(""));
+        let (naked_key, component) =
+            if let Some(naked_key) = stem.to_string_lossy().strip_suffix("-edited") {
+                (OsString::from(naked_key), PairComponent::ImgEdited)
+            } else if p.extension().is_some_and(|e| e == "json") {
+                let img_name = Path::new(stem);
+                let naked_key = img_name.file_stem().unwrap_or(stem);
+                (naked_key.to_owned(), PairComponent::Json)
+            } else {
+                (stem.to_owned(), PairComponent::Img)
+            };
+        let key = dir.join(naked_key).to_string_lossy().into_owned();
 
         let pair = pairs.entry(key).or_insert(Pair::new());
         match component {
@@ -292,4 +308,36 @@ mod tests {
         assert_eq!(pair1.img.as_ref().unwrap(), &img1);
         assert_eq!(pair2.img.as_ref().unwrap(), &img2);
     }
+
+    #[test]
+    fn file_without_extension() {
+        let file = PathBuf::from("dir/README");
+        let pairs = create_pairs(HashSet::from([file.clone()]));
+
+        let pair = pairs.get("dir/README").unwrap();
+        assert_eq!(pair.img.as_ref().unwrap(), &file);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn name_that_is_not_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let stem = std::ffi::OsStr::from_bytes(b"my_img\xff");
+        let img = Path::new("dir").join(stem).with_extension("jpg");
+        let json = Path::new("dir").join(stem).with_extension("jpg.json");
+        let pairs = create_pairs(HashSet::from([img.clone(), json.clone()]));
+
+        assert_eq!(pairs.len(), 1);
+        let pair = pairs.values().next().unwrap();
+        assert_eq!(pair.img.as_ref().unwrap(), &img);
+        assert_eq!(pair.json.as_ref().unwrap(), &json);
+    }
+
+    #[test]
+    fn parse_json_fails_for_missing_file() {
+        let contents = std::fs::read("./test-assets/does_not_exist.json");
+
+        assert!(matches!(parse_json(contents), Err(PairError::IoError(_))));
+    }
 }
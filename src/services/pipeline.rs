@@ -0,0 +1,144 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, mpsc},
+    thread,
+};
+
+/// How much work is done at the same time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Parallelism {
+    /// Number of worker threads
+    pub workers: usize,
+    /// Maximum number of jobs that are queued, being worked on or waiting for earlier jobs to finish. Caps the memory
+    /// used by jobs that hold file contents.
+    pub queue_size: usize,
+}
+impl Parallelism {
+    pub fn new(workers: usize) -> Self {
+        let workers = workers.max(1);
+        Self {
+            workers,
+            queue_size: workers * 2,
+        }
+    }
+}
+impl Default for Parallelism {
+    /// One worker per CPU core
+    fn default() -> Self {
+        Self::new(thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+}
+
+/// Runs `work` for every job on a pool of worker threads. Jobs are pulled from `jobs` on a separate thread, and `done`
+/// is called on the current thread with the results in the same order as the jobs, no matter which worker finishes
+/// first.
+pub fn run_ordered<J, R, I, W, D>(jobs: I, parallelism: Parallelism, work: W, mut done: D)
+where
+    J: Send,
+    R: Send,
+    I: Iterator<Item = J> + Send,
+    W: Fn(J) -> R + Sync,
+    D: FnMut(R),
+{
+    let workers = parallelism.workers.max(1);
+    let in_flight = parallelism.queue_size.max(workers);
+
+    // the producer needs a permit for every job, and a permit is only returned once the job's result was handed to
+    // `done`, so there are never more than `in_flight` jobs in memory
+    let (permit_tx, permit_rx) = mpsc::sync_channel(in_flight);
+    for _ in 0..in_flight {
+        permit_tx.send(()).unwrap();
+    }
+    let (job_tx, job_rx) = mpsc::channel();
+    let job_rx = Mutex::new(job_rx);
+    let (result_tx, result_rx) = mpsc::channel();
+
+    thread::scope(|s| {
+        s.spawn(move || {
+            for job in jobs.enumerate() {
+                if permit_rx.recv().is_err() || job_tx.send(job).is_err() {
+                    break;
+                }
+            }
+        });
+
+        for _ in 0..workers {
+            let (job_rx, work, result_tx) = (&job_rx, &work, result_tx.clone());
+            s.spawn(move || {
+                loop {
+                    // the lock is released before working on the job
+                    let job = job_rx.lock().unwrap().recv();
+                    let Ok((i, job)) = job else {
+                        break;
+                    };
+                    if result_tx.send((i, work(job))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(result_tx);
+
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        for (i, result) in result_rx {
+            pending.insert(i, result);
+            while let Some(result) = pending.remove(&next) {
+                done(result);
+                next += 1;
+                // the producer is gone once all jobs were sent
+                let _ = permit_tx.send(());
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    #[test]
+    fn results_keep_job_order() {
+        let mut results = Vec::new();
+        run_ordered(
+            0..50u64,
+            Parallelism::new(4),
+            |i| {
+                // later jobs finish first
+                thread::sleep(Duration::from_millis(50 - i));
+                i
+            },
+            |i| results.push(i),
+        );
+
+        assert_eq!(results, (0..50).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn jobs_in_flight_are_bounded() {
+        let produced = AtomicUsize::new(0);
+        let mut max_ahead = 0;
+        let mut finished = 0;
+        run_ordered(
+            (0..100).inspect(|_| {
+                produced.fetch_add(1, Ordering::SeqCst);
+            }),
+            Parallelism {
+                workers: 2,
+                queue_size: 3,
+            },
+            |i| i,
+            |_| {
+                finished += 1;
+                max_ahead = max_ahead.max(produced.load(Ordering::SeqCst) - finished);
+            },
+        );
+
+        assert_eq!(finished, 100);
+        assert!(max_ahead <= 3);
+    }
+}
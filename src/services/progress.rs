@@ -0,0 +1,165 @@
+use std::{
+    fmt,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use super::{Error, summary::RunSummary};
+
+/// What a run is currently doing, in the order a run goes through them. Stages that aren't needed are skipped, e.g.
+/// extracting for a directory source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub enum Stage {
+    Extracting,
+    /// Listing the files of the Takeout
+    Indexing,
+    Pairing,
+    /// Checking which files previous runs already processed, so the run continues where they stopped
+    Resuming,
+    Writing,
+}
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stage = match self {
+            Stage::Extracting => "Extracting archive",
+            Stage::Indexing => "Indexing files",
+            Stage::Pairing => "Pairing files",
+            Stage::Resuming => "Checking previous runs",
+            Stage::Writing => "Writing files",
+        };
+        f.write_str(stage)
+    }
+}
+
+/// Sent by a run of [`super::extract_and_apply_metadata`] to whoever started it.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Event {
+    Stage(Stage),
+    /// Number and total size of the files that are going to be written, sent before writing starts
+    Total {
+        files: usize,
+        bytes: u64,
+    },
+    /// A file is done, whether it could be written or not. Its errors are sent separately.
+    FileDone {
+        path: PathBuf,
+        bytes: u64,
+    },
+    /// Something couldn't be processed, the run continues with the next file
+    Error(Error),
+    /// What the run did, sent after its last error once all files are written. Not sent if the run couldn't get that
+    /// far.
+    Summary(RunSummary),
+    /// The run was cancelled and stopped early, sent right before [`Event::Done`]
+    Cancelled,
+    /// Always the last event of a run
+    Done,
+}
+impl Event {
+    pub fn into_error(self) -> Option<Error> {
+        match self {
+            Event::Error(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Progress of a run, built from its events.
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    /// `None` until the run reports its first stage
+    pub stage: Option<Stage>,
+    pub files: usize,
+    pub total_files: usize,
+    pub bytes: u64,
+    pub total_bytes: u64,
+    pub errors: usize,
+    pub cancelled: bool,
+    pub done: bool,
+    writing_started: Option<Instant>,
+}
+impl Progress {
+    pub fn update(&mut self, event: &Event) {
+        match event {
+            Event::Stage(stage) => {
+                self.stage = Some(*stage);
+                if *stage == Stage::Writing {
+                    self.writing_started = Some(Instant::now());
+                }
+            }
+            Event::Total { files, bytes } => {
+                self.total_files = *files;
+                self.total_bytes = *bytes;
+            }
+            Event::FileDone { bytes, .. } => {
+                self.files += 1;
+                self.bytes += bytes;
+            }
+            Event::Error(_) => self.errors += 1,
+            Event::Summary(_) => {}
+            Event::Cancelled => self.cancelled = true,
+            Event::Done => self.done = true,
+        }
+    }
+
+    /// Share of the files that are done, by size. `None` while the total isn't known yet.
+    pub fn fraction(&self) -> Option<f32> {
+        if self.stage != Some(Stage::Writing) {
+            return None;
+        }
+        let fraction = if self.total_bytes > 0 {
+            self.bytes as f64 / self.total_bytes as f64
+        } else if self.total_files > 0 {
+            self.files as f64 / self.total_files as f64
+        } else {
+            1.0
+        };
+        Some(fraction.min(1.0) as f32)
+    }
+
+    /// Estimated time until all files are written, extrapolated from how long the ones that are done took.
+    pub fn eta(&self) -> Option<Duration> {
+        let elapsed = self.writing_started?.elapsed();
+        let fraction = self.fraction()? as f64;
+        if fraction <= 0.0 {
+            return None;
+        }
+        Some(elapsed.mul_f64((1.0 - fraction) / fraction))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_is_counted_by_size() {
+        let mut progress = Progress::default();
+        assert!(progress.fraction().is_none());
+
+        for event in [
+            Event::Stage(Stage::Pairing),
+            Event::Total {
+                files: 2,
+                bytes: 400,
+            },
+            Event::Stage(Stage::Writing),
+            Event::FileDone {
+                path: PathBuf::from("a.jpg"),
+                bytes: 100,
+            },
+        ] {
+            progress.update(&event);
+        }
+
+        assert_eq!(progress.files, 1);
+        assert_eq!(progress.fraction(), Some(0.25));
+        assert!(progress.eta().is_some());
+        assert!(!progress.done);
+    }
+}
@@ -0,0 +1,421 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::mpsc,
+};
+
+use super::{
+    Error, Event, RunControl, RunOptions, RunSummary, Stage, exif_data, journal::Journal,
+    links::ManualLinks, metadata_hash, output::MediaSink, pair, pipeline, send, utils,
+};
+
+/// Files of a Takeout that are read into memory one at a time.
+pub trait TakeoutFiles {
+    /// Paths of all files, relative to the root of the Takeout
+    fn names(&self) -> HashSet<PathBuf>;
+    fn read(&mut self, name: &Path) -> io::Result<Vec<u8>>;
+    /// Uncompressed size of a file, 0 if it can't be determined
+    fn size(&self, name: &Path) -> u64;
+    /// Path used to refer to a file when reporting errors
+    fn display_path(&self, name: &Path) -> PathBuf;
+}
+
+/// Zip archives that are read entry by entry. Entries of all parts of a multi-part set are indexed together, so
+/// json files and media can be paired across parts.
+pub struct ZipSet {
+    parts: Vec<PathBuf>,
+    archives: Vec<zip::ZipArchive<fs::File>>,
+    /// Maps the path of every file inside the archives to its part, entry index and uncompressed size
+    index: HashMap<PathBuf, (usize, usize, u64)>,
+}
+impl ZipSet {
+    /// Open all parts and build the index from their central directories. No file contents are read.
+    pub fn open(parts: &[PathBuf]) -> io::Result<Self> {
+        let mut archives = Vec::new();
+        let mut index = HashMap::new();
+        for (part, path) in parts.iter().enumerate() {
+            let mut archive = zip::ZipArchive::new(fs::File::open(path)?)?;
+            for i in 0..archive.len() {
+                let file = archive.by_index_raw(i)?;
+                if file.is_dir() {
+                    // we don't care about empty dirs
+                    continue;
+                }
+                if let Some(name) = file.enclosed_name() {
+                    index.insert(name, (part, i, file.size()));
+                }
+            }
+            archives.push(archive);
+        }
+        Ok(Self {
+            parts: parts.to_owned(),
+            archives,
+            index,
+        })
+    }
+}
+impl TakeoutFiles for ZipSet {
+    fn names(&self) -> HashSet<PathBuf> {
+        self.index.keys().cloned().collect()
+    }
+
+    fn read(&mut self, name: &Path) -> io::Result<Vec<u8>> {
+        let (part, i, _) = self.index[name];
+        let mut file = self.archives[part].by_index(i)?;
+        let mut contents = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut contents)?;
+        Ok(contents)
+    }
+
+    fn size(&self, name: &Path) -> u64 {
+        self.index.get(name).map_or(0, |(_, _, size)| *size)
+    }
+
+    fn display_path(&self, name: &Path) -> PathBuf {
+        let (part, _, _) = self.index[name];
+        self.parts[part].join(name)
+    }
+}
+
+/// An extracted Takeout in a directory. Files are only ever read.
+pub struct DirFiles {
+    root: PathBuf,
+    names: HashSet<PathBuf>,
+}
+impl DirFiles {
+    pub fn open(root: &Path) -> io::Result<Self> {
+        let names = utils::recursively_collect_filenames(root)?
+            .into_iter()
+            .filter_map(|p| p.strip_prefix(root).ok().map(Path::to_path_buf))
+            .collect();
+        Ok(Self {
+            root: root.to_owned(),
+            names,
+        })
+    }
+}
+impl TakeoutFiles for DirFiles {
+    fn names(&self) -> HashSet<PathBuf> {
+        self.names.clone()
+    }
+
+    fn read(&mut self, name: &Path) -> io::Result<Vec<u8>> {
+        fs::read(self.root.join(name))
+    }
+
+    fn size(&self, name: &Path) -> u64 {
+        fs::metadata(self.root.join(name)).map_or(0, |m| m.len())
+    }
+
+    fn display_path(&self, name: &Path) -> PathBuf {
+        self.root.join(name)
+    }
+}
+
+/// Open the files of a Takeout for reading them one at a time. Zip archives are read in place, other archives can't be
+/// read in random order and are extracted first. They are extracted where a run writing in place extracts them,
+/// journaled the same way, so neither extracts a part the other already extracted. Extracting stops once `control` is
+/// cancelled, which fails with [`io::ErrorKind::Interrupted`].
+pub fn open_takeout(
+    source: &Path,
+    control: &RunControl,
+) -> io::Result<Box<dyn TakeoutFiles + Send>> {
+    if source.is_dir() {
+        return Ok(Box::new(DirFiles::open(source)?));
+    }
+    if !is_extracted(source)? {
+        Ok(Box::new(ZipSet::open(&utils::archive_parts(source))?))
+    } else {
+        let dir = utils::working_dir(source);
+        let (dir, errors) = utils::extract_to(source, &dir, &mut Journal::for_dir(&dir)?, control);
+        if control.is_cancelled() {
+            return Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "Extracting the Takeout was cancelled",
+            ));
+        }
+        match errors.into_iter().next() {
+            Some(err) => Err(err.into()),
+            None => Ok(Box::new(DirFiles::open(&dir)?)),
+        }
+    }
+}
+
+/// Whether [`open_takeout`] extracts `source` before reading it, i.e. it's an archive with a part that isn't a zip.
+pub fn is_extracted(source: &Path) -> io::Result<bool> {
+    if source.is_dir() {
+        return Ok(false);
+    }
+    for part in utils::archive_parts(source) {
+        if utils::ArchiveKind::detect(&part)? != Some(utils::ArchiveKind::Zip) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// A file of a pair, read into memory by the producer.
+struct FileJob {
+    name: PathBuf,
+    display_path: PathBuf,
+    contents: io::Result<Vec<u8>>,
+}
+
+struct PairJob {
+    json: Option<FileJob>,
+    json_hash: Option<String>,
+    /// The json file is only read for its metadata if it was written by a previous run
+    write_json: bool,
+    imgs: Vec<FileJob>,
+}
+
+/// What to do with a file once a worker is done with it.
+struct FileResult {
+    name: PathBuf,
+    display_path: PathBuf,
+    /// Size of the file as it was read, for reporting progress
+    bytes: u64,
+    /// `None` if there is nothing to write
+    contents: Option<Vec<u8>>,
+    error: Option<Error>,
+    /// Names of the tags that were written to it
+    tags: Vec<&'static str>,
+}
+
+/// Apply metadata to the media of a Takeout while reading it. Every file is read into memory on its own, modified
+/// there and handed to `sink`, so for archives the raw extraction never touches the disk. Files are read one after the
+/// other, while json parsing and applying metadata happens on the worker pool. Files are paired by their names and
+/// `links`, and are written in a stable order.
+pub fn stream(
+    files: &mut (dyn TakeoutFiles + Send),
+    sink: &mut dyn MediaSink,
+    links: &ManualLinks,
+    options: &RunOptions,
+    control: &RunControl,
+    tx: &mpsc::Sender<Event>,
+) {
+    send(tx, Event::Stage(Stage::Pairing));
+    let mut pairs = pair::create_pairs(files.names());
+    links.apply(&mut pairs, Path::new(""));
+    let mut pairs: Vec<_> = pairs.into_iter().collect();
+    pairs.sort_by(|(a, _), (b, _)| a.cmp(b));
+    // decided up front, the sink is written while the pipeline runs
+    send(tx, Event::Stage(Stage::Resuming));
+    let mut pending = Vec::new();
+    let mut summary = RunSummary::default();
+    for (_, pair) in pairs {
+        let display_paths: Vec<_> = pair.files().map(|p| files.display_path(p)).collect();
+        if !options.includes(display_paths.iter()) {
+            continue;
+        }
+        summary.count_unmatched(&pair);
+        // read only once, for its hash and later for its metadata. Json files are small, so they are kept until their
+        // pair is processed. An unreadable json file is reported then.
+        let json = pair.json.map(|name| read_file(files, name));
+        let json_hash = json
+            .as_ref()
+            .and_then(|j| j.contents.as_ref().ok())
+            .map(|c| metadata_hash(c, &options.metadata));
+        let is_pending = |p: &PathBuf| options.force || !sink.is_written(p, json_hash.as_deref());
+        let imgs: Vec<PathBuf> = [&pair.img, &pair.img_edited]
+            .into_iter()
+            .flatten()
+            .filter(|p| is_pending(p))
+            .cloned()
+            .collect();
+        let write_json = json.as_ref().is_some_and(|j| is_pending(&j.name));
+        summary.skipped += display_paths.len() - imgs.len() - usize::from(write_json);
+        if write_json || !imgs.is_empty() {
+            pending.push((json, json_hash, write_json, imgs));
+        }
+    }
+
+    let pending_files = pending.iter().flat_map(|(json, _, write_json, imgs)| {
+        let json = json.iter().filter(|_| *write_json).map(|j| &j.name);
+        json.chain(imgs)
+    });
+    let (total_files, total_bytes) = pending_files.fold((0, 0), |(count, bytes), p| {
+        (count + 1, bytes + files.size(p))
+    });
+    send(
+        tx,
+        Event::Total {
+            files: total_files,
+            bytes: total_bytes,
+        },
+    );
+    send(tx, Event::Stage(Stage::Writing));
+
+    // files are only read once the pipeline asks for them, so nothing new is read after the run was cancelled
+    let jobs = pending
+        .into_iter()
+        .take_while(|_| control.checkpoint())
+        .map(|(json, json_hash, write_json, imgs)| PairJob {
+            json,
+            json_hash,
+            write_json,
+            imgs: imgs
+                .into_iter()
+                .map(|name| read_file(files, name))
+                .collect(),
+        });
+
+    pipeline::run_ordered(
+        jobs,
+        options.parallelism,
+        |job| process_pair(job, &options.metadata),
+        |(json_hash, json_error, results)| {
+            if let Some(err) = json_error {
+                send(tx, Event::Error(err));
+            }
+            for result in results {
+                send(
+                    tx,
+                    Event::FileDone {
+                        path: result.display_path.clone(),
+                        bytes: result.bytes,
+                    },
+                );
+                let written = match result.contents.as_ref() {
+                    Some(contents) => sink.write(&result.name, contents, json_hash.as_deref()),
+                    None => Ok(()),
+                };
+                let written = written.map_err(|source| Error::Write {
+                    path: result.display_path.clone(),
+                    source,
+                });
+                match written.err().or(result.error) {
+                    Some(err) => send(tx, Event::Error(err)),
+                    None if result.contents.is_some() => {
+                        summary.written(&result.display_path, &result.tags)
+                    }
+                    None => {}
+                }
+            }
+        },
+    );
+    send(tx, Event::Summary(summary));
+}
+
+fn read_file(files: &mut (dyn TakeoutFiles + Send), name: PathBuf) -> FileJob {
+    FileJob {
+        display_path: files.display_path(&name),
+        contents: files.read(&name),
+        name,
+    }
+}
+
+/// Parse the json file of a pair and apply it to the images in memory. Runs on a worker thread. Returns the hash of
+/// the json file and why it couldn't be read or parsed, along with the files to write. Images whose json file can't
+/// be read or parsed, or that `metadata` doesn't apply to, are written as they are.
+fn process_pair(
+    job: PairJob,
+    metadata: &exif_data::MetadataOptions,
+) -> (Option<String>, Option<Error>, Vec<FileResult>) {
+    let mut json_error = None;
+    let mut results = Vec::new();
+    let exif = job.json.and_then(|json| {
+        let (contents, parsed) = match json.contents {
+            Ok(c) => (Some(c.clone()), pair::parse_json(Ok(c))),
+            Err(err) => (None, pair::parse_json(Err(err))),
+        };
+        let exif = match parsed {
+            Ok(exif) => Some(exif),
+            Err(source) => {
+                let path = json.display_path.clone();
+                json_error = Some(Error::Json { path, source });
+                None
+            }
+        };
+        // a json file that can't be parsed is still written as it is
+        if job.write_json {
+            results.push(FileResult {
+                name: json.name,
+                display_path: json.display_path,
+                bytes: contents.as_ref().map_or(0, |c| c.len() as u64),
+                contents,
+                error: None,
+                tags: Vec::new(),
+            });
+        }
+        exif
+    });
+
+    for img in job.imgs {
+        let path = img.display_path.clone();
+        let bytes = img.contents.as_ref().map_or(0, |c| c.len() as u64);
+        let exif = exif.as_ref().filter(|_| metadata.applies_to(&img.name));
+        let (contents, error, tags) = match (img.contents, exif) {
+            (Err(source), _) => (None, Some(Error::Read { path, source }), Vec::new()),
+            (Ok(contents), None) => (Some(contents), None, Vec::new()),
+            (Ok(contents), Some(exif)) => {
+                // keep the original bytes if the metadata can't be applied, so the file is still part of the output
+                let mut modified = contents.clone();
+                match exif.apply_to_bytes(&img.name, &mut modified, metadata) {
+                    Ok(_) => (Some(modified), None, exif.tag_names(metadata)),
+                    Err(source) => (
+                        Some(contents),
+                        Some(Error::Metadata { path, source }),
+                        Vec::new(),
+                    ),
+                }
+            }
+        };
+        results.push(FileResult {
+            name: img.name,
+            display_path: img.display_path,
+            bytes,
+            contents,
+            error,
+            tags,
+        });
+    }
+    (job.json_hash, json_error, results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::{journal::Journal, output::DirSink, test_utils::takeout_zip};
+
+    #[test]
+    fn stream_zip_writes_every_file() {
+        let out_dir = "./test-assets/stream_zip_writes_every_file";
+        let test_zip = takeout_zip(out_dir.to_string() + ".zip");
+        let mut files = ZipSet::open(std::slice::from_ref(&test_zip)).unwrap();
+        let (tx_err, rx_err) = mpsc::channel();
+
+        let mut sink = DirSink::new(PathBuf::from(out_dir), Journal::in_memory());
+        stream(
+            &mut files,
+            &mut sink,
+            &ManualLinks::default(),
+            &RunOptions::default(),
+            &RunControl::default(),
+            &tx_err,
+        );
+        drop(tx_err);
+
+        // assert
+        let written = utils::recursively_collect_filenames(Path::new(out_dir)).unwrap();
+        assert_eq!(written.len(), 8);
+        let failed: Vec<_> = rx_err
+            .iter()
+            .filter_map(Event::into_error)
+            .map(|e| e.path().to_owned())
+            .collect();
+        assert_eq!(failed, vec![test_zip.join("takeout/TEST_HEIC.HEIC")]);
+        // untouched files are written as they are
+        let heic = "takeout/TEST_HEIC.HEIC";
+        assert_eq!(
+            fs::read(Path::new(out_dir).join(heic)).unwrap(),
+            fs::read(Path::new("./test-assets/takeout").join(heic)).unwrap()
+        );
+
+        // cleanup
+        fs::remove_dir_all(out_dir).unwrap();
+        fs::remove_file(test_zip).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+use serde::Serialize;
+
+use super::{ErrorLog, pair::Pair};
+
+/// What a run did, sent with [`super::Event::Summary`] once all files are written.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunSummary {
+    /// Files that were written, including json files and images that were placed without changes
+    pub processed: usize,
+    /// Files that a previous run already processed
+    pub skipped: usize,
+    /// Errors of the run, mostly files that couldn't be read or written
+    pub failed: usize,
+    /// Images that no json file belongs to, they are left untouched
+    pub images_without_json: usize,
+    /// Json files that no image belongs to
+    pub json_without_image: usize,
+    /// Images that got a GPS location
+    pub gps_written: usize,
+    /// Images that got a date
+    pub dates_written: usize,
+    /// Images and videos that were written, by lower case file extension
+    pub by_type: BTreeMap<String, usize>,
+}
+impl RunSummary {
+    /// Count a file that was written, along with the names of the tags that were written to it.
+    pub(super) fn written(&mut self, path: &Path, tags: &[&str]) {
+        self.processed += 1;
+        let extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if extension == "json" {
+            return;
+        }
+        *self.by_type.entry(extension).or_default() += 1;
+        if tags.iter().any(|t| t.starts_with("GPS")) {
+            self.gps_written += 1;
+        }
+        if tags.contains(&"DateTimeOriginal") {
+            self.dates_written += 1;
+        }
+    }
+
+    /// Count the images of `pair` if it has no json file, or its json file if it has no images.
+    pub(super) fn count_unmatched(&mut self, pair: &Pair) {
+        let images = [&pair.img, &pair.img_edited].into_iter().flatten().count();
+        match (&pair.json, images) {
+            (None, images) => self.images_without_json += images,
+            (Some(_), 0) => self.json_without_image += 1,
+            _ => {}
+        }
+    }
+
+    /// Add the results of a run that retried the files of `retried` errors of this run.
+    pub fn add_retry(&mut self, retry: &RunSummary, retried: usize) {
+        self.failed = self.failed.saturating_sub(retried) + retry.failed;
+        self.processed += retry.processed;
+        self.gps_written += retry.gps_written;
+        self.dates_written += retry.dates_written;
+        for (extension, count) in &retry.by_type {
+            *self.by_type.entry(extension.clone()).or_default() += count;
+        }
+    }
+
+    /// The summary along with every error of the run as json.
+    pub fn to_json(&self, errors: &ErrorLog) -> String {
+        #[derive(Serialize)]
+        struct Report<'a, E: Serialize> {
+            summary: &'a RunSummary,
+            errors: E,
+        }
+        let report = Report {
+            summary: self,
+            errors: errors.rows().collect::<Vec<_>>(),
+        };
+        serde_json::to_string_pretty(&report).expect("Reports can always be serialized")
+    }
+
+    /// Write the summary and the errors of the run to `path` as json.
+    pub fn export(&self, errors: &ErrorLog, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_json(errors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn written_files_are_counted_by_type() {
+        let mut summary = RunSummary::default();
+        summary.written(Path::new("a.JPG"), &["GPSLatitude", "DateTimeOriginal"]);
+        summary.written(Path::new("a.JPG.json"), &[]);
+        summary.written(Path::new("b.mp4"), &[]);
+
+        assert_eq!(summary.processed, 3);
+        assert_eq!(summary.gps_written, 1);
+        assert_eq!(summary.dates_written, 1);
+        assert_eq!(
+            summary.by_type,
+            BTreeMap::from([("jpg".to_owned(), 1), ("mp4".to_owned(), 1)])
+        );
+    }
+
+    #[test]
+    fn retry_replaces_failed_files() {
+        let mut summary = RunSummary {
+            processed: 5,
+            failed: 2,
+            ..Default::default()
+        };
+        let retry = RunSummary {
+            processed: 1,
+            failed: 1,
+            ..Default::default()
+        };
+
+        summary.add_retry(&retry, 2);
+
+        assert_eq!(summary.processed, 6);
+        assert_eq!(summary.failed, 1);
+    }
+}
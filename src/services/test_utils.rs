@@ -0,0 +1,70 @@
+//! Fixtures that are built while testing instead of being committed.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// The Takeout that archives are built from. The name of one image doesn't match its json file, like it happens in
+/// real Takeouts.
+pub const TAKEOUT: &str = "./test-assets/takeout";
+
+/// Files and directories of [`TAKEOUT`] relative to it, directories before their contents.
+fn entries() -> Vec<(PathBuf, bool)> {
+    fn walk(dir: &Path, entries: &mut Vec<(PathBuf, bool)>) {
+        let mut children: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        children.sort();
+        for child in children {
+            let name = child.strip_prefix(TAKEOUT).unwrap().to_owned();
+            if child.is_dir() {
+                entries.push((name, true));
+                walk(&child, entries);
+            } else {
+                entries.push((name, false));
+            }
+        }
+    }
+    let mut entries = Vec::new();
+    walk(Path::new(TAKEOUT), &mut entries);
+    entries
+}
+
+/// Pack [`TAKEOUT`] into a zip archive at `path`, like Google packs a Takeout download.
+pub fn takeout_zip(path: impl AsRef<Path>) -> PathBuf {
+    let path = path.as_ref();
+    let mut zip = zip::ZipWriter::new(fs::File::create(path).unwrap());
+    let options =
+        zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    for (name, is_dir) in entries() {
+        let name = name.to_str().unwrap().replace('\\', "/");
+        if is_dir {
+            zip.add_directory(name, options).unwrap();
+        } else {
+            zip.start_file(&name, options).unwrap();
+            io::copy(
+                &mut fs::File::open(Path::new(TAKEOUT).join(&name)).unwrap(),
+                &mut zip,
+            )
+            .unwrap();
+        }
+    }
+    zip.finish().unwrap();
+    path.to_owned()
+}
+
+/// Pack [`TAKEOUT`] into a gzipped tar archive at `path`, like Google packs a Takeout download.
+pub fn takeout_tgz(path: impl AsRef<Path>) -> PathBuf {
+    let path = path.as_ref();
+    let gz =
+        flate2::write::GzEncoder::new(fs::File::create(path).unwrap(), flate2::Compression::fast());
+    let mut tar = tar::Builder::new(gz);
+    for (name, _) in entries() {
+        tar.append_path_with_name(Path::new(TAKEOUT).join(&name), &name)
+            .unwrap();
+    }
+    tar.into_inner().unwrap().finish().unwrap();
+    path.to_owned()
+}
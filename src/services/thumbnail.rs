@@ -0,0 +1,245 @@
+use std::{
+    env, fs,
+    io::{self, Cursor},
+    path::{Path, PathBuf},
+};
+
+use image::{ImageFormat, RgbaImage, imageops};
+use jpeg_decoder::PixelFormat;
+
+use super::journal;
+
+/// A small version of an image, for showing it in the GUI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Thumbnail {
+    pub width: u32,
+    pub height: u32,
+    /// Pixels row by row, four bytes each
+    pub rgba: Vec<u8>,
+}
+
+impl Thumbnail {
+    fn to_png(&self) -> io::Result<Vec<u8>> {
+        let image = RgbaImage::from_raw(self.width, self.height, self.rgba.clone())
+            .ok_or_else(|| io::Error::other("Thumbnail has fewer pixels than its size"))?;
+        let mut png = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .map_err(io::Error::other)?;
+        Ok(png)
+    }
+
+    fn from_png(bytes: &[u8]) -> io::Result<Self> {
+        let image = image::load_from_memory_with_format(bytes, ImageFormat::Png)
+            .map_err(io::Error::other)?
+            .to_rgba8();
+        Ok(Self {
+            width: image.width(),
+            height: image.height(),
+            rgba: image.into_raw(),
+        })
+    }
+}
+
+/// Thumbnails that were decoded before, kept as PNG files in the cache directory of the system, so browsing a Takeout
+/// again doesn't decode every image again. Every Takeout gets a directory of its own, which is only removed by
+/// [`Self::clear`].
+#[derive(Debug, Clone)]
+pub struct ThumbnailCache {
+    dir: PathBuf,
+}
+impl ThumbnailCache {
+    /// The cache of the Takeout at `source`, which is recognized by its absolute path.
+    pub fn for_source(source: &Path) -> Self {
+        let source = std::path::absolute(source).unwrap_or_else(|_| source.to_owned());
+        let key = journal::content_hash(source.as_os_str().as_encoded_bytes());
+        Self {
+            dir: cache_root().join(&key[..16]),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Delete every cached thumbnail of the Takeout.
+    pub fn clear(&self) -> io::Result<()> {
+        match fs::remove_dir_all(&self.dir) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    /// The cached thumbnail of the file `name`, or `decode` it and cache the result. Files are recognized by their
+    /// name and `file_size`, so a file that was replaced gets a new thumbnail.
+    pub fn get_or_insert(
+        &self,
+        name: &Path,
+        file_size: u64,
+        size: u32,
+        decode: impl FnOnce() -> io::Result<Thumbnail>,
+    ) -> io::Result<Thumbnail> {
+        let key = format!("{}:{}:{}", name.display(), file_size, size);
+        let path = self
+            .dir
+            .join(format!("{}.png", journal::content_hash(key.as_bytes())));
+        // a broken cache file is replaced
+        if let Ok(thumbnail) = fs::read(&path).and_then(|png| Thumbnail::from_png(&png)) {
+            return Ok(thumbnail);
+        }
+        let thumbnail = decode()?;
+        // a thumbnail that can't be cached is still shown
+        let _ = fs::create_dir_all(&self.dir).and_then(|_| fs::write(&path, thumbnail.to_png()?));
+        Ok(thumbnail)
+    }
+}
+
+/// Where the thumbnails of all Takeouts are cached: the cache directory of the user, or the temporary directory if
+/// there is none.
+fn cache_root() -> PathBuf {
+    let var = |name| {
+        env::var_os(name)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+    };
+    let user_cache = if cfg!(windows) {
+        var("LOCALAPPDATA")
+    } else if cfg!(target_os = "macos") {
+        var("HOME").map(|home| home.join("Library/Caches"))
+    } else {
+        var("XDG_CACHE_HOME").or_else(|| var("HOME").map(|home| home.join(".cache")))
+    };
+    user_cache
+        .unwrap_or_else(env::temp_dir)
+        .join(env!("CARGO_PKG_NAME"))
+        .join("thumbnails")
+}
+
+/// Whether [`thumbnail`] can decode files like `path`, without reading them.
+pub fn is_supported(path: &Path) -> bool {
+    matches!(extension(path).as_str(), "jpg" | "jpeg" | "png")
+}
+
+/// Decode an image and scale it down to fit into a square of `size` pixels. `path` is only used to determine the file
+/// type, only JPEG and PNG images can be decoded.
+pub fn thumbnail(path: &Path, bytes: &[u8], size: u32) -> io::Result<Thumbnail> {
+    let image = match extension(path).as_str() {
+        "jpg" | "jpeg" => decode_jpeg(bytes, size)?,
+        "png" => image::load_from_memory_with_format(bytes, ImageFormat::Png)
+            .map_err(io::Error::other)?
+            .to_rgba8(),
+        _ => return Err(io::Error::other("Can't show a preview of this file type")),
+    };
+    let scale = (size as f64 / image.width().max(image.height()) as f64).min(1.0);
+    let width = ((image.width() as f64 * scale).round() as u32).max(1);
+    let height = ((image.height() as f64 * scale).round() as u32).max(1);
+    let image = imageops::thumbnail(&image, width, height);
+    Ok(Thumbnail {
+        width,
+        height,
+        rgba: image.into_raw(),
+    })
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+/// JPEGs are decoded at a reduced size right away, which is a lot faster than decoding all pixels.
+fn decode_jpeg(bytes: &[u8], size: u32) -> io::Result<RgbaImage> {
+    let mut decoder = jpeg_decoder::Decoder::new(bytes);
+    let size = size.min(u16::MAX as u32) as u16;
+    decoder.scale(size, size).map_err(io::Error::other)?;
+    let pixels = decoder.decode().map_err(io::Error::other)?;
+    let info = decoder
+        .info()
+        .ok_or_else(|| io::Error::other("JPEG has no image information"))?;
+    let rgba = match info.pixel_format {
+        PixelFormat::RGB24 => pixels
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        PixelFormat::L8 => pixels.iter().flat_map(|&l| [l, l, l, 255]).collect(),
+        PixelFormat::L16 => pixels
+            .chunks_exact(2)
+            .flat_map(|l| [l[0], l[0], l[0], 255])
+            .collect(),
+        PixelFormat::CMYK32 => pixels
+            .chunks_exact(4)
+            .flat_map(|p| {
+                let k = p[3] as u16;
+                let channel = |c: u8| (c as u16 * k / 255) as u8;
+                [channel(p[0]), channel(p[1]), channel(p[2]), 255]
+            })
+            .collect(),
+    };
+    RgbaImage::from_raw(info.width as u32, info.height as u32, rgba)
+        .ok_or_else(|| io::Error::other("JPEG has fewer pixels than its size"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jpeg_is_scaled_down() {
+        let path = Path::new("./test-assets/takeout-unzipped/takeout/TEST_JPG.jpg");
+        let bytes = std::fs::read(path).unwrap();
+
+        let thumbnail = thumbnail(path, &bytes, 64).unwrap();
+
+        assert_eq!(thumbnail.width.max(thumbnail.height), 64);
+        assert_eq!(
+            thumbnail.rgba.len(),
+            (thumbnail.width * thumbnail.height * 4) as usize
+        );
+    }
+
+    #[test]
+    fn heic_is_not_supported() {
+        assert!(thumbnail(Path::new("a.HEIC"), &[], 64).is_err());
+        assert!(!is_supported(Path::new("a.HEIC")));
+    }
+
+    #[test]
+    fn cached_thumbnail_is_reused() {
+        let cache = ThumbnailCache {
+            dir: PathBuf::from("./test-assets/cached_thumbnail_is_reused"),
+        };
+        let thumbnail = Thumbnail {
+            width: 2,
+            height: 1,
+            rgba: vec![1, 2, 3, 255, 4, 5, 6, 255],
+        };
+
+        let first = cache.get_or_insert(Path::new("a.jpg"), 10, 64, || Ok(thumbnail.clone()));
+        let cached = cache.get_or_insert(Path::new("a.jpg"), 10, 64, || {
+            Err(io::Error::other("should be cached"))
+        });
+        let changed = cache.get_or_insert(Path::new("a.jpg"), 11, 64, || {
+            Err(io::Error::other("file changed"))
+        });
+
+        assert_eq!(first.unwrap(), thumbnail);
+        assert_eq!(cached.unwrap(), thumbnail);
+        assert!(changed.is_err());
+        cache.clear().unwrap();
+        assert!(!cache.dir().exists());
+        // clearing an empty cache is fine
+        cache.clear().unwrap();
+    }
+
+    #[test]
+    fn takeouts_get_their_own_cache() {
+        let cache = ThumbnailCache::for_source(Path::new("./test-assets/takeout"));
+        let same =
+            ThumbnailCache::for_source(&std::path::absolute("./test-assets/takeout").unwrap());
+        let other = ThumbnailCache::for_source(Path::new("./test-assets/takeout-unzipped"));
+
+        assert_eq!(cache.dir(), same.dir());
+        assert_ne!(cache.dir(), other.dir());
+        assert!(cache.dir().starts_with(cache_root()));
+    }
+}
@@ -0,0 +1,138 @@
+use std::path::PathBuf;
+
+use chrono::{Datelike, NaiveDateTime};
+
+/// Months with at least this many images can be spikes
+const SPIKE_MIN: usize = 20;
+/// Months with this many times the images of a typical month are spikes
+const SPIKE_FACTOR: usize = 5;
+
+/// Images of a month, see [`Timeline`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Month {
+    pub year: i32,
+    /// From 1 for January to 12 for December
+    pub month: u32,
+    pub files: Vec<PathBuf>,
+    /// A lot more images than usual have a date in this month. Broken Takeouts often give thousands of images the
+    /// date they were uploaded or exported at.
+    pub is_spike: bool,
+}
+
+/// How many images have a date in each month. Wrong dates show up as spikes or as images in unexpected years.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Timeline {
+    /// Every month from the first to the last one with images, also the ones without any
+    pub months: Vec<Month>,
+    /// Images without a date
+    pub undated: Vec<PathBuf>,
+}
+impl Timeline {
+    pub fn new(dates: impl IntoIterator<Item = (PathBuf, Option<NaiveDateTime>)>) -> Self {
+        let mut timeline = Self::default();
+        let mut dated = Vec::new();
+        for (path, date) in dates {
+            match date {
+                Some(date) => dated.push((month_index(date.year(), date.month()), path)),
+                None => timeline.undated.push(path),
+            }
+        }
+        let (Some(first), Some(last)) = (
+            dated.iter().map(|(m, _)| *m).min(),
+            dated.iter().map(|(m, _)| *m).max(),
+        ) else {
+            return timeline;
+        };
+
+        timeline.months = (first..=last)
+            .map(|m| Month {
+                year: m.div_euclid(12) as i32,
+                month: m.rem_euclid(12) as u32 + 1,
+                files: Vec::new(),
+                is_spike: false,
+            })
+            .collect();
+        for (m, path) in dated {
+            timeline.months[(m - first) as usize].files.push(path);
+        }
+
+        let mut counts: Vec<_> = timeline
+            .months
+            .iter()
+            .map(|m| m.files.len())
+            .filter(|c| *c > 0)
+            .collect();
+        counts.sort_unstable();
+        let median = counts[counts.len() / 2];
+        for month in &mut timeline.months {
+            month.files.sort();
+            month.is_spike =
+                month.files.len() >= SPIKE_MIN && month.files.len() > median * SPIKE_FACTOR;
+        }
+        timeline
+    }
+
+    /// Number of images of the month with the most.
+    pub fn max(&self) -> usize {
+        self.months.iter().map(|m| m.files.len()).max().unwrap_or(0)
+    }
+
+    pub fn spikes(&self) -> impl Iterator<Item = (usize, &Month)> {
+        self.months.iter().enumerate().filter(|(_, m)| m.is_spike)
+    }
+}
+
+/// Months since the year 0, so consecutive months have consecutive indices.
+fn month_index(year: i32, month: u32) -> i64 {
+    year as i64 * 12 + month as i64 - 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32) -> Option<NaiveDateTime> {
+        chrono::NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(12, 0, 0)
+    }
+
+    #[test]
+    fn months_without_images_are_included() {
+        let timeline = Timeline::new([
+            (PathBuf::from("a.jpg"), date(2019, 11)),
+            (PathBuf::from("b.jpg"), date(2020, 2)),
+            (PathBuf::from("c.jpg"), date(2020, 2)),
+            (PathBuf::from("d.jpg"), None),
+        ]);
+
+        let months: Vec<_> = timeline
+            .months
+            .iter()
+            .map(|m| (m.year, m.month, m.files.len()))
+            .collect();
+        assert_eq!(
+            months,
+            [(2019, 11, 1), (2019, 12, 0), (2020, 1, 0), (2020, 2, 2)]
+        );
+        assert_eq!(timeline.undated, [PathBuf::from("d.jpg")]);
+        assert_eq!(timeline.max(), 2);
+        assert_eq!(timeline.spikes().count(), 0);
+    }
+
+    #[test]
+    fn month_with_many_more_images_is_a_spike() {
+        let usual = (1..=12).flat_map(|month| {
+            (0..3).map(move |i| {
+                (
+                    PathBuf::from(format!("{}-{}.jpg", month, i)),
+                    date(2018, month),
+                )
+            })
+        });
+        let upload = (0..100).map(|i| (PathBuf::from(format!("upload-{}.jpg", i)), date(2021, 6)));
+
+        let timeline = Timeline::new(usual.chain(upload));
+
+        let spikes: Vec<_> = timeline.spikes().map(|(_, m)| (m.year, m.month)).collect();
+        assert_eq!(spikes, [(2021, 6)]);
+    }
+}
@@ -0,0 +1,284 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::{Deserialize, Serialize};
+
+use super::journal::content_hash;
+
+/// What is needed to turn a file that was modified in place back into its original. Only the bytes between the
+/// unchanged start and end of the file are kept, which for images is the metadata segment that was rewritten.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Backup {
+    /// Absolute, so the backup doesn't depend on the directory the run was started from
+    path: PathBuf,
+    original_hash: String,
+    written_hash: String,
+    prefix_len: usize,
+    suffix_len: usize,
+    /// Base64 encoded original bytes between prefix and suffix
+    segment: String,
+}
+impl Backup {
+    pub fn new(path: &Path, original: &[u8], written: &[u8]) -> Self {
+        let prefix_len = original
+            .iter()
+            .zip(written)
+            .take_while(|(a, b)| a == b)
+            .count();
+        // prefix and suffix must not overlap in either file
+        let max_suffix = original.len().min(written.len()) - prefix_len;
+        let suffix_len = original
+            .iter()
+            .rev()
+            .zip(written.iter().rev())
+            .take(max_suffix)
+            .take_while(|(a, b)| a == b)
+            .count();
+        Self {
+            path: std::path::absolute(path).unwrap_or_else(|_| path.to_owned()),
+            original_hash: content_hash(original),
+            written_hash: content_hash(written),
+            prefix_len,
+            suffix_len,
+            segment: STANDARD.encode(&original[prefix_len..original.len() - suffix_len]),
+        }
+    }
+
+    /// Rebuild the original bytes from the written ones.
+    fn restore(&self, written: &[u8]) -> io::Result<Vec<u8>> {
+        if content_hash(written) != self.written_hash {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "File was modified after metadata was applied",
+            ));
+        }
+        let segment = STANDARD
+            .decode(&self.segment)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut original = Vec::with_capacity(self.prefix_len + segment.len() + self.suffix_len);
+        original.extend_from_slice(&written[..self.prefix_len]);
+        original.extend_from_slice(&segment);
+        original.extend_from_slice(&written[written.len() - self.suffix_len..]);
+        Ok(original)
+    }
+}
+
+/// Keeps the backups of every file a run modified in place. Like the journal, it is an append-only file with one json
+/// entry per line next to the directory it belongs to.
+#[derive(Debug, Default)]
+pub struct UndoStore {
+    file: Option<fs::File>,
+}
+impl UndoStore {
+    /// A store that keeps nothing, for runs that never modify their source.
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Open the undo store of the given directory, creating it if necessary.
+    pub fn for_dir(dir: &Path) -> io::Result<Self> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(Self::path_for_dir(dir))?;
+        Ok(Self { file: Some(file) })
+    }
+
+    pub fn path_for_dir(dir: &Path) -> PathBuf {
+        match dir.file_name() {
+            Some(name) => dir.with_file_name(format!("{}.undo.jsonl", name.to_string_lossy())),
+            None => dir.join(".undo.jsonl"),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.file.is_some()
+    }
+
+    pub fn record(&mut self, backup: &Backup) -> io::Result<()> {
+        if let Some(file) = self.file.as_mut() {
+            let mut line = serde_json::to_vec(backup)?;
+            line.push(b'\n');
+            file.write_all(&line)?;
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Restore every file in the undo store of `dir` to the bytes it had before the first run modified it. A file that was
+/// modified by several runs is rolled back one run after the other. Every restored file is verified against the hash
+/// of its original. Backups of files that couldn't be restored are kept, so undo can be retried.
+///
+/// Returns the files that couldn't be restored.
+pub fn restore_dir(dir: &Path) -> io::Result<Vec<(PathBuf, io::Error)>> {
+    let store_path = UndoStore::path_for_dir(dir);
+    if !store_path.exists() {
+        return Ok(Vec::new());
+    }
+    let mut backups: HashMap<PathBuf, Vec<Backup>> = HashMap::new();
+    let mut order = Vec::new();
+    for line in io::BufReader::new(fs::File::open(&store_path)?).lines() {
+        // the last line is incomplete if a run died while writing it
+        let Ok(backup) = serde_json::from_str::<Backup>(&line?) else {
+            continue;
+        };
+        if !backups.contains_key(&backup.path) {
+            order.push(backup.path.clone());
+        }
+        backups.entry(backup.path.clone()).or_default().push(backup);
+    }
+
+    let mut failed = Vec::new();
+    let mut remaining = Vec::new();
+    for path in order {
+        let mut chain = backups.remove(&path).unwrap_or_default();
+        while let Some(backup) = chain.last() {
+            match restore_file(backup) {
+                Ok(_) => {
+                    chain.pop();
+                }
+                Err(err) => {
+                    failed.push((path.clone(), err));
+                    break;
+                }
+            }
+        }
+        remaining.extend(chain);
+    }
+
+    if remaining.is_empty() {
+        fs::remove_file(&store_path)?;
+    } else {
+        let mut contents = Vec::new();
+        for backup in &remaining {
+            contents.extend(serde_json::to_vec(backup)?);
+            contents.push(b'\n');
+        }
+        write_atomically(&store_path, &contents)?;
+    }
+    Ok(failed)
+}
+
+fn restore_file(backup: &Backup) -> io::Result<()> {
+    let original = backup.restore(&fs::read(&backup.path)?)?;
+    if content_hash(&original) != backup.original_hash {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Restored file doesn't match the original",
+        ));
+    }
+    write_atomically(&backup.path, &original)?;
+    if content_hash(&fs::read(&backup.path)?) != backup.original_hash {
+        return Err(io::Error::other("Restored file couldn't be verified"));
+    }
+    Ok(())
+}
+
+/// Replace the contents of `path` without ever leaving a partially written file behind: the contents are written to a
+/// temporary file next to it first, which then takes its place.
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_owned();
+    tmp_name.push(".undo-tmp");
+    let tmp = path.with_file_name(tmp_name);
+    let written = fs::File::create(&tmp).and_then(|mut file| {
+        file.write_all(contents)?;
+        file.sync_all()?;
+        if let Ok(metadata) = fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        Ok(())
+    });
+    match written.and_then(|_| fs::rename(&tmp, path)) {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            let _ = fs::remove_file(&tmp);
+            Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backup_keeps_only_changed_segment() {
+        let original = b"header old metadata image data";
+        let written = b"header new and longer metadata image data";
+
+        let backup = Backup::new(Path::new("img.jpg"), original, written);
+
+        assert!(backup.path.is_absolute());
+        assert_eq!(backup.prefix_len, "header ".len());
+        assert_eq!(backup.suffix_len, " metadata image data".len());
+        assert_eq!(backup.restore(written).unwrap(), original);
+    }
+
+    #[test]
+    fn backup_handles_repeated_bytes() {
+        // prefix and suffix would overlap if they weren't limited
+        let original = b"aaaa";
+        let written = b"aaaaaa";
+
+        let backup = Backup::new(Path::new("img.jpg"), original, written);
+
+        assert_eq!(backup.restore(written).unwrap(), original);
+    }
+
+    #[test]
+    fn restore_rolls_back_every_run() {
+        let dir = PathBuf::from("./test-assets/restore_rolls_back_every_run");
+        let file = dir.join("my_img.jpg");
+        fs::create_dir_all(&dir).unwrap();
+        let versions: [&[u8]; 3] = [b"jpg original", b"jpg first run", b"jpg second run"];
+        fs::write(&file, versions[2]).unwrap();
+        let mut store = UndoStore::for_dir(&dir).unwrap();
+        for v in versions.windows(2) {
+            store.record(&Backup::new(&file, v[0], v[1])).unwrap();
+        }
+        drop(store);
+
+        let failed = restore_dir(&dir).unwrap();
+
+        // assert
+        assert!(failed.is_empty());
+        assert_eq!(fs::read(&file).unwrap(), versions[0]);
+        assert!(!UndoStore::path_for_dir(&dir).exists());
+        // no temporary files are left behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        // cleanup
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn modified_files_are_not_restored() {
+        let dir = PathBuf::from("./test-assets/modified_files_are_not_restored");
+        let file = dir.join("my_img.jpg");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&file, b"modified by someone else").unwrap();
+        UndoStore::for_dir(&dir)
+            .unwrap()
+            .record(&Backup::new(&file, b"original", b"written"))
+            .unwrap();
+
+        let failed = restore_dir(&dir).unwrap();
+
+        // assert
+        assert_eq!(failed.len(), 1);
+        assert_eq!(fs::read(&file).unwrap(), b"modified by someone else");
+        // kept to allow retrying
+        assert!(UndoStore::path_for_dir(&dir).exists());
+
+        // cleanup
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_file(UndoStore::path_for_dir(&dir)).unwrap();
+    }
+}
@@ -1,21 +1,268 @@
+use super::{control::RunControl, error::Error, journal::Journal, pair};
 use std::{
     collections::{HashSet, VecDeque},
-    fs, io,
-    path::Path,
+    fs,
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
 };
 
-/// Unzips given zip file, creating a new directory for unzipped contents and only keeping files (not empty directories).
-/// Returns directory with extracted files.
-pub fn unzip(zip_path: &Path) -> std::path::PathBuf {
-    let file = fs::File::open(zip_path).unwrap();
-    let mut archive = zip::ZipArchive::new(file).unwrap();
-    let working_dir = zip_path
-        .parent()
-        .unwrap()
-        .join(zip_path.file_stem().unwrap());
-    fs::create_dir_all(&working_dir).unwrap();
+/// Archive formats Google Takeout is offered in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    TarGz,
+}
+impl ArchiveKind {
+    /// Detect the archive format by looking at the magic bytes at the start of the file. Returns `None` if the file is
+    /// not an archive we know how to read.
+    pub fn detect(path: &Path) -> io::Result<Option<Self>> {
+        let mut magic = [0; 4];
+        let mut file = fs::File::open(path)?;
+        let read = file.read(&mut magic)?;
+        Ok(match &magic[..read] {
+            [0x50, 0x4b, 0x03, 0x04] | [0x50, 0x4b, 0x05, 0x06] => Some(ArchiveKind::Zip),
+            [0x1f, 0x8b, ..] => Some(ArchiveKind::TarGz),
+            _ => None,
+        })
+    }
+}
+
+/// How many entries of a tgz archive are looked at, each one has to be decompressed to get to the next
+const TAKEOUT_TGZ_ENTRIES: usize = 20;
+
+/// Whether `source` looks like a Google Takeout, i.e. has a `Takeout` directory or a json file next to the image it
+/// belongs to near its top. Zip archives are checked by their central directory, tgz archives only by their first
+/// few entries.
+pub fn looks_like_takeout(source: &Path) -> io::Result<bool> {
+    if source.is_dir() {
+        return Ok(dir_looks_like_takeout(source, 2));
+    }
+    match ArchiveKind::detect(source)? {
+        Some(ArchiveKind::Zip) => {
+            let archive = zip::ZipArchive::new(fs::File::open(source)?)?;
+            Ok(is_takeout(archive.file_names().map(PathBuf::from)))
+        }
+        Some(ArchiveKind::TarGz) => {
+            let file = fs::File::open(source)?;
+            let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+            let mut names = Vec::new();
+            for entry in archive.entries()?.take(TAKEOUT_TGZ_ENTRIES) {
+                let entry = entry?;
+                if entry.header().entry_type().is_file() {
+                    names.push(entry.path()?.into_owned());
+                }
+            }
+            Ok(is_takeout(names))
+        }
+        None => Ok(false),
+    }
+}
+
+/// Every file of a Google Takeout archive is inside its `Takeout` directory. Archives that were packed again have json
+/// files next to their images instead, a json file on its own (e.g. `package.json`) doesn't count.
+fn is_takeout(names: impl IntoIterator<Item = PathBuf>) -> bool {
+    let mut files = HashSet::new();
+    for name in names {
+        if name.components().next().is_some_and(is_takeout_dir) {
+            return true;
+        }
+        files.insert(name);
+    }
+    pair::create_pairs(files)
+        .values()
+        .any(|p| p.json.is_some() && (p.img.is_some() || p.img_edited.is_some()))
+}
+
+fn is_takeout_dir(name: Component) -> bool {
+    name.as_os_str().eq_ignore_ascii_case("takeout")
+}
+
+/// The directory could be the `Takeout` directory, the one it was extracted into or one further down, so only a few
+/// levels are looked at. Unreadable directories are ignored.
+fn dir_looks_like_takeout(dir: &Path, depth: usize) -> bool {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return false;
+    };
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if path.is_dir() {
+            if is_takeout_dir(Component::Normal(&entry.file_name())) {
+                return true;
+            }
+            dirs.push(path);
+        } else {
+            files.push(path);
+        }
+    }
+    is_takeout(files) || depth > 0 && dirs.iter().any(|d| dir_looks_like_takeout(d, depth - 1))
+}
+
+/// Strips the archive extension and, for parts of a multi-part set, the part number from a file name. Returns the
+/// base name and whether the file is one part of a set. Only the names Google gives the parts of an export count as
+/// parts (e.g. `takeout-20250101T000000Z-001.tgz`, or `takeout-20250101T000000Z-3-001.tgz` for repeated exports), so
+/// unrelated archives that happen to end in a number are never mixed into a set.
+fn split_archive_name(file_name: &str) -> (&str, bool) {
+    let lower = file_name.to_ascii_lowercase();
+    let base = [".tar.gz", ".tgz", ".zip"]
+        .into_iter()
+        .find(|ext| lower.ends_with(ext))
+        .map_or(file_name, |ext| &file_name[..file_name.len() - ext.len()]);
+    match base.rsplit_once('-') {
+        Some((export, part)) if part.len() == 3 && is_number(part) && is_takeout_export(export) => {
+            (export, true)
+        }
+        _ => (base, false),
+    }
+}
+
+/// Whether `name` is `takeout-<timestamp>`, optionally followed by the number of the export.
+fn is_takeout_export(name: &str) -> bool {
+    let Some((prefix, rest)) = name.split_once('-') else {
+        return false;
+    };
+    let (timestamp, export) = match rest.split_once('-') {
+        Some((timestamp, export)) => (timestamp, Some(export)),
+        None => (rest, None),
+    };
+    // e.g. 20250101T000000Z
+    let is_timestamp = timestamp.len() == 16
+        && timestamp.is_ascii()
+        && is_number(&timestamp[..8])
+        && &timestamp[8..9] == "T"
+        && is_number(&timestamp[9..15])
+        && &timestamp[15..] == "Z";
+    prefix.eq_ignore_ascii_case("takeout") && is_timestamp && export.is_none_or(is_number)
+}
+
+fn is_number(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Returns all parts of the multi-part set that `archive_path` belongs to, sorted by part number. Archives that are not
+/// part of a set are returned on their own.
+pub fn archive_parts(archive_path: &Path) -> Vec<PathBuf> {
+    let file_name = archive_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let (base, is_part) = split_archive_name(&file_name);
+    let dir = match archive_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let siblings = match fs::read_dir(dir) {
+        Ok(siblings) if is_part => siblings,
+        _ => return vec![archive_path.to_owned()],
+    };
+
+    let mut parts: Vec<PathBuf> = siblings
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| split_archive_name(name) == (base, true))
+        .map(|name| dir.join(name))
+        .filter(|p| p.is_file())
+        .collect();
+    parts.sort();
+    parts
+}
+
+/// Directory next to the archive that it is extracted into when it can't be read in place. All parts of a multi-part
+/// set share the same directory.
+pub fn working_dir(archive_path: &Path) -> PathBuf {
+    let file_name = archive_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let (base, _) = split_archive_name(&file_name);
+    archive_path.parent().unwrap_or(Path::new("")).join(base)
+}
+
+/// A path next to the Takeout `source` for keeping data about it, named after it with `suffix` appended. All parts of a
+/// multi-part set share the same path.
+pub fn next_to_source(source: &Path, suffix: &str) -> PathBuf {
+    let first = if source.is_dir() {
+        source.to_owned()
+    } else {
+        archive_parts(source)
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| source.to_owned())
+    };
+    match first.file_name() {
+        Some(name) => first.with_file_name(format!("{}.{}", name.to_string_lossy(), suffix)),
+        None => first.join(format!(".{}", suffix)),
+    }
+}
+
+/// Extracts the given archive into `working_dir`, usually the [`working_dir`] next to it. If the archive is one part
+/// of a multi-part set, all parts are extracted into the same directory. Parts that the journal lists as completely
+/// extracted are skipped. Parts with errors aren't recorded, so they are extracted again by the next run. Extraction
+/// stops after the current entry once `control` is cancelled. Returns the directory with the extracted files, along
+/// with the parts and files that couldn't be extracted.
+pub fn extract_to(
+    archive_path: &Path,
+    working_dir: &Path,
+    journal: &mut Journal,
+    control: &RunControl,
+) -> (PathBuf, Vec<Error>) {
+    let mut errors = Vec::new();
+    for part in archive_parts(archive_path) {
+        if !control.checkpoint() {
+            break;
+        }
+        if journal.is_extracted(&part) {
+            continue;
+        }
+        let part_errors = match ArchiveKind::detect(&part) {
+            Ok(Some(ArchiveKind::Zip)) => unzip_to(&part, working_dir, control),
+            Ok(Some(ArchiveKind::TarGz)) => untar_gz_to(&part, working_dir, control),
+            Ok(None) => vec![Error::Extract {
+                source: io::Error::new(io::ErrorKind::InvalidData, "Unsupported archive format"),
+                path: part.clone(),
+            }],
+            Err(source) => vec![Error::Read {
+                path: part.clone(),
+                source,
+            }],
+        };
+        // a cancelled part may be incomplete
+        if part_errors.is_empty()
+            && !control.is_cancelled()
+            && let Err(source) = journal.record_extracted(&part)
+        {
+            errors.push(Error::Write { path: part, source });
+        }
+        errors.extend(part_errors);
+    }
+    (working_dir.to_owned(), errors)
+}
+
+/// Unzips given zip file into `working_dir`, only keeping files (not empty directories). Entries that can't be
+/// extracted are skipped. Returns why the archive or any of its entries couldn't be extracted.
+pub fn unzip_to(zip_path: &Path, working_dir: &Path, control: &RunControl) -> Vec<Error> {
+    let extract_error = |path: &Path, source| Error::Extract {
+        path: path.to_owned(),
+        source,
+    };
+    let archive = fs::File::open(zip_path).and_then(|f| Ok(zip::ZipArchive::new(f)?));
+    let mut archive = match archive {
+        Ok(archive) => archive,
+        Err(err) => return vec![extract_error(zip_path, err)],
+    };
+    let mut errors = Vec::new();
     for i in 0..archive.len() {
-        let mut file = archive.by_index(i).unwrap();
+        if !control.checkpoint() {
+            break;
+        }
+        let mut file = match archive.by_index(i) {
+            Ok(file) => file,
+            Err(err) => {
+                errors.push(extract_error(zip_path, err.into()));
+                continue;
+            }
+        };
         if file.is_dir() {
             // we don't care about empty dirs
             continue;
@@ -24,40 +271,134 @@ pub fn unzip(zip_path: &Path) -> std::path::PathBuf {
             Some(path) => working_dir.join(path),
             None => continue,
         };
-        if let Some(p) = outpath.parent() {
-            if !p.exists() {
-                fs::create_dir_all(p).unwrap();
+        let mode = file.unix_mode();
+        if let Err(err) = extract_entry(&mut file, &outpath, mode) {
+            errors.push(extract_error(&outpath, err));
+        }
+    }
+    errors
+}
+
+/// Extracts a gzip compressed tar file into `working_dir`, only keeping files (not empty directories). Entries that
+/// can't be extracted are skipped, but a corrupt archive can't be read past the first broken entry. Returns why the
+/// archive or any of its entries couldn't be extracted.
+pub fn untar_gz_to(tgz_path: &Path, working_dir: &Path, control: &RunControl) -> Vec<Error> {
+    let extract_error = |path: &Path, source| Error::Extract {
+        path: path.to_owned(),
+        source,
+    };
+    let file = match fs::File::open(tgz_path) {
+        Ok(file) => file,
+        Err(err) => return vec![extract_error(tgz_path, err)],
+    };
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+    let entries = match archive.entries() {
+        Ok(entries) => entries,
+        Err(err) => return vec![extract_error(tgz_path, err)],
+    };
+    let mut errors = Vec::new();
+    for entry in entries {
+        if !control.checkpoint() {
+            break;
+        }
+        let mut entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                errors.push(extract_error(tgz_path, err));
+                break;
+            }
+        };
+        if !entry.header().entry_type().is_file() {
+            // we don't care about empty dirs, links or other special entries
+            continue;
+        }
+        let outpath = match entry.path().map(|p| enclosed_name(&p)) {
+            Ok(Some(path)) => working_dir.join(path),
+            Ok(None) => continue,
+            Err(err) => {
+                errors.push(extract_error(tgz_path, err));
+                continue;
             }
+        };
+        let mode = entry.header().mode().ok();
+        if let Err(err) = extract_entry(&mut entry, &outpath, mode) {
+            errors.push(extract_error(&outpath, err));
         }
-        let mut outfile = fs::File::create(&outpath).unwrap();
-        io::copy(&mut file, &mut outfile).unwrap();
+    }
+    errors
+}
 
-        println!(
-            "File extracted to \"{}\" ({} bytes)",
-            outpath.display(),
-            file.size()
-        );
+/// Write a single archive entry to `outpath`, creating its directory if necessary.
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn extract_entry(entry: &mut impl Read, outpath: &Path, mode: Option<u32>) -> io::Result<()> {
+    if let Some(p) = outpath.parent()
+        && !p.exists()
+    {
+        fs::create_dir_all(p)?;
+    }
+    let mut outfile = fs::File::create(outpath)?;
+    io::copy(entry, &mut outfile)?;
 
-        // get and Set permissions
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::PermissionsExt;
+    // get and Set permissions
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
 
-            if let Some(mode) = file.unix_mode() {
-                fs::set_permissions(&outpath, fs::Permissions::from_mode(mode)).unwrap();
-            }
+        if let Some(mode) = mode {
+            fs::set_permissions(outpath, fs::Permissions::from_mode(mode))?;
+        }
+    }
+    Ok(())
+}
+
+/// Equivalent of [`zip::read::ZipFile::enclosed_name`] for tar entries. Returns `None` for paths that would escape the
+/// working directory.
+fn enclosed_name(path: &Path) -> Option<PathBuf> {
+    let mut enclosed = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(c) => enclosed.push(c),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
         }
     }
-    working_dir
+    Some(enclosed)
 }
 
 /// Recursively read a given directory and return hash set of all file names.
 pub fn recursively_collect_filenames(path: &Path) -> std::io::Result<HashSet<std::path::PathBuf>> {
+    let (paths, errors) = collect_filenames(path);
+    match errors.into_iter().next() {
+        Some(err) => Err(err.into()),
+        None => Ok(paths),
+    }
+}
+
+/// Same as [`recursively_collect_filenames`], but directories that can't be read are skipped instead of failing.
+/// Returns the files that were found along with the directories that couldn't be read.
+pub fn collect_filenames(path: &Path) -> (HashSet<PathBuf>, Vec<Error>) {
     let mut paths = HashSet::new();
+    let mut errors = Vec::new();
     let mut queue = VecDeque::from([path.to_owned()]);
     while let Some(path) = queue.pop_back() {
-        for maybe_dir in fs::read_dir(&path)? {
-            let dir = maybe_dir?;
+        let entries = match fs::read_dir(&path) {
+            Ok(entries) => entries,
+            Err(source) => {
+                errors.push(Error::Read { path, source });
+                continue;
+            }
+        };
+        for maybe_dir in entries {
+            let dir = match maybe_dir {
+                Ok(dir) => dir,
+                Err(source) => {
+                    errors.push(Error::Read {
+                        path: path.clone(),
+                        source,
+                    });
+                    continue;
+                }
+            };
             if dir.path().is_file() {
                 paths.insert(dir.path());
             } else if dir.path().is_dir() {
@@ -66,13 +407,24 @@ pub fn recursively_collect_filenames(path: &Path) -> std::io::Result<HashSet<std
         }
     }
 
-    Ok(paths)
+    (paths, errors)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::path::PathBuf;
+    use crate::services::test_utils::{takeout_tgz, takeout_zip};
+    use std::{io::Write, path::PathBuf};
+
+    /// Extract into the directory next to the archive, without keeping a journal.
+    fn extract(archive_path: &Path) -> (PathBuf, Vec<Error>) {
+        extract_to(
+            archive_path,
+            &working_dir(archive_path),
+            &mut Journal::in_memory(),
+            &RunControl::default(),
+        )
+    }
 
     #[test]
     fn collect_filenames_returns_correct_number_of_files() {
@@ -95,13 +447,13 @@ mod tests {
 
     #[test]
     fn collect_filenames_returns_correct_result_after_unzip() {
-        // copy zip for use in tests
-        let original = "./test-assets/takeout.zip";
+        // build zip for use in tests
         let test_dir = "./test-assets/collect_filenames_returns_same_results_as_unzip";
         let test_zip = test_dir.to_string() + ".zip";
-        fs::copy(original, &test_zip).unwrap();
+        takeout_zip(&test_zip);
 
-        let unzip_path = unzip(Path::new(&test_zip));
+        let (unzip_path, errors) = extract(Path::new(&test_zip));
+        assert!(errors.is_empty());
         let paths = recursively_collect_filenames(&unzip_path).unwrap();
 
         assert_eq!(paths.len(), 8);
@@ -113,14 +465,13 @@ mod tests {
 
     #[test]
     fn unzip_keeps_directory_structure() {
-        // copy zip for use in tests
-        let original = "./test-assets/takeout.zip";
+        // build zip for use in tests
         let test_dir = "./test-assets/unzip_keeps_directory_structure";
         let test_zip = test_dir.to_string() + ".zip";
-        fs::copy(original, &test_zip).unwrap();
+        takeout_zip(&test_zip);
 
         // unzip
-        unzip(Path::new(&test_zip));
+        extract(Path::new(&test_zip));
 
         // assert
         let other_dir = PathBuf::from(test_dir.to_string() + "/takeout/other");
@@ -138,20 +489,242 @@ mod tests {
 
     #[test]
     fn unzip_over_already_unzipped_dir() {
-        // copy zip for use in tests
-        let original = "./test-assets/takeout.zip";
+        // build zip for use in tests
         let test_dir = "./test-assets/unzip_over_already_unzipped_dir";
         let test_zip = test_dir.to_string() + ".zip";
-        fs::copy(original, &test_zip).unwrap();
+        takeout_zip(&test_zip);
 
         // unzip
-        unzip(Path::new(&test_zip));
+        extract(Path::new(&test_zip));
 
         // unzip again
-        unzip(Path::new(&test_zip));
+        extract(Path::new(&test_zip));
+
+        // cleanup
+        fs::remove_dir_all(test_dir).unwrap();
+        fs::remove_file(test_zip).unwrap();
+    }
+
+    #[test]
+    fn detects_archive_kind_by_magic_bytes() {
+        let test_zip = takeout_zip("./test-assets/detects_archive_kind_by_magic_bytes.zip");
+        let zip = ArchiveKind::detect(&test_zip).unwrap();
+        let test_tgz = takeout_tgz("./test-assets/detects_archive_kind_by_magic_bytes.tgz");
+        let tgz = ArchiveKind::detect(&test_tgz).unwrap();
+        let json = ArchiveKind::detect(Path::new(
+            "./test-assets/takeout-unzipped/takeout/TEST_JPG.jpg.json",
+        ))
+        .unwrap();
+
+        assert_eq!(zip, Some(ArchiveKind::Zip));
+        assert_eq!(tgz, Some(ArchiveKind::TarGz));
+        assert_eq!(json, None);
+
+        // cleanup
+        fs::remove_file(test_zip).unwrap();
+        fs::remove_file(test_tgz).unwrap();
+    }
+
+    #[test]
+    fn split_archive_name_strips_extension_and_part() {
+        assert_eq!(split_archive_name("takeout.zip"), ("takeout", false));
+        assert_eq!(split_archive_name("takeout.TAR.GZ"), ("takeout", false));
+        assert_eq!(
+            split_archive_name("takeout-20250101T000000Z-002.tgz"),
+            ("takeout-20250101T000000Z", true)
+        );
+        assert_eq!(
+            split_archive_name("takeout-20250101T000000Z-3-001.zip"),
+            ("takeout-20250101T000000Z-3", true)
+        );
+        assert_eq!(
+            split_archive_name("takeout-20250101T000000Z"),
+            ("takeout-20250101T000000Z", false)
+        );
+        // only Google's names are parts of a set
+        assert_eq!(split_archive_name("backup-002.zip"), ("backup-002", false));
+        assert_eq!(
+            split_archive_name("takeout-2025-001.zip"),
+            ("takeout-2025-001", false)
+        );
+    }
+
+    #[test]
+    fn extract_tgz_keeps_directory_structure() {
+        // build tgz for use in tests
+        let test_dir = "./test-assets/extract_tgz_keeps_directory_structure";
+        let test_tgz = test_dir.to_string() + ".tgz";
+        takeout_tgz(&test_tgz);
+
+        let (extracted, errors) = extract(Path::new(&test_tgz));
+
+        // assert
+        assert!(errors.is_empty());
+        assert_eq!(extracted, PathBuf::from(test_dir));
+        assert!(PathBuf::from(test_dir.to_string() + "/takeout/other").is_dir());
+        assert!(PathBuf::from(test_dir.to_string() + "/takeout/edited").is_dir());
+        assert_eq!(recursively_collect_filenames(&extracted).unwrap().len(), 8);
+
+        // cleanup
+        fs::remove_dir_all(test_dir).unwrap();
+        fs::remove_file(test_tgz).unwrap();
+    }
+
+    #[test]
+    fn extract_multi_part_set() {
+        // build archives as parts of a set for use in tests
+        let test_dir = "./test-assets/extract_multi_part_set";
+        let set = test_dir.to_string() + "/takeout-20250101T000000Z";
+        fs::create_dir_all(test_dir).unwrap();
+        let part1 = set.clone() + "-001.zip";
+        let part2 = set.clone() + "-002.tgz";
+        takeout_zip(&part1);
+        takeout_tgz(&part2);
+
+        // any part selects the whole set
+        assert_eq!(
+            archive_parts(Path::new(&part2)),
+            vec![PathBuf::from(&part1), PathBuf::from(&part2)]
+        );
+        let (extracted, errors) = extract(Path::new(&part2));
+
+        // assert
+        assert!(errors.is_empty());
+        assert_eq!(extracted, PathBuf::from(set));
+        assert_eq!(recursively_collect_filenames(&extracted).unwrap().len(), 8);
+
+        // cleanup
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn extract_skips_parts_in_journal() {
+        // build zip for use in tests
+        let test_dir = "./test-assets/extract_skips_parts_in_journal";
+        let test_zip = test_dir.to_string() + ".zip";
+        takeout_zip(&test_zip);
+        let mut journal = Journal::for_dir(Path::new(test_dir)).unwrap();
+
+        // extract, then remove a file that a second extraction would restore
+        let (extracted, _) = extract_to(
+            Path::new(&test_zip),
+            Path::new(test_dir),
+            &mut journal,
+            &RunControl::default(),
+        );
+        let removed = extracted.join("takeout/TEST_JPG.jpg");
+        fs::remove_file(&removed).unwrap();
+        drop(journal);
+
+        let mut journal = Journal::for_dir(Path::new(test_dir)).unwrap();
+        extract_to(
+            Path::new(&test_zip),
+            Path::new(test_dir),
+            &mut journal,
+            &RunControl::default(),
+        );
+
+        // assert
+        assert!(!removed.exists());
 
         // cleanup
         fs::remove_dir_all(test_dir).unwrap();
         fs::remove_file(test_zip).unwrap();
+        fs::remove_file(Journal::path_for_dir(Path::new(test_dir))).unwrap();
+    }
+
+    #[test]
+    fn unzip_to_leaves_zip_untouched() {
+        // build zip for use in tests
+        let test_dir = "./test-assets/unzip_to_leaves_zip_untouched";
+        let test_zip = test_dir.to_string() + ".zip";
+        let out_dir = test_dir.to_string() + "_out";
+        takeout_zip(&test_zip);
+        let original = fs::read(&test_zip).unwrap();
+
+        let errors = unzip_to(
+            Path::new(&test_zip),
+            Path::new(&out_dir),
+            &RunControl::default(),
+        );
+
+        // assert
+        assert!(errors.is_empty());
+        assert!(!Path::new(test_dir).exists());
+        assert_eq!(
+            recursively_collect_filenames(Path::new(&out_dir))
+                .unwrap()
+                .len(),
+            8
+        );
+        assert_eq!(original, fs::read(&test_zip).unwrap());
+
+        // cleanup
+        fs::remove_dir_all(out_dir).unwrap();
+        fs::remove_file(test_zip).unwrap();
+    }
+
+    #[test]
+    fn takeouts_are_recognized() {
+        let test_zip = takeout_zip("./test-assets/takeouts_are_recognized.zip");
+        let test_tgz = takeout_tgz("./test-assets/takeouts_are_recognized.tgz");
+        for source in [
+            test_zip.to_str().unwrap(),
+            test_tgz.to_str().unwrap(),
+            "./test-assets/takeout-unzipped",
+            "./test-assets/takeout-unzipped/takeout/other",
+        ] {
+            assert!(looks_like_takeout(Path::new(source)).unwrap(), "{}", source);
+        }
+        assert!(!looks_like_takeout(Path::new("./src")).unwrap());
+        assert!(!looks_like_takeout(Path::new("./Cargo.toml")).unwrap());
+        // json files on their own are no Takeout
+        let other_zip = Path::new("./test-assets/takeouts_are_recognized_other.zip");
+        let mut zip = zip::ZipWriter::new(fs::File::create(other_zip).unwrap());
+        zip.start_file("package.json", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"{}").unwrap();
+        zip.finish().unwrap();
+        assert!(!looks_like_takeout(other_zip).unwrap());
+
+        // cleanup
+        fs::remove_file(test_zip).unwrap();
+        fs::remove_file(test_tgz).unwrap();
+        fs::remove_file(other_zip).unwrap();
+    }
+
+    #[test]
+    fn corrupt_archive_is_reported() {
+        let test_dir = "./test-assets/corrupt_archive_is_reported";
+        let test_zip = test_dir.to_string() + ".zip";
+        // valid magic bytes, but nothing else
+        fs::write(&test_zip, [0x50, 0x4b, 0x03, 0x04, 0x00]).unwrap();
+        let mut journal = Journal::in_memory();
+
+        let (_, errors) = extract_to(
+            Path::new(&test_zip),
+            Path::new(test_dir),
+            &mut journal,
+            &RunControl::default(),
+        );
+
+        // assert
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path(), Path::new(&test_zip));
+        assert!(!journal.is_extracted(Path::new(&test_zip)));
+
+        // cleanup
+        fs::remove_file(test_zip).unwrap();
+    }
+
+    #[test]
+    fn collect_filenames_reports_unreadable_dir() {
+        let missing = Path::new("./test-assets/collect_filenames_reports_unreadable_dir");
+
+        let (paths, errors) = collect_filenames(missing);
+
+        assert!(paths.is_empty());
+        assert_eq!(errors.len(), 1);
+        assert!(recursively_collect_filenames(missing).is_err());
     }
 }
@@ -1,21 +1,35 @@
-use std::{io, mem::MaybeUninit, path::PathBuf, sync::mpsc, thread, time::Duration};
+use std::{collections::HashSet, io, path::PathBuf, sync::mpsc};
 
-use crate::{AppState, services};
+use crate::{
+    AppState,
+    services::{self, DryRunReport, ErrorLog, Event, OutputMode, Progress, RunControl},
+};
 use eframe::egui;
 
+use super::error_list::ErrorList;
+use super::utils::{Receiver, spawn_worker};
 use super::{ViewNavigation, Viewable};
 
-#[derive(Debug)]
-pub struct ThreadManager<R, T> {
-    pub rx: mpsc::Receiver<R>,
-    pub tx: mpsc::Sender<T>,
-    pub handle: thread::JoinHandle<()>,
-}
+/// Hover texts of the cancel button, a cancelled run only continues where it stopped if it doesn't write an archive
+const CANCEL_ARCHIVE: &str =
+    "Stop after the files that are being worked on. The unfinished archive is deleted.";
+const CANCEL_FILES: &str =
+    "Stop after the files that are being worked on. Starting again continues where it stopped.";
 
 #[derive(Default)]
 pub struct ApplyMetadata {
-    thread_manager: Option<ThreadManager<Option<(PathBuf, io::Error)>, ()>>,
-    error: Option<(PathBuf, io::Error)>,
+    run: Option<Receiver<Event>>,
+    /// All runs are done, but there were errors the user may want to look at or retry first
+    finished: bool,
+    /// Number of errors of the previous runs whose files are being retried
+    retried: Option<usize>,
+    progress: Progress,
+    /// Why the run stopped before it was done, if it crashed
+    run_error: Option<String>,
+    control: RunControl,
+    error_list: ErrorList,
+    dry_run: Option<Receiver<io::Result<DryRunReport>>>,
+    dry_run_error: Option<io::Error>,
 }
 impl Viewable for ApplyMetadata {
     fn show(
@@ -24,57 +38,221 @@ impl Viewable for ApplyMetadata {
         ctx: &egui::Context,
         ui: &mut egui::Ui,
     ) -> Option<ViewNavigation> {
-        if let Some(receiver) = self.thread_manager.take() {
-            if let Ok(maybe_err) = receiver.rx.recv_timeout(Duration::from_millis(1)) {
-                self.error = maybe_err;
-                if self.error.is_none() {
-                    receiver.handle.join().unwrap();
-                    return Some(ViewNavigation::Next);
+        // reading the Takeout for the other views extracts archives into the same folder a run extracts them to
+        if self.run.is_none()
+            && self.dry_run.is_none()
+            && writes_working_dir(app)
+            && app.library(ctx).get().is_none()
+        {
+            ui.vertical_centered(|ui| {
+                ui.label("Waiting for the Takeout to be read...");
+                ui.spinner();
+            });
+            return None;
+        }
+        if app.dry_run {
+            return self.show_dry_run(app, ui);
+        }
+
+        if let Some(receiver) = self.run.as_ref() {
+            loop {
+                let event = match receiver.rx.try_recv() {
+                    Ok(event) => event,
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    // the run never ends without sending Done, unless it crashed
+                    Err(mpsc::TryRecvError::Disconnected) => {
+                        self.run_error = Some(self.run.take().unwrap().join_disconnected());
+                        self.finished = true;
+                        break;
+                    }
+                };
+                self.progress.update(&event);
+                match event {
+                    Event::Error(err) => app.errors.push(err),
+                    Event::Summary(summary) => match (app.summary.as_mut(), self.retried) {
+                        (Some(previous), Some(retried)) => previous.add_retry(&summary, retried),
+                        _ => app.summary = Some(summary),
+                    },
+                    Event::Done => {
+                        app.cancelled = self.progress.cancelled;
+                        self.run_error = self.run.take().unwrap().join().err();
+                        if app.errors.is_empty() && self.run_error.is_none() {
+                            return Some(ViewNavigation::Next);
+                        }
+                        self.finished = true;
+                        break;
+                    }
+                    _ => {}
                 }
-                self.thread_manager = Some(receiver);
+            }
+        } else if !self.finished {
+            // results of an earlier run that was left with Back
+            app.errors = ErrorLog::default();
+            app.summary = None;
+            app.cancelled = false;
+            self.spawn_run(ctx, app, None);
+        }
+
+        let mut next = None;
+        ui.vertical_centered(|ui| {
+            if let Some(err) = self.run_error.as_ref() {
+                ui.heading("The run stopped unexpectedly");
+                ui.colored_label(ui.visuals().error_fg_color, err);
+            } else if self.finished {
+                ui.heading("Finished with errors");
+                ui.label("The other files were processed. Retrying a group only processes its files again.");
+            }
+            if self.finished {
+                ui.horizontal(|ui| {
+                    if ui.button("Back").clicked() {
+                        next = Some(ViewNavigation::Prev);
+                    }
+                    if ui.button("Continue").clicked() {
+                        next = Some(ViewNavigation::Next);
+                    }
+                });
             } else {
-                self.thread_manager = Some(receiver);
+                show_progress(&self.progress, &self.control, ui);
+                self.show_controls(&app.options.output, ui);
+            }
+        });
+        // the archive would be rewritten with only the retried files
+        let can_retry = self.finished && !matches!(app.options.output, OutputMode::Archive(_));
+        if let Some(i) = self.error_list.show(&app.errors, can_retry, ui) {
+            let group = app.errors.take_group(i);
+            self.retried = Some(group.errors.len());
+            self.spawn_run(ctx, app, Some(group.paths().map(PathBuf::from).collect()));
+        }
+        next
+    }
+
+    /// Showing this view starts a run
+    fn skip_on_back(&self) -> bool {
+        true
+    }
+}
+
+fn show_progress(progress: &Progress, control: &RunControl, ui: &mut egui::Ui) {
+    match progress.stage {
+        _ if control.is_cancelled() => ui.label("Finishing the current files before stopping..."),
+        _ if control.is_paused() => ui.label("Paused"),
+        Some(stage) => ui.label(format!("{}...", stage)),
+        None => ui.label("Applying metadata..."),
+    };
+    let Some(fraction) = progress.fraction() else {
+        ui.spinner();
+        return;
+    };
+    let mut text = format!(
+        "{} / {} files, {:.1} / {:.1} MB",
+        progress.files,
+        progress.total_files,
+        progress.bytes as f64 / 1e6,
+        progress.total_bytes as f64 / 1e6
+    );
+    if let Some(eta) = progress.eta().filter(|_| !control.is_paused()) {
+        text += &format!(", about {}s left", eta.as_secs());
+    }
+    ui.add(egui::ProgressBar::new(fraction).text(text).animate(true));
+    if progress.errors > 0 {
+        ui.label(format!("{} errors so far", progress.errors));
+    }
+}
+impl ApplyMetadata {
+    /// Start a run on a separate thread, restricted to `only` if given.
+    fn spawn_run(&mut self, ctx: &egui::Context, app: &AppState, only: Option<HashSet<PathBuf>>) {
+        let path = app
+            .picked_path
+            .clone()
+            .expect("Did not save file path correctly. Please report this unexpected bug.");
+        let mut options = app.options.clone();
+        options.only = only;
+        self.progress = Progress::default();
+        self.control = RunControl::default();
+        self.run_error = None;
+        self.finished = false;
+        let control = self.control.clone();
+        self.run = Some(spawn_worker(ctx, move |tx| {
+            services::extract_and_apply_metadata(&path, &options, &control, &tx);
+        }));
+    }
+
+    /// Pause, resume and cancel buttons. Files that are being worked on are always finished first.
+    fn show_controls(&self, output: &OutputMode, ui: &mut egui::Ui) {
+        if self.control.is_cancelled() {
+            return;
+        }
+        ui.horizontal(|ui| {
+            if self.control.is_paused() {
+                if ui.button("Resume").clicked() {
+                    self.control.resume();
+                }
+            } else if ui.button("Pause").clicked() {
+                self.control.pause();
+            }
+            let hover = match output {
+                OutputMode::Archive(_) => CANCEL_ARCHIVE,
+                _ => CANCEL_FILES,
+            };
+            if ui.button("Cancel").on_hover_text(hover).clicked() {
+                self.control.cancel();
+            }
+        });
+    }
+
+    /// Compute what a run would change on a separate thread and hand the report to the next view.
+    fn show_dry_run(&mut self, app: &mut AppState, ui: &mut egui::Ui) -> Option<ViewNavigation> {
+        if let Some(receiver) = self.dry_run.take() {
+            match receiver.rx.try_recv() {
+                Ok(result) => match receiver.join().map_err(io::Error::other).and(result) {
+                    Ok(report) => {
+                        app.report = Some(report);
+                        return Some(ViewNavigation::Next);
+                    }
+                    Err(err) => self.dry_run_error = Some(err),
+                },
+                Err(mpsc::TryRecvError::Empty) => self.dry_run = Some(receiver),
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.dry_run_error = Some(io::Error::other(receiver.join_disconnected()));
+                }
             }
-        } else {
-            // spawn thread and execute metadata application
-            let (tx_confirm, rx_confirm) = mpsc::channel();
-            let (tx_err, rx_err) = mpsc::channel();
+        } else if self.dry_run_error.is_none() {
+            app.report = None;
             let path = app
                 .picked_path
                 .clone()
                 .expect("Did not save file path correctly. Please report this unexpected bug.");
-            let handle = thread::spawn(move || {
-                services::extract_and_apply_metadata(&path, &rx_confirm, &tx_err);
-                if let Err(err) = tx_err.send(None) {
-                    panic!("Failed to signal end of metadata application: {}", err);
-                }
-            });
-            self.thread_manager = Some(ThreadManager {
-                handle,
-                rx: rx_err,
-                tx: tx_confirm,
-            });
+            let parallelism = app.options.parallelism;
+            let metadata = app.options.metadata.clone();
+            self.dry_run = Some(spawn_worker(ui.ctx(), move |tx| {
+                let _ = tx.send(services::dry_run(&path, parallelism, &metadata));
+            }));
         }
 
         ui.vertical_centered(|ui| {
-            if let Some((img_path, err)) = self.error.as_ref() {
-                ui.label("An error has occurred for file:");
-                ui.label(img_path.to_str().unwrap());
+            if let Some(err) = self.dry_run_error.as_ref() {
+                ui.label("The dry run failed:");
                 ui.label(err.to_string());
-                if ui.button("Ok").clicked() {
-                    self.thread_manager
-                        .as_ref()
-                        .unwrap()
-                        .tx
-                        .send(())
-                        .expect("Could not confirm error");
-                    self.error = None;
-                }
+                ui.button("Back").clicked().then_some(ViewNavigation::Prev)
             } else {
-                ui.label("Applying metadata...");
+                ui.label("Computing what would change...");
                 ui.spinner();
+                None
             }
-        });
-        None
+        })
+        .inner
+    }
+}
+
+/// Whether the run, or dry run, of the picked Takeout extracts or writes into the folder its library is read from
+fn writes_working_dir(app: &AppState) -> bool {
+    let Some(source) = app.picked_path.as_deref() else {
+        return false;
+    };
+    match &app.options.output {
+        _ if app.dry_run => services::is_extracted_for_reading(source),
+        OutputMode::InPlace => !source.is_dir(),
+        OutputMode::Tree { .. } => false,
+        OutputMode::Archive(_) => services::is_extracted_for_reading(source),
     }
 }
@@ -0,0 +1,109 @@
+use std::path::PathBuf;
+
+use crate::services::{DryRunReport, FileDiff};
+use eframe::egui;
+
+use super::utils::{Receiver, spawn_dialog};
+
+/// Shows the result of a dry run as a filterable table and allows exporting it.
+#[derive(Default)]
+pub struct DiffTable {
+    filter: String,
+    only_changed: bool,
+    export_receiver: Option<Receiver<Option<PathBuf>>>,
+    export_result: Option<String>,
+}
+impl DiffTable {
+    pub fn show(&mut self, report: &DryRunReport, ui: &mut egui::Ui) {
+        match Receiver::poll(&mut self.export_receiver) {
+            Some(Ok(Some(path))) => {
+                self.export_result = Some(match report.export(&path) {
+                    Ok(_) => format!("Exported to {}", path.display()),
+                    Err(err) => format!("Export failed: {}", err),
+                });
+            }
+            Some(Err(err)) => self.export_result = Some(format!("Export failed: {}", err)),
+            Some(Ok(None)) | None => {}
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Filter:");
+            ui.text_edit_singleline(&mut self.filter);
+            ui.checkbox(&mut self.only_changed, "Only files that would change");
+            if ui.button("Export…").clicked() {
+                self.export_receiver = Some(spawn_dialog(ui.ctx(), || {
+                    rfd::FileDialog::new()
+                        .add_filter("JSON", &["json"])
+                        .add_filter("CSV", &["csv"])
+                        .set_file_name("dry-run.json")
+                        .save_file()
+                }));
+            }
+        });
+        if let Some(result) = self.export_result.as_ref() {
+            ui.label(result);
+        }
+
+        let changed = report.files.iter().filter(|f| f.is_changed()).count();
+        let failed = report.files.iter().filter(|f| f.error.is_some()).count();
+        ui.label(format!(
+            "{} files, {} would change, {} would fail",
+            report.files.len(),
+            changed,
+            failed
+        ));
+
+        egui::ScrollArea::both().show(ui, |ui| {
+            egui::Grid::new("dry_run_diff")
+                .striped(true)
+                .num_columns(4)
+                .show(ui, |ui| {
+                    ui.strong("File");
+                    ui.strong("Tag");
+                    ui.strong("Before");
+                    ui.strong("After");
+                    ui.end_row();
+
+                    for file in report.files.iter().filter(|f| self.matches(f)) {
+                        let path = file.path.display().to_string();
+                        if let Some(err) = file.error.as_ref() {
+                            ui.label(path);
+                            ui.colored_label(ui.visuals().error_fg_color, err);
+                            ui.end_row();
+                            continue;
+                        }
+                        if file.changes.is_empty() {
+                            ui.label(path);
+                            ui.weak("No metadata to apply");
+                            ui.end_row();
+                            continue;
+                        }
+                        for change in &file.changes {
+                            ui.label(&path);
+                            ui.label(&change.tag);
+                            ui.label(change.before.as_deref().unwrap_or("–"));
+                            if change.is_changed() {
+                                ui.strong(&change.after);
+                            } else {
+                                ui.label(&change.after);
+                            }
+                            ui.end_row();
+                        }
+                    }
+                });
+        });
+    }
+
+    fn matches(&self, file: &FileDiff) -> bool {
+        if self.only_changed && !file.is_changed() && file.error.is_none() {
+            return false;
+        }
+        let filter = self.filter.to_lowercase();
+        filter.is_empty()
+            || file.path.to_string_lossy().to_lowercase().contains(&filter)
+            || file
+                .changes
+                .iter()
+                .any(|c| c.tag.to_lowercase().contains(&filter))
+    }
+}
@@ -0,0 +1,98 @@
+use std::{collections::HashSet, path::PathBuf};
+
+use crate::services::{ErrorLog, Phase};
+use eframe::egui;
+
+use super::utils::{Receiver, spawn_dialog};
+
+/// Shows the errors of a run grouped by what went wrong and allows exporting them.
+#[derive(Default)]
+pub struct ErrorList {
+    /// Groups the user doesn't want to see the files of anymore, also for errors that are still coming in
+    skipped: HashSet<(Phase, String)>,
+    export_receiver: Option<Receiver<Option<PathBuf>>>,
+    export_result: Option<String>,
+}
+impl ErrorList {
+    /// Returns the index of a group the user wants to retry. Retrying is only offered if `can_retry` is set.
+    pub fn show(&mut self, log: &ErrorLog, can_retry: bool, ui: &mut egui::Ui) -> Option<usize> {
+        match Receiver::poll(&mut self.export_receiver) {
+            Some(Ok(Some(path))) => {
+                self.export_result = Some(match log.export(&path) {
+                    Ok(_) => format!("Exported to {}", path.display()),
+                    Err(err) => format!("Export failed: {}", err),
+                });
+            }
+            Some(Err(err)) => self.export_result = Some(format!("Export failed: {}", err)),
+            Some(Ok(None)) | None => {}
+        }
+
+        if log.is_empty() {
+            return None;
+        }
+
+        let mut retry = None;
+        ui.horizontal(|ui| {
+            ui.label(format!("{} files could not be processed", log.len()));
+            if ui.button("Export…").clicked() {
+                self.export_receiver = Some(spawn_dialog(ui.ctx(), || {
+                    rfd::FileDialog::new()
+                        .add_filter("CSV", &["csv"])
+                        .add_filter("JSON", &["json"])
+                        .set_file_name("errors.csv")
+                        .save_file()
+                }));
+            }
+        });
+        if let Some(result) = self.export_result.as_ref() {
+            ui.label(result);
+        }
+
+        egui::ScrollArea::vertical()
+            .max_height(300.0)
+            .show(ui, |ui| {
+                for (i, group) in log.groups().iter().enumerate() {
+                    let key = (group.phase, group.reason.clone());
+                    let title =
+                        format!("{}: {} ({})", group.phase, group.reason, group.errors.len());
+                    if self.skipped.contains(&key) {
+                        ui.horizontal(|ui| {
+                            ui.weak(format!("{}, skipped", title));
+                            if ui.small_button("Show").clicked() {
+                                self.skipped.remove(&key);
+                            }
+                        });
+                        continue;
+                    }
+
+                    ui.horizontal(|ui| {
+                        if ui
+                            .small_button("Skip all like this")
+                            .on_hover_text(
+                                "Hide these files, also ones that fail the same way later",
+                            )
+                            .clicked()
+                        {
+                            self.skipped.insert(key.clone());
+                        }
+                        // extracting is repeated by any run anyway, so there is nothing to restrict a retry to
+                        if can_retry
+                            && group.phase != Phase::Extract
+                            && ui.small_button("Retry").clicked()
+                        {
+                            retry = Some(i);
+                        }
+                    });
+                    egui::CollapsingHeader::new(title)
+                        .id_salt(("error_group", i))
+                        .show(ui, |ui| {
+                            for err in &group.errors {
+                                ui.label(err.path().display().to_string());
+                                ui.colored_label(ui.visuals().error_fg_color, err.to_string());
+                            }
+                        });
+                }
+            });
+        retry
+    }
+}
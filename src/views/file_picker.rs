@@ -1,15 +1,16 @@
 use crate::AppState;
+use crate::services;
 use eframe::egui;
 use std::path::PathBuf;
-use std::time::Duration;
 
-use super::utils::Receiver;
+use super::utils::{Receiver, spawn_dialog};
 use super::{ViewNavigation, Viewable};
 
 #[derive(Default)]
 pub struct FilePicker {
-    dropped_files: Vec<egui::DroppedFile>,
-    receiver: Option<Receiver<PathBuf>>,
+    receiver: Option<Receiver<Option<PathBuf>>>,
+    /// Files that were selected last but can't be used, along with why
+    rejected: Option<(Vec<PathBuf>, String)>,
 }
 impl Viewable for FilePicker {
     fn show(
@@ -19,57 +20,40 @@ impl Viewable for FilePicker {
         ui: &mut egui::Ui,
     ) -> Option<ViewNavigation> {
         let nav = ui.vertical_centered_justified(|ui| {
-            ui.label("Drag-and-drop files onto the window!");
+            ui.label(
+                "Drag-and-drop a Takeout archive (zip or tgz), all parts of one, \
+                or an extracted Takeout folder onto the window!",
+            );
             if ui.button("Open file…").clicked() {
-                let (tx, rx) = std::sync::mpsc::channel();
-                let handle = std::thread::spawn(move || {
-                    if let Some(path) = rfd::FileDialog::new().pick_file() {
-                        match tx.send(path) {
-                            Ok(_) => {}
-                            Err(err) => println!("Uh oh {:?}", err.to_string()),
-                        }
-                    }
-                });
-                self.receiver = Some(Receiver { rx, handle });
+                self.receiver = Some(spawn_dialog(ctx, || {
+                    rfd::FileDialog::new()
+                        .add_filter("Takeout archive", &["zip", "tgz", "gz"])
+                        .pick_file()
+                }));
+            }
+            if ui.button("Open folder…").clicked() {
+                self.receiver = Some(spawn_dialog(ctx, || rfd::FileDialog::new().pick_folder()));
+            }
+            // coming back from a later step
+            if let Some(path) = app.picked_path.as_ref()
+                && ui
+                    .button(format!("Continue with {}", path.display()))
+                    .clicked()
+            {
+                return Some(ViewNavigation::Next);
             }
 
-            if let Some(receiver) = self.receiver.take() {
-                if let Ok(picked_path) = receiver.rx.recv_timeout(Duration::from_millis(1)) {
-                    app.picked_path = Some(picked_path);
-                    receiver.handle.join().unwrap();
-                    return Some(ViewNavigation::Next);
-                } else {
-                    // put receiver back if not used
-                    self.receiver = Some(receiver);
-                }
+            match Receiver::poll(&mut self.receiver) {
+                Some(Ok(Some(picked_path))) => return self.select(app, vec![picked_path]),
+                Some(Err(err)) => self.rejected = Some((Vec::new(), err)),
+                Some(Ok(None)) | None => {}
             }
 
-            // Show dropped files (if any):
-            if !self.dropped_files.is_empty() {
+            if let Some((paths, reason)) = self.rejected.as_ref() {
                 ui.group(|ui| {
-                    ui.label("Dropped files:");
-
-                    for file in &self.dropped_files {
-                        let mut info = if let Some(path) = &file.path {
-                            path.display().to_string()
-                        } else if !file.name.is_empty() {
-                            file.name.clone()
-                        } else {
-                            "???".to_owned()
-                        };
-
-                        let mut additional_info = vec![];
-                        if !file.mime.is_empty() {
-                            additional_info.push(format!("type: {}", file.mime));
-                        }
-                        if let Some(bytes) = &file.bytes {
-                            additional_info.push(format!("{} bytes", bytes.len()));
-                        }
-                        if !additional_info.is_empty() {
-                            info += &format!(" ({})", additional_info.join(", "));
-                        }
-
-                        ui.label(info);
+                    ui.colored_label(ui.visuals().error_fg_color, reason);
+                    for path in paths {
+                        ui.label(path.display().to_string());
                     }
                 });
             }
@@ -79,16 +63,41 @@ impl Viewable for FilePicker {
 
         preview_files_being_dropped(ctx);
 
-        // Collect dropped files:
-        ctx.input(|i| {
-            if !i.raw.dropped_files.is_empty() {
-                self.dropped_files.clone_from(&i.raw.dropped_files);
-            }
-        });
+        // Use dropped files as input:
+        let dropped = ctx.input(|i| i.raw.dropped_files.clone());
+        if !dropped.is_empty() {
+            let paths: Option<Vec<_>> = dropped.iter().map(|f| f.path.clone()).collect();
+            return match paths {
+                Some(paths) => self.select(app, paths),
+                None => {
+                    let names = dropped.into_iter().map(|f| PathBuf::from(f.name)).collect();
+                    let reason =
+                        "Only files that are stored on this computer can be used".to_owned();
+                    self.rejected = Some((names, reason));
+                    None
+                }
+            };
+        }
 
         nav.inner
     }
 }
+impl FilePicker {
+    /// Use `paths` as the input of the run if they are a Takeout, and move on to the next step.
+    fn select(&mut self, app: &mut AppState, paths: Vec<PathBuf>) -> Option<ViewNavigation> {
+        match services::source_from_paths(&paths) {
+            Ok(source) => {
+                app.picked_path = Some(source);
+                self.rejected = None;
+                Some(ViewNavigation::Next)
+            }
+            Err(err) => {
+                self.rejected = Some((paths, err.to_string()));
+                None
+            }
+        }
+    }
+}
 
 fn preview_files_being_dropped(ctx: &egui::Context) {
     use egui::{Align2, Color32, Id, LayerId, Order, TextStyle};
@@ -0,0 +1,483 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+    io, mem,
+    path::Path,
+    sync::{Arc, Mutex, mpsc},
+};
+
+use crate::{
+    AppState,
+    services::{
+        ErrorLog, GalleryItem, GroupPreview, MetadataOptions, TakeoutLibrary, Thumbnail,
+        ThumbnailCache,
+    },
+};
+use eframe::egui;
+
+use super::library::lock;
+use super::preview::GroupDetails;
+use super::utils::{Receiver, load_thumbnail, spawn_worker};
+use super::{ViewNavigation, Viewable};
+
+/// Size of the square thumbnails are scaled into
+const THUMBNAIL_SIZE: u32 = 128;
+/// Size of the square thumbnails of the details are scaled into
+const DETAILS_THUMBNAIL_SIZE: u32 = 256;
+/// Thumbnails that were shown longest ago are dropped from the GPU once there are more than this
+const MAX_TEXTURES: usize = 1000;
+
+enum Request {
+    /// Items that are visible and have no thumbnail yet, top to bottom. They replace the ones asked for before, which
+    /// may have been scrolled past already.
+    Thumbnails(Vec<usize>),
+    Details(usize, MetadataOptions),
+    ClearCache,
+}
+
+enum Message {
+    Items(Vec<GalleryItem>),
+    /// `None` if the file can't be shown
+    Thumbnail(usize, Option<Thumbnail>),
+    Details(usize, Box<GroupPreview>),
+    CacheCleared(io::Result<()>),
+}
+
+/// What the gallery is restricted to, `None` for any.
+#[derive(Default)]
+struct Filter {
+    album: Option<String>,
+    year: Option<i32>,
+    person: Option<String>,
+    has_gps: bool,
+    has_errors: bool,
+}
+
+/// Values the items can be filtered by.
+#[derive(Default)]
+struct FilterValues {
+    albums: BTreeSet<String>,
+    years: BTreeSet<i32>,
+    people: BTreeSet<String>,
+}
+
+/// A grid of thumbnails of every image of the Takeout. Clicking one shows its metadata. It's opened on top of the
+/// wizard and stops reading the Takeout once it is closed.
+#[derive(Default)]
+pub struct Gallery {
+    worker: Option<Receiver<Message>>,
+    /// Why the worker stopped early
+    error: Option<String>,
+    /// Asks the worker for thumbnails and details
+    requests: Option<mpsc::Sender<Request>>,
+    items: Option<Vec<GalleryItem>>,
+    values: FilterValues,
+    filter: Filter,
+    /// Items with a file that failed in a run
+    failed: HashSet<usize>,
+    /// How many errors there were when [`Self::failed`] was computed, it's computed again once that changes
+    failed_for: Option<usize>,
+    /// Thumbnails by item along with the frame they were shown last, `None` for files that can't be shown
+    textures: HashMap<usize, (Option<egui::TextureHandle>, u64)>,
+    /// Visible items whose thumbnails were asked for last
+    requested: HashSet<usize>,
+    /// Visible items without a thumbnail, collected while the grid is shown
+    missing: Vec<usize>,
+    frame: u64,
+    selected: Option<usize>,
+    details: Option<GroupDetails>,
+    cache_cleared: Option<io::Result<()>>,
+}
+impl Viewable for Gallery {
+    fn show(
+        &mut self,
+        app: &mut AppState,
+        ctx: &egui::Context,
+        ui: &mut egui::Ui,
+    ) -> Option<ViewNavigation> {
+        let navigation = ui
+            .horizontal(|ui| {
+                let close = ui.button("Close").clicked();
+                if ui
+                    .button("Clear thumbnail cache")
+                    .on_hover_text("Thumbnails are kept after closing the gallery, so it opens faster next time")
+                    .clicked()
+                    && let Some(requests) = self.requests.as_ref()
+                {
+                    let _ = requests.send(Request::ClearCache);
+                }
+                match self.cache_cleared.as_ref() {
+                    Some(Ok(())) => {
+                        ui.label("Cleared");
+                    }
+                    Some(Err(err)) => {
+                        ui.label(format!("Clearing failed: {}", err));
+                    }
+                    None => {}
+                }
+                close.then_some(ViewNavigation::Prev)
+            })
+            .inner;
+        let Some(library) = app.library(ctx).show(ui) else {
+            return navigation;
+        };
+        if self.worker.is_none() && self.items.is_none() && self.error.is_none() {
+            self.spawn_worker(ctx, app, library);
+        }
+        self.receive(ctx);
+        self.frame += 1;
+
+        if let Some(err) = self.error.as_ref() {
+            ui.colored_label(
+                ui.visuals().error_fg_color,
+                format!("Could not read Takeout: {}", err),
+            );
+        }
+        match self.items.as_ref() {
+            None if self.error.is_none() => {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label("Reading metadata...");
+                });
+            }
+            None => {}
+            Some(items) => {
+                if self.failed_for != Some(app.errors.len()) {
+                    self.failed = failed_items(items, &app.errors);
+                    self.failed_for = Some(app.errors.len());
+                }
+                self.show_filter(ui);
+                self.show_details(ui);
+                self.show_grid(app, ui);
+                self.request_thumbnails();
+                self.evict_textures();
+            }
+        }
+        navigation
+    }
+}
+impl Gallery {
+    /// Read the metadata of every image on a separate thread, which then keeps answering requests for thumbnails and
+    /// details until the view is closed. Details are answered first, since the user is waiting for them.
+    fn spawn_worker(
+        &mut self,
+        ctx: &egui::Context,
+        app: &AppState,
+        library: Arc<Mutex<TakeoutLibrary>>,
+    ) {
+        let (request_tx, request_rx) = mpsc::channel();
+        let cache = ThumbnailCache::for_source(
+            app.picked_path
+                .as_ref()
+                .expect("Did not save file path correctly. Please report this unexpected bug."),
+        );
+        self.worker = Some(spawn_worker(ctx, move |tx| {
+            let items = lock(&library).gallery();
+            if tx.send(Message::Items(items.clone())).is_err() {
+                return;
+            }
+            let mut thumbnails = VecDeque::new();
+            let mut details = None;
+            loop {
+                // only waits if there is nothing left to do, the requests disconnect once the view is closed
+                let waited = if thumbnails.is_empty() && details.is_none() {
+                    match request_rx.recv() {
+                        Ok(request) => Some(request),
+                        Err(_) => return,
+                    }
+                } else {
+                    None
+                };
+                for request in waited.into_iter().chain(request_rx.try_iter()) {
+                    match request {
+                        Request::Thumbnails(visible) => thumbnails = VecDeque::from(visible),
+                        Request::Details(i, options) => details = Some((i, options)),
+                        Request::ClearCache => {
+                            if tx.send(Message::CacheCleared(cache.clear())).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                }
+                let message = if let Some((i, options)) = details.take() {
+                    let preview =
+                        lock(&library).preview(&items[i].group, &options, DETAILS_THUMBNAIL_SIZE);
+                    Message::Details(i, Box::new(preview))
+                } else if let Some(i) = thumbnails.pop_front() {
+                    let thumbnail =
+                        lock(&library).cached_thumbnail(&items[i].path, THUMBNAIL_SIZE, &cache);
+                    Message::Thumbnail(i, thumbnail.ok())
+                } else {
+                    continue;
+                };
+                if tx.send(message).is_err() {
+                    return;
+                }
+            }
+        }));
+        self.requests = Some(request_tx);
+    }
+
+    fn receive(&mut self, ctx: &egui::Context) {
+        let Some(worker) = self.worker.as_ref() else {
+            return;
+        };
+        loop {
+            let message = match worker.rx.try_recv() {
+                Ok(message) => message,
+                Err(mpsc::TryRecvError::Empty) => return,
+                // the worker keeps running until the view is closed, unless it crashed
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.error = Some(self.worker.take().unwrap().join_disconnected());
+                    return;
+                }
+            };
+            match message {
+                Message::Items(items) => {
+                    self.values = filter_values(&items);
+                    self.items = Some(items);
+                }
+                Message::Thumbnail(i, thumbnail) => {
+                    let texture =
+                        thumbnail.map(|t| load_thumbnail(ctx, &format!("gallery_{}", i), &t));
+                    self.textures.insert(i, (texture, self.frame));
+                }
+                // answers for items that were selected before the current one are outdated
+                Message::Details(i, preview) if self.selected == Some(i) => {
+                    self.details = Some(GroupDetails::new(ctx, preview));
+                }
+                Message::Details(..) => {}
+                Message::CacheCleared(result) => self.cache_cleared = Some(result),
+            }
+        }
+    }
+
+    /// Ask for the thumbnails of the visible items once items become visible that weren't asked for yet. Thumbnails
+    /// that were asked for before and aren't visible anymore are dropped by the worker.
+    fn request_thumbnails(&mut self) {
+        let missing = mem::take(&mut self.missing);
+        if missing.iter().all(|i| self.requested.contains(i)) {
+            return;
+        }
+        if let Some(requests) = self.requests.as_ref() {
+            self.requested = missing.iter().copied().collect();
+            let _ = requests.send(Request::Thumbnails(missing));
+        }
+    }
+
+    fn show_filter(&mut self, ui: &mut egui::Ui) {
+        let filter = &mut self.filter;
+        ui.horizontal_wrapped(|ui| {
+            combo_box("Album", &mut filter.album, &self.values.albums, ui);
+            combo_box("Year", &mut filter.year, &self.values.years, ui);
+            combo_box("Person", &mut filter.person, &self.values.people, ui);
+            ui.checkbox(&mut filter.has_gps, "Has location");
+            ui.checkbox(&mut filter.has_errors, "Has errors");
+        });
+    }
+
+    fn show_details(&mut self, ui: &mut egui::Ui) {
+        if self.selected.is_none() {
+            return;
+        }
+        let mut close = false;
+        egui::SidePanel::right("gallery_details")
+            .resizable(true)
+            .default_width(400.0)
+            .show_inside(ui, |ui| {
+                close = ui.button("Close").clicked();
+                match self.details.as_ref() {
+                    Some(details) => details.show(ui),
+                    None => {
+                        ui.spinner();
+                    }
+                }
+            });
+        if close {
+            self.selected = None;
+            self.details = None;
+        }
+    }
+
+    /// Only the rows that are visible are laid out, so their thumbnails are the only ones that are asked for.
+    fn show_grid(&mut self, app: &AppState, ui: &mut egui::Ui) {
+        // taken out while its cells are shown, which updates the thumbnails
+        let Some(items) = self.items.take() else {
+            return;
+        };
+        let shown: Vec<_> = (0..items.len())
+            .filter(|&i| self.matches(i, &items[i]))
+            .collect();
+        ui.label(format!("{} of {} images", shown.len(), items.len()));
+
+        let cell = THUMBNAIL_SIZE as f32;
+        let spacing = ui.spacing().item_spacing.x;
+        let columns = (((ui.available_width() + spacing) / (cell + spacing)) as usize).max(1);
+        let rows = shown.len().div_ceil(columns);
+        let mut clicked = None;
+        egui::ScrollArea::vertical()
+            .auto_shrink(false)
+            .show_rows(ui, cell, rows, |ui, rows| {
+                for row in rows {
+                    ui.horizontal(|ui| {
+                        for &i in shown.iter().skip(row * columns).take(columns) {
+                            if self.show_cell(i, &items[i], ui).clicked() {
+                                clicked = Some(i);
+                            }
+                        }
+                    });
+                }
+            });
+        self.items = Some(items);
+
+        if let Some(i) = clicked
+            && self.selected != Some(i)
+            && let Some(requests) = self.requests.as_ref()
+        {
+            self.selected = Some(i);
+            self.details = None;
+            let _ = requests.send(Request::Details(i, app.options.metadata.clone()));
+        }
+    }
+
+    fn show_cell(&mut self, i: usize, item: &GalleryItem, ui: &mut egui::Ui) -> egui::Response {
+        let size = egui::Vec2::splat(THUMBNAIL_SIZE as f32);
+        let (rect, response) = ui.allocate_exact_size(size, egui::Sense::click());
+        let visuals = ui.visuals();
+        match self.textures.get_mut(&i) {
+            Some((Some(texture), shown)) => {
+                *shown = self.frame;
+                let fit = texture.size_vec2() * (size.x / texture.size_vec2().max_elem());
+                let uv = egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0));
+                ui.painter().image(
+                    texture.id(),
+                    egui::Rect::from_center_size(rect.center(), fit),
+                    uv,
+                    egui::Color32::WHITE,
+                );
+            }
+            entry => {
+                match entry {
+                    Some((None, shown)) => *shown = self.frame,
+                    _ => self.missing.push(i),
+                }
+                ui.painter().rect_filled(rect, 2.0, visuals.faint_bg_color);
+                let extension = item
+                    .path
+                    .extension()
+                    .map(|e| e.to_string_lossy().to_uppercase())
+                    .unwrap_or_default();
+                ui.painter().text(
+                    rect.center(),
+                    egui::Align2::CENTER_CENTER,
+                    extension,
+                    egui::FontId::proportional(16.0),
+                    visuals.weak_text_color(),
+                );
+            }
+        }
+        if self.selected == Some(i) {
+            ui.painter().rect_stroke(
+                rect,
+                2.0,
+                visuals.selection.stroke,
+                egui::StrokeKind::Inside,
+            );
+        } else if item.error.is_some() || self.failed.contains(&i) {
+            let stroke = egui::Stroke::new(2.0, visuals.error_fg_color);
+            ui.painter()
+                .rect_stroke(rect, 2.0, stroke, egui::StrokeKind::Inside);
+        }
+        response.on_hover_text(item.path.display().to_string())
+    }
+
+    fn matches(&self, i: usize, item: &GalleryItem) -> bool {
+        let filter = &self.filter;
+        filter.album.as_ref().is_none_or(|a| *a == item.album)
+            && filter.year.is_none_or(|y| item.year == Some(y))
+            && filter
+                .person
+                .as_ref()
+                .is_none_or(|p| item.people.contains(p))
+            && (!filter.has_gps || item.has_gps)
+            && (!filter.has_errors || item.error.is_some() || self.failed.contains(&i))
+    }
+
+    /// Drop the thumbnails that weren't shown for the longest time, they are read from the disk cache again when they
+    /// are scrolled to.
+    fn evict_textures(&mut self) {
+        if self.textures.len() <= MAX_TEXTURES {
+            return;
+        }
+        let mut shown: Vec<_> = self
+            .textures
+            .iter()
+            .map(|(i, (_, frame))| (*frame, *i))
+            .collect();
+        shown.sort_unstable();
+        for (_, i) in &shown[..shown.len() - MAX_TEXTURES / 2] {
+            self.textures.remove(i);
+        }
+    }
+}
+
+/// A drop-down to restrict the gallery to one of `values`.
+fn combo_box<T: Clone + PartialEq + ToString>(
+    label: &str,
+    selected: &mut Option<T>,
+    values: &BTreeSet<T>,
+    ui: &mut egui::Ui,
+) {
+    let text = selected
+        .as_ref()
+        .map_or_else(|| "Any".to_owned(), ToString::to_string);
+    egui::ComboBox::from_label(label)
+        .selected_text(text)
+        .show_ui(ui, |ui| {
+            ui.selectable_value(selected, None, "Any");
+            for value in values {
+                ui.selectable_value(selected, Some(value.clone()), value.to_string());
+            }
+        });
+}
+
+fn filter_values(items: &[GalleryItem]) -> FilterValues {
+    let mut values = FilterValues::default();
+    for item in items {
+        values.albums.insert(item.album.clone());
+        values.years.extend(item.year);
+        values.people.extend(item.people.iter().cloned());
+    }
+    values
+}
+
+/// Items with a file that an error of a run is about. Errors are reported with the path of the archive or directory
+/// in front, so they are matched by their end.
+fn failed_items(items: &[GalleryItem], errors: &ErrorLog) -> HashSet<usize> {
+    let mut by_path = HashMap::new();
+    for (i, item) in items.iter().enumerate() {
+        for p in item.group.files() {
+            by_path.insert(p.as_path(), i);
+        }
+    }
+    let mut failed = HashSet::new();
+    for err in errors.groups().iter().flat_map(|g| &g.errors) {
+        let mut suffix = err.path();
+        loop {
+            if let Some(i) = by_path.get(suffix) {
+                failed.insert(*i);
+            }
+            let Some(rest) = strip_first_component(suffix) else {
+                break;
+            };
+            suffix = rest;
+        }
+    }
+    failed
+}
+
+fn strip_first_component(path: &Path) -> Option<&Path> {
+    let mut components = path.components();
+    components.next()?;
+    let rest = components.as_path();
+    (!rest.as_os_str().is_empty()).then_some(rest)
+}
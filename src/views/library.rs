@@ -0,0 +1,97 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, PoisonError, mpsc},
+};
+
+use crate::services::{ManualLinks, RunControl, TakeoutLibrary};
+use eframe::egui;
+
+use super::utils::{Receiver, spawn_worker};
+
+/// The picked Takeout, opened once on a separate thread and shared by every view that reads it, so archives are only
+/// read or extracted once. Workers of the views lock it for each file they read. Dropping it stops the extraction.
+pub struct SharedLibrary {
+    source: PathBuf,
+    worker: Option<Receiver<io::Result<TakeoutLibrary>>>,
+    library: Option<Result<Arc<Mutex<TakeoutLibrary>>, String>>,
+    control: RunControl,
+}
+impl SharedLibrary {
+    /// Start opening `source` with the links that were saved for it.
+    pub fn open(ctx: &egui::Context, source: PathBuf) -> Self {
+        let control = RunControl::default();
+        let worker = {
+            let source = source.clone();
+            let control = control.clone();
+            spawn_worker(ctx, move |tx| {
+                let library = ManualLinks::load(&source)
+                    .and_then(|links| TakeoutLibrary::open_with(&source, &links, &control));
+                let _ = tx.send(library);
+            })
+        };
+        Self {
+            source,
+            worker: Some(worker),
+            library: None,
+            control,
+        }
+    }
+
+    pub fn source(&self) -> &Path {
+        &self.source
+    }
+
+    /// The library once it is open, or why it couldn't be opened. `None` while it is still being opened.
+    pub fn get(&mut self) -> Option<Result<Arc<Mutex<TakeoutLibrary>>, String>> {
+        if let Some(worker) = self.worker.take() {
+            match worker.rx.try_recv() {
+                Ok(library) => {
+                    let library = worker.join().map_err(io::Error::other).and(library);
+                    self.library = Some(
+                        library
+                            .map(|l| Arc::new(Mutex::new(l)))
+                            .map_err(|err| err.to_string()),
+                    );
+                }
+                Err(mpsc::TryRecvError::Empty) => self.worker = Some(worker),
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.library = Some(Err(worker.join_disconnected()));
+                }
+            }
+        }
+        self.library.clone()
+    }
+
+    /// Like [`Self::get`], but shows that the Takeout is still being read or why it couldn't be read instead.
+    pub fn show(&mut self, ui: &mut egui::Ui) -> Option<Arc<Mutex<TakeoutLibrary>>> {
+        match self.get() {
+            None => {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label("Reading Takeout...");
+                });
+                None
+            }
+            Some(Err(err)) => {
+                ui.colored_label(
+                    ui.visuals().error_fg_color,
+                    format!("Could not read Takeout: {}", err),
+                );
+                None
+            }
+            Some(Ok(library)) => Some(library),
+        }
+    }
+}
+impl Drop for SharedLibrary {
+    fn drop(&mut self) {
+        self.control.cancel();
+    }
+}
+
+/// Lock the library to read from it. A worker that panicked while reading didn't leave it half changed, so it can still
+/// be used.
+pub fn lock(library: &Mutex<TakeoutLibrary>) -> MutexGuard<'_, TakeoutLibrary> {
+    library.lock().unwrap_or_else(PoisonError::into_inner)
+}
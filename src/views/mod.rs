@@ -1,45 +1,69 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    cell::RefCell,
+    rc::{Rc, Weak},
+};
 
 use eframe::egui;
 
 mod apply_metadata;
+mod diff_table;
+mod error_list;
 mod file_picker;
+mod gallery;
+mod library;
+mod pairing;
+mod preview;
+mod settings;
 mod success;
+mod timeline;
 pub mod utils;
 
 use apply_metadata::ApplyMetadata;
 use file_picker::FilePicker;
+use pairing::Pairing;
+use preview::Preview;
+use settings::Settings;
 use success::Success;
 
+pub use library::SharedLibrary;
+
 use crate::AppState;
 
 /// A double linked list to allow traversing to prev and next views easily. Also allows inserting new ones with O(1)
-/// runtime.
+/// runtime. Views only hold weak references to their previous view, so the list doesn't keep itself alive.
 pub struct View {
-    pub prev: Option<Rc<RefCell<View>>>,
+    pub prev: Option<Weak<RefCell<View>>>,
     pub next: Option<Rc<RefCell<View>>>,
     pub item: Box<dyn Viewable>,
+    /// Creates the state of the view, again whenever it is left so nothing is left over from a previous visit and its
+    /// workers stop
+    new_item: fn() -> Box<dyn Viewable>,
 }
-impl Default for View {
-    fn default() -> Self {
+impl View {
+    /// Build all views and return the first one.
+    pub fn wizard() -> Rc<RefCell<View>> {
         // list of view in order from first to last
-        let views: [Box<dyn Viewable>; 3] = [
-            Box::new(FilePicker::default()),
-            Box::new(ApplyMetadata::default()),
-            Box::new(Success),
+        let views: [fn() -> Box<dyn Viewable>; 6] = [
+            || Box::new(FilePicker::default()),
+            || Box::new(Settings::default()),
+            || Box::new(Pairing::default()),
+            || Box::new(Preview::default()),
+            || Box::new(ApplyMetadata::default()),
+            || Box::new(Success::default()),
         ];
 
         // build views as type `View`
-        let mut root = None;
-        for view in views.into_iter().rev() {
-            let mut v = Self {
+        let mut root: Option<Rc<RefCell<View>>> = None;
+        for new_item in views.into_iter().rev() {
+            let v = Rc::new(RefCell::new(Self {
                 prev: None,
-                next: None,
-                item: view,
-            };
+                next: root.clone(),
+                item: new_item(),
+                new_item,
+            }));
 
-            if let Some(root) = root {
-                v.next = Some(Rc::new(RefCell::new(root)));
+            if let Some(next) = root {
+                next.borrow_mut().prev = Some(Rc::downgrade(&v));
             }
 
             root = Some(v);
@@ -47,6 +71,11 @@ impl Default for View {
 
         root.expect("No views were created! Please report unexpected this bug.")
     }
+
+    /// Drop the state of the view once it is left, so its workers stop and it starts over when it is shown again.
+    pub fn leave(&mut self) {
+        self.item = (self.new_item)();
+    }
 }
 
 pub trait Viewable {
@@ -56,10 +85,43 @@ pub trait Viewable {
         ctx: &egui::Context,
         ui: &mut egui::Ui,
     ) -> Option<ViewNavigation>;
+
+    /// Whether going back passes over this view instead of showing it again, e.g. because showing it starts a run.
+    fn skip_on_back(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Clone)]
 pub enum ViewNavigation {
     Prev,
     Next,
+    /// Forget everything and start over with the first view
+    Restart,
+    /// Show a view on top of the current one, which is not part of the wizard. Any navigation from it closes it again.
+    Open(fn() -> Box<dyn Viewable>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wizard_links_views_both_ways() {
+        let first = View::wizard();
+        let mut view = first.clone();
+        let mut count = 1;
+        loop {
+            let Some(next) = view.borrow().next.clone() else {
+                break;
+            };
+            let prev = next.borrow().prev.as_ref().and_then(Weak::upgrade).unwrap();
+            assert!(Rc::ptr_eq(&prev, &view));
+            view = next;
+            count += 1;
+        }
+
+        assert_eq!(count, 6);
+        assert!(first.borrow().prev.is_none());
+    }
 }
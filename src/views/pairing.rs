@@ -0,0 +1,359 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, mpsc},
+};
+
+use crate::{
+    AppState,
+    services::{ManualLinks, OrphanImage, OrphanJson, TakeoutLibrary},
+};
+use eframe::egui;
+
+use super::library::lock;
+use super::utils::{Receiver, load_thumbnail, spawn_worker};
+use super::{ViewNavigation, Viewable};
+
+/// Size of the square thumbnails are scaled into
+const THUMBNAIL_SIZE: u32 = 128;
+/// Size thumbnails are shown with in the list of images
+const LIST_THUMBNAIL_SIZE: f32 = 48.0;
+/// How many json files are suggested for an image
+const SUGGESTIONS: usize = 10;
+
+enum Message {
+    Json(OrphanJson),
+    Image(OrphanImage),
+}
+
+/// The index of a json file that is being dragged onto an image.
+struct JsonDrag(usize);
+
+/// The json files suggested for the selected image. Ranking every json file is too slow to do every frame, so they are
+/// only ranked again once another image is selected or the json files that aren't linked yet change.
+struct Suggestions {
+    image: usize,
+    /// Indices of the json files that were ranked
+    candidates: Vec<usize>,
+    /// Index of a json file and its score, most likely first
+    ranked: Vec<(usize, f64)>,
+}
+
+/// Lets the user pair images without json file and json files without image by hand. Links are saved right away and
+/// used by every run on the Takeout.
+#[derive(Default)]
+pub struct Pairing {
+    worker: Option<Receiver<Message>>,
+    /// Reading the Takeout is done
+    loaded: bool,
+    error: Option<String>,
+    links: Option<ManualLinks>,
+    images: Vec<OrphanImage>,
+    jsons: Vec<OrphanJson>,
+    textures: HashMap<PathBuf, egui::TextureHandle>,
+    selected: Option<usize>,
+    suggestions: Option<Suggestions>,
+}
+impl Viewable for Pairing {
+    fn show(
+        &mut self,
+        app: &mut AppState,
+        ctx: &egui::Context,
+        ui: &mut egui::Ui,
+    ) -> Option<ViewNavigation> {
+        let source = app
+            .picked_path
+            .clone()
+            .expect("Did not save file path correctly. Please report this unexpected bug.");
+        if self.links.is_none() && self.error.is_none() {
+            match ManualLinks::load(&source) {
+                Ok(links) => self.links = Some(links),
+                Err(err) => self.error = Some(format!("Could not read saved links: {}", err)),
+            }
+        }
+        self.receive(ctx);
+
+        ui.heading("Pair files by hand");
+        ui.label(
+            "These images have no json file and these json files have no image, because their names don't match. \
+             Drag a json file onto an image or pick one of the suggestions to link them.",
+        );
+        let navigation = ui
+            .horizontal(|ui| {
+                if ui.button("Back").clicked() {
+                    return Some(ViewNavigation::Prev);
+                }
+                ui.button("Next").clicked().then_some(ViewNavigation::Next)
+            })
+            .inner;
+        if let Some(err) = self.error.as_ref() {
+            ui.colored_label(ui.visuals().error_fg_color, err);
+        }
+        let Some(library) = app.library(ctx).show(ui) else {
+            return navigation;
+        };
+        let Some(mut links) = self.links.take() else {
+            return navigation;
+        };
+        if self.worker.is_none() && !self.loaded {
+            self.spawn_worker(ctx, library.clone(), links.clone());
+        }
+        if !self.loaded {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label("Reading unpaired files...");
+            });
+        } else if self.images.is_empty() && self.jsons.is_empty() {
+            ui.label("All files are paired.");
+        }
+
+        let before = links.clone();
+        self.show_linked(&mut links, ui);
+        self.show_images(&mut links, ui);
+        self.show_selected(&mut links, ui);
+        if links != before {
+            // the other views see the new pairs right away
+            lock(&library).relink(&links);
+            if let Err(err) = links.save(&source) {
+                self.error = Some(format!("Could not save links: {}", err));
+            }
+        }
+        self.links = Some(links);
+        navigation
+    }
+}
+impl Pairing {
+    /// Read the files that aren't paired by their names on a separate thread. Json files are sent first, since
+    /// decoding the thumbnails of the images takes a while.
+    fn spawn_worker(
+        &mut self,
+        ctx: &egui::Context,
+        library: Arc<Mutex<TakeoutLibrary>>,
+        links: ManualLinks,
+    ) {
+        self.worker = Some(spawn_worker(ctx, move |tx| {
+            let summary = {
+                let mut library = lock(&library);
+                library.relink(&ManualLinks::default());
+                let summary = library.summary();
+                library.relink(&links);
+                summary
+            };
+            for json in &summary.json_without_image {
+                if tx
+                    .send(Message::Json(lock(&library).orphan_json(json)))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            for img in &summary.images_without_json {
+                let image = lock(&library).orphan_image(img, THUMBNAIL_SIZE);
+                if tx.send(Message::Image(image)).is_err() {
+                    return;
+                }
+            }
+        }));
+    }
+
+    fn receive(&mut self, ctx: &egui::Context) {
+        let Some(worker) = self.worker.as_ref() else {
+            return;
+        };
+        loop {
+            match worker.rx.try_recv() {
+                Ok(Message::Json(json)) => self.jsons.push(json),
+                Ok(Message::Image(image)) => {
+                    if let Some(thumbnail) = image.thumbnail.as_ref() {
+                        let texture =
+                            load_thumbnail(ctx, &image.path.display().to_string(), thumbnail);
+                        self.textures.insert(image.path.clone(), texture);
+                    }
+                    self.images.push(image);
+                }
+                Err(mpsc::TryRecvError::Empty) => return,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    if let Err(err) = self.worker.take().unwrap().join() {
+                        self.error = Some(format!("Could not read Takeout: {}", err));
+                    }
+                    self.loaded = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// The links that were made so far, they can be undone.
+    fn show_linked(&self, links: &mut ManualLinks, ui: &mut egui::Ui) {
+        if links.is_empty() {
+            return;
+        }
+        let mut unlink = None;
+        egui::CollapsingHeader::new(format!("Linked ({})", links.iter().count()))
+            .id_salt("pairing_linked")
+            .show(ui, |ui| {
+                for (img, json) in links.iter() {
+                    ui.horizontal(|ui| {
+                        if ui.small_button("Unlink").clicked() {
+                            unlink = Some(img.to_owned());
+                        }
+                        ui.label(format!("{} ← {}", img.display(), json.display()));
+                    });
+                }
+            });
+        if let Some(img) = unlink {
+            links.unlink(&img);
+        }
+    }
+
+    /// Images that aren't linked yet on the left. Clicking one shows suggestions for it, dropping a json file onto it
+    /// links them.
+    fn show_images(&mut self, links: &mut ManualLinks, ui: &mut egui::Ui) {
+        let mut clicked = None;
+        egui::SidePanel::left("pairing_images")
+            .resizable(true)
+            .default_width(320.0)
+            .show_inside(ui, |ui| {
+                ui.strong("Images without json file");
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for (i, image) in self.images.iter().enumerate() {
+                        if links.json_for(&image.path).is_some() {
+                            continue;
+                        }
+                        let row = ui.horizontal(|ui| {
+                            self.show_thumbnail(&image.path, LIST_THUMBNAIL_SIZE, ui);
+                            let name = file_name(&image.path);
+                            ui.selectable_label(self.selected == Some(i), name)
+                                .on_hover_text(image.path.display().to_string())
+                                .clicked()
+                        });
+                        if row.inner {
+                            clicked = Some(i);
+                        }
+                        if row.response.dnd_hover_payload::<JsonDrag>().is_some() {
+                            ui.painter().rect_stroke(
+                                row.response.rect,
+                                2.0,
+                                ui.visuals().selection.stroke,
+                                egui::StrokeKind::Inside,
+                            );
+                        }
+                        if let Some(drag) = row.response.dnd_release_payload::<JsonDrag>() {
+                            links.link(image.path.clone(), self.jsons[drag.0].path.clone());
+                        }
+                    }
+                });
+            });
+        if clicked.is_some() {
+            self.selected = clicked;
+        }
+    }
+
+    /// The selected image with the json files most likely to belong to it, followed by all json files that aren't
+    /// linked yet.
+    fn show_selected(&mut self, links: &mut ManualLinks, ui: &mut egui::Ui) {
+        let unlinked: Vec<_> = self
+            .jsons
+            .iter()
+            .enumerate()
+            .filter(|(_, json)| !links.is_linked(&json.path))
+            .map(|(i, _)| i)
+            .collect();
+        let selected = self
+            .selected
+            .filter(|i| links.json_for(&self.images[*i].path).is_none());
+        if let Some(image) = selected {
+            self.update_suggestions(image, unlinked.clone());
+        }
+        let mut link = None;
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            if let Some(image) = selected.map(|i| &self.images[i]) {
+                ui.heading(image.path.display().to_string());
+                self.show_thumbnail(&image.path, THUMBNAIL_SIZE as f32, ui);
+                match image.taken {
+                    Some(taken) => ui.label(format!("Taken {}", taken)),
+                    None => ui.weak("No date in the file"),
+                };
+
+                ui.strong("Suggestions");
+                let ranked = self.suggestions.iter().flat_map(|s| &s.ranked);
+                for &(index, score) in ranked.take(SUGGESTIONS) {
+                    let json = &self.jsons[index];
+                    ui.horizontal(|ui| {
+                        if ui.button("Link").clicked() {
+                            link = Some((image.path.clone(), json.path.clone()));
+                        }
+                        show_json(index, json, ui);
+                        ui.weak(format!("{:.0}% match", score / 2.0 * 100.0));
+                    });
+                }
+                ui.separator();
+            } else {
+                ui.label("Select an image to see which json files likely belong to it.");
+            }
+
+            ui.strong("Json files without image");
+            for &index in &unlinked {
+                show_json(index, &self.jsons[index], ui);
+            }
+        });
+        if let Some((img, json)) = link {
+            links.link(img, json);
+        }
+    }
+
+    fn update_suggestions(&mut self, image: usize, candidates: Vec<usize>) {
+        if self
+            .suggestions
+            .as_ref()
+            .is_some_and(|s| s.image == image && s.candidates == candidates)
+        {
+            return;
+        }
+        let ranked = self.images[image]
+            .suggest(candidates.iter().map(|&i| &self.jsons[i]))
+            .into_iter()
+            .map(|(i, score)| (candidates[i], score))
+            .collect();
+        self.suggestions = Some(Suggestions {
+            image,
+            candidates,
+            ranked,
+        });
+    }
+
+    fn show_thumbnail(&self, path: &Path, size: f32, ui: &mut egui::Ui) {
+        match self.textures.get(path) {
+            Some(texture) => {
+                let scale = size / texture.size_vec2().max_elem();
+                ui.image((texture.id(), texture.size_vec2() * scale));
+            }
+            None => {
+                ui.allocate_exact_size(egui::vec2(size, size), egui::Sense::hover());
+            }
+        }
+    }
+}
+
+/// A json file with its title and date, which can be dragged onto an image.
+fn show_json(index: usize, json: &OrphanJson, ui: &mut egui::Ui) {
+    let id = egui::Id::new(("pairing_json", index));
+    ui.dnd_drag_source(id, JsonDrag(index), |ui| {
+        ui.horizontal(|ui| {
+            ui.label(file_name(&json.path))
+                .on_hover_text(json.path.display().to_string());
+            if let Some(title) = json.title.as_ref() {
+                ui.weak(title);
+            }
+            if let Some(taken) = json.taken {
+                ui.weak(taken.format("%Y-%m-%d %H:%M").to_string());
+            }
+        });
+    });
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
@@ -0,0 +1,324 @@
+use std::sync::{Arc, Mutex, mpsc};
+
+use crate::{
+    AppState,
+    services::{GroupPreview, MediaGroup, MetadataOptions, TakeoutExif, TakeoutLibrary},
+};
+use eframe::egui;
+
+use super::library::lock;
+use super::utils::{Receiver, load_thumbnail, spawn_worker};
+use super::{ViewNavigation, Viewable};
+
+/// Size of the square thumbnails are scaled into
+const THUMBNAIL_SIZE: u32 = 256;
+
+enum Message {
+    Groups(Vec<MediaGroup>),
+    Preview(usize, Box<GroupPreview>),
+}
+
+/// Lets the user browse the media groups of the Takeout and compare the json metadata with what the files contain now
+/// and what a run would write, before starting the run.
+#[derive(Default)]
+pub struct Preview {
+    worker: Option<Receiver<Message>>,
+    /// Asks the worker for the preview of a group
+    requests: Option<mpsc::Sender<(usize, MetadataOptions)>>,
+    /// The groups of the Takeout, or why the worker stopped
+    groups: Option<Result<Vec<MediaGroup>, String>>,
+    filter: String,
+    selected: Option<usize>,
+    details: Option<GroupDetails>,
+}
+impl Viewable for Preview {
+    fn show(
+        &mut self,
+        app: &mut AppState,
+        ctx: &egui::Context,
+        ui: &mut egui::Ui,
+    ) -> Option<ViewNavigation> {
+        self.receive(ctx);
+
+        let navigation = ui
+            .horizontal(|ui| {
+                if ui.button("Back").clicked() {
+                    return Some(ViewNavigation::Prev);
+                }
+                let label = if app.dry_run {
+                    "Start dry run"
+                } else {
+                    "Start"
+                };
+                ui.button(label).clicked().then_some(ViewNavigation::Next)
+            })
+            .inner;
+
+        let Some(library) = app.library(ctx).show(ui) else {
+            return navigation;
+        };
+        if self.worker.is_none() && self.groups.is_none() {
+            self.spawn_worker(ctx, library);
+        }
+        match self.groups.as_ref() {
+            None => {
+                ui.spinner();
+            }
+            Some(Err(err)) => {
+                ui.colored_label(
+                    ui.visuals().error_fg_color,
+                    format!("Could not read Takeout: {}", err),
+                );
+            }
+            Some(Ok(_)) => {
+                self.show_group_list(app, ui);
+                self.show_preview(ui);
+            }
+        }
+        navigation
+    }
+}
+impl Preview {
+    /// Answer requests for previews on a separate thread until the view is left.
+    fn spawn_worker(&mut self, ctx: &egui::Context, library: Arc<Mutex<TakeoutLibrary>>) {
+        let (request_tx, request_rx) = mpsc::channel::<(usize, MetadataOptions)>();
+        self.worker = Some(spawn_worker(ctx, move |tx| {
+            let groups = lock(&library).media_groups().to_vec();
+            if tx.send(Message::Groups(groups.clone())).is_err() {
+                return;
+            }
+            for (index, options) in request_rx {
+                let preview = lock(&library).preview(&groups[index], &options, THUMBNAIL_SIZE);
+                if tx.send(Message::Preview(index, Box::new(preview))).is_err() {
+                    return;
+                }
+            }
+        }));
+        self.requests = Some(request_tx);
+    }
+
+    fn receive(&mut self, ctx: &egui::Context) {
+        let Some(worker) = self.worker.as_ref() else {
+            return;
+        };
+        loop {
+            let message = match worker.rx.try_recv() {
+                Ok(message) => message,
+                Err(mpsc::TryRecvError::Empty) => return,
+                // the worker keeps running while the view is shown, unless it crashed
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.groups = Some(Err(self.worker.take().unwrap().join_disconnected()));
+                    return;
+                }
+            };
+            match message {
+                Message::Groups(groups) => self.groups = Some(Ok(groups)),
+                // answers for groups that were selected before the current one are outdated
+                Message::Preview(index, preview) if self.selected == Some(index) => {
+                    self.details = Some(GroupDetails::new(ctx, preview));
+                }
+                Message::Preview(..) => {}
+            }
+        }
+    }
+
+    /// A list of all groups on the left, clicking one requests its preview.
+    fn show_group_list(&mut self, app: &AppState, ui: &mut egui::Ui) {
+        let Some(Ok(groups)) = self.groups.as_ref() else {
+            return;
+        };
+        let mut clicked = None;
+        egui::SidePanel::left("preview_groups")
+            .resizable(true)
+            .default_width(300.0)
+            .show_inside(ui, |ui| {
+                ui.text_edit_singleline(&mut self.filter)
+                    .on_hover_text("Only show files whose path contains this");
+                let filter = self.filter.to_lowercase();
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for (i, group) in groups.iter().enumerate() {
+                        let name = group_name(group);
+                        if !name.to_lowercase().contains(&filter) {
+                            continue;
+                        }
+                        if ui
+                            .selectable_label(self.selected == Some(i), name)
+                            .clicked()
+                        {
+                            clicked = Some(i);
+                        }
+                    }
+                });
+            });
+        if let Some(i) = clicked
+            && self.selected != Some(i)
+            && let Some(requests) = self.requests.as_ref()
+        {
+            self.selected = Some(i);
+            self.details = None;
+            let _ = requests.send((i, app.options.metadata.clone()));
+        }
+    }
+
+    fn show_preview(&self, ui: &mut egui::Ui) {
+        if self.selected.is_none() {
+            ui.label("Select a file to see its metadata.");
+            return;
+        }
+        match self.details.as_ref() {
+            Some(details) => details.show(ui),
+            None => {
+                ui.spinner();
+            }
+        }
+    }
+}
+
+/// The metadata of a group as it is now and as a run would leave it, with thumbnails of its images.
+pub struct GroupDetails {
+    preview: Box<GroupPreview>,
+    textures: Vec<egui::TextureHandle>,
+}
+impl GroupDetails {
+    pub fn new(ctx: &egui::Context, preview: Box<GroupPreview>) -> Self {
+        let textures = preview
+            .images
+            .iter()
+            .filter_map(|image| {
+                let thumbnail = image.thumbnail.as_ref()?;
+                Some(load_thumbnail(
+                    ctx,
+                    &image.path.display().to_string(),
+                    thumbnail,
+                ))
+            })
+            .collect();
+        Self { preview, textures }
+    }
+
+    pub fn show(&self, ui: &mut egui::Ui) {
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            ui.heading("Takeout json");
+            match self.preview.metadata.as_ref() {
+                None => {
+                    ui.label("No json file belongs to these files, they are left untouched.");
+                }
+                Some(Err(err)) => {
+                    ui.colored_label(ui.visuals().error_fg_color, err.to_string());
+                }
+                Some(Ok(exif)) => show_takeout_exif(exif, ui),
+            }
+
+            for (i, image) in self.preview.images.iter().enumerate() {
+                ui.separator();
+                ui.heading(image.path.display().to_string());
+                if let Some(texture) = self
+                    .textures
+                    .iter()
+                    .find(|t| t.name() == image.path.display().to_string())
+                {
+                    ui.image((texture.id(), texture.size_vec2()));
+                } else {
+                    ui.weak("No preview available for this file type");
+                }
+                if let Some(err) = image.error.as_ref() {
+                    ui.colored_label(ui.visuals().error_fg_color, err);
+                    continue;
+                }
+
+                if image.changes.is_empty() {
+                    ui.label("Nothing would be written to this file.");
+                } else {
+                    ui.label(
+                        "Tags that would change are highlighted, the others keep their value.",
+                    );
+                    egui::Grid::new(("preview_changes", i))
+                        .striped(true)
+                        .show(ui, |ui| {
+                            ui.strong("Tag");
+                            ui.strong("Current");
+                            ui.strong("Proposed");
+                            ui.end_row();
+                            for change in &image.changes {
+                                ui.label(&change.tag);
+                                ui.label(change.before.as_deref().unwrap_or("—"));
+                                if change.is_changed() {
+                                    ui.strong(&change.after);
+                                } else {
+                                    ui.label(&change.after);
+                                }
+                                ui.end_row();
+                            }
+                        });
+                }
+
+                egui::CollapsingHeader::new(format!("All current tags ({})", image.tags.len()))
+                    .id_salt(("preview_tags", i))
+                    .show(ui, |ui| {
+                        egui::Grid::new(("preview_tags_grid", i))
+                            .striped(true)
+                            .show(ui, |ui| {
+                                for tag in &image.tags {
+                                    ui.label(&tag.name);
+                                    ui.label(&tag.value);
+                                    ui.end_row();
+                                }
+                            });
+                    });
+                if let Some(xmp) = image.xmp.as_ref() {
+                    egui::CollapsingHeader::new("XMP")
+                        .id_salt(("preview_xmp", i))
+                        .show(ui, |ui| {
+                            ui.monospace(xmp);
+                        });
+                }
+            }
+        });
+    }
+}
+
+/// The values of the json file that a run can write.
+fn show_takeout_exif(exif: &TakeoutExif, ui: &mut egui::Ui) {
+    let people = exif.people().collect::<Vec<_>>().join(", ");
+    let geo = exif.geo_data().filter(|geo| geo.is_known()).map(|geo| {
+        format!(
+            "{}, {}",
+            geo.latitude.unwrap_or_default(),
+            geo.longitude.unwrap_or_default()
+        )
+    });
+    let rows = [
+        ("Title", exif.title().map(str::to_owned)),
+        ("Description", exif.description().map(str::to_owned)),
+        (
+            "Photo taken",
+            exif.photo_taken_time().map(|t| t.to_rfc3339()),
+        ),
+        ("Uploaded", exif.creation_time().map(|t| t.to_rfc3339())),
+        ("Location", geo),
+        ("People", Some(people).filter(|p| !p.is_empty())),
+        (
+            "Favorite",
+            Some(if exif.is_favorite() { "Yes" } else { "No" }.to_owned()),
+        ),
+    ];
+    egui::Grid::new("preview_json")
+        .striped(true)
+        .show(ui, |ui| {
+            for (name, value) in rows {
+                ui.label(name);
+                ui.label(value.as_deref().unwrap_or("—"));
+                ui.end_row();
+            }
+        });
+}
+
+/// The path of the image of the group, or of its json file if it has none.
+fn group_name(group: &MediaGroup) -> String {
+    [&group.img, &group.img_edited, &group.json]
+        .into_iter()
+        .flatten()
+        .next()
+        .map(|p| p.display().to_string())
+        .unwrap_or_default()
+}
@@ -0,0 +1,281 @@
+use std::path::PathBuf;
+
+use crate::AppState;
+use crate::services::{
+    ArchiveFormat, ArchiveOptions, DateSource, EditedMode, LinkMode, MetadataOptions, OutputMode,
+    Parallelism, RunOptions, TimeZoneMode,
+};
+use eframe::egui;
+
+use super::gallery::Gallery;
+use super::timeline::TimelineView;
+use super::utils::{Receiver, spawn_dialog};
+use super::{ViewNavigation, Viewable};
+
+/// Lets the user choose what is written and where before a run starts.
+#[derive(Default)]
+pub struct Settings {
+    output_receiver: Option<Receiver<Option<PathBuf>>>,
+    archive_receiver: Option<Receiver<Option<PathBuf>>>,
+    /// Why the last dialog failed
+    dialog_error: Option<String>,
+}
+impl Viewable for Settings {
+    fn show(
+        &mut self,
+        app: &mut AppState,
+        _ctx: &egui::Context,
+        ui: &mut egui::Ui,
+    ) -> Option<ViewNavigation> {
+        egui::ScrollArea::vertical()
+            .show(ui, |ui| {
+                if let Some(path) = app.picked_path.as_ref() {
+                    ui.label(format!("Takeout: {}", path.display()));
+                }
+
+                ui.heading("Metadata");
+                metadata_settings(&mut app.options.metadata, ui);
+
+                ui.heading("Output");
+                self.output_settings(app, ui);
+
+                ui.heading("Run");
+                run_settings(&mut app.options, ui);
+                ui.checkbox(&mut app.dry_run, "Dry run: only show what would change");
+
+                ui.horizontal(|ui| {
+                    if ui.button("Back").clicked() {
+                        return Some(ViewNavigation::Prev);
+                    }
+                    if ui
+                        .button("Next")
+                        .on_hover_text("Preview the metadata of the files before starting")
+                        .clicked()
+                    {
+                        return Some(ViewNavigation::Next);
+                    }
+                    ui.separator();
+                    if ui
+                        .button("Gallery")
+                        .on_hover_text("Browse the images of the Takeout with their metadata")
+                        .clicked()
+                    {
+                        return Some(ViewNavigation::Open(|| Box::new(Gallery::default())));
+                    }
+                    ui.button("Timeline")
+                        .on_hover_text(
+                            "See how many images were taken in each month with these settings",
+                        )
+                        .clicked()
+                        .then_some(ViewNavigation::Open(|| Box::new(TimelineView::default())))
+                })
+                .inner
+            })
+            .inner
+    }
+}
+impl Settings {
+    /// Lets the user choose between modifying the extracted files, writing results into a separate folder or
+    /// packaging them into a new archive.
+    fn output_settings(&mut self, app: &mut AppState, ui: &mut egui::Ui) {
+        match Receiver::poll(&mut self.output_receiver) {
+            Some(Ok(Some(dir))) => {
+                let link = match &app.options.output {
+                    OutputMode::Tree { link, .. } => *link,
+                    _ => LinkMode::default(),
+                };
+                app.options.output = OutputMode::Tree { dir, link };
+            }
+            Some(Err(err)) => self.dialog_error = Some(err),
+            Some(Ok(None)) | None => {}
+        }
+        match Receiver::poll(&mut self.archive_receiver) {
+            Some(Ok(Some(path))) => {
+                let mut options = ArchiveOptions::new(path);
+                if ArchiveFormat::from_path(&options.path).is_none() {
+                    options.set_format(ArchiveFormat::default());
+                }
+                app.options.output = OutputMode::Archive(options);
+            }
+            Some(Err(err)) => self.dialog_error = Some(err),
+            Some(Ok(None)) | None => {}
+        }
+
+        ui.group(|ui| {
+            let mut write_in_place = false;
+            match &mut app.options.output {
+                OutputMode::InPlace => {
+                    ui.label(
+                        "Metadata will be written into the extracted files or the picked folder.",
+                    );
+                }
+                OutputMode::Tree { dir, link } => {
+                    ui.label(format!("Output folder: {}", dir.display()));
+                    let source_is_dir = app.picked_path.as_ref().is_some_and(|p| p.is_dir());
+                    ui.add_enabled_ui(source_is_dir, |ui| {
+                        egui::ComboBox::from_label("Unchanged files")
+                            .selected_text(link.label())
+                            .show_ui(ui, |ui| {
+                                for mode in LinkMode::ALL {
+                                    ui.selectable_value(link, mode, mode.label());
+                                }
+                            });
+                    })
+                    .response
+                    .on_disabled_hover_text(
+                        "Files from an archive are always written as new files, there is nothing to link to.",
+                    );
+                    write_in_place = ui.button("Write in place instead").clicked();
+                }
+                OutputMode::Archive(options) => {
+                    archive_settings(options, ui);
+                    write_in_place = ui.button("Write in place instead").clicked();
+                }
+            }
+            if write_in_place {
+                app.options.output = OutputMode::InPlace;
+            }
+
+            ui.horizontal(|ui| {
+                if ui.button("Choose output folder…").clicked() {
+                    self.dialog_error = None;
+                    self.output_receiver = Some(spawn_dialog(ui.ctx(), || {
+                        rfd::FileDialog::new().pick_folder()
+                    }));
+                }
+                if ui.button("Save as archive…").clicked() {
+                    self.dialog_error = None;
+                    self.archive_receiver = Some(spawn_dialog(ui.ctx(), || {
+                        rfd::FileDialog::new()
+                            .add_filter("Archive", &["zip", "tar", "tgz", "gz"])
+                            .save_file()
+                    }));
+                }
+            });
+            if let Some(err) = self.dialog_error.as_ref() {
+                ui.colored_label(ui.visuals().error_fg_color, err);
+            }
+        });
+    }
+}
+
+fn archive_settings(options: &mut ArchiveOptions, ui: &mut egui::Ui) {
+    ui.label(format!("Output archive: {}", options.path.display()));
+
+    let mut format = options.format;
+    egui::ComboBox::from_label("Format")
+        .selected_text(format.extension())
+        .show_ui(ui, |ui| {
+            for f in ArchiveFormat::ALL {
+                ui.selectable_value(&mut format, f, f.extension());
+            }
+        });
+    if format != options.format {
+        options.set_format(format);
+    }
+
+    if options.format != ArchiveFormat::Tar {
+        ui.add(egui::Slider::new(&mut options.compression_level, 0..=9).text("Compression level"));
+    }
+
+    let mut split = options.split_size.is_some();
+    ui.checkbox(&mut split, "Split into parts");
+    if split {
+        // edited in MB, stored in bytes
+        let mut megabytes = options.split_size.map_or(2048, |s| s / 1_000_000);
+        ui.add(
+            egui::DragValue::new(&mut megabytes)
+                .range(1..=u64::MAX / 1_000_000)
+                .suffix(" MB"),
+        );
+        options.split_size = Some(megabytes * 1_000_000);
+    } else {
+        options.split_size = None;
+    }
+
+    ui.checkbox(&mut options.include_json, "Include Takeout json files");
+    ui.checkbox(&mut options.include_manifest, "Include manifest");
+}
+
+fn run_settings(options: &mut RunOptions, ui: &mut egui::Ui) {
+    ui.horizontal(|ui| {
+        let mut workers = options.parallelism.workers;
+        ui.label("Worker threads:");
+        ui.add(egui::DragValue::new(&mut workers).range(1..=256));
+        if workers != options.parallelism.workers {
+            options.parallelism = Parallelism::new(workers);
+        }
+    });
+    ui.checkbox(&mut options.force, "Reprocess files that were already processed")
+        .on_hover_text("By default, files are skipped if a previous run wrote them and their json file didn't change.");
+}
+
+fn metadata_settings(options: &mut MetadataOptions, ui: &mut egui::Ui) {
+    ui.group(|ui| {
+        ui.label("Fields to write:");
+        ui.horizontal_wrapped(|ui| {
+            ui.checkbox(&mut options.dates, "Dates");
+            ui.checkbox(&mut options.gps, "GPS location");
+            ui.checkbox(&mut options.description, "Description");
+            ui.checkbox(&mut options.title, "Title")
+                .on_hover_text("Google Photos uses the file name as title unless it was changed.");
+            ui.checkbox(&mut options.people, "People")
+                .on_hover_text("Names of the people in the photo, written as keywords.");
+            ui.checkbox(&mut options.favorites, "Favorites")
+                .on_hover_text("Favorites get a five star rating.");
+        });
+
+        ui.add_enabled_ui(options.dates, |ui| {
+            egui::ComboBox::from_label("Date")
+                .selected_text(options.date_source.label())
+                .show_ui(ui, |ui| {
+                    for source in DateSource::ALL {
+                        ui.selectable_value(&mut options.date_source, source, source.label());
+                    }
+                });
+
+            // Takeout timestamps are in UTC, the zone a photo was taken in isn't known
+            ui.horizontal(|ui| {
+                ui.label("Time zone:");
+                ui.radio_value(&mut options.time_zone, TimeZoneMode::Utc, "UTC");
+                ui.radio_value(
+                    &mut options.time_zone,
+                    TimeZoneMode::Local,
+                    "This computer's",
+                );
+                let offset = match options.time_zone {
+                    TimeZoneMode::Offset(minutes) => Some(minutes),
+                    _ => None,
+                };
+                if ui.radio(offset.is_some(), "Fixed offset").clicked() && offset.is_none() {
+                    options.time_zone = TimeZoneMode::Offset(0);
+                }
+                if let TimeZoneMode::Offset(minutes) = &mut options.time_zone {
+                    // edited in hours, stored in minutes
+                    let mut hours = *minutes as f64 / 60.0;
+                    ui.add(
+                        egui::DragValue::new(&mut hours)
+                            .range(-12.0..=14.0)
+                            .speed(0.25)
+                            .prefix("UTC ")
+                            .suffix(" h"),
+                    );
+                    *minutes = (hours * 60.0).round() as i32;
+                }
+            });
+        });
+
+        egui::ComboBox::from_label("Edited photos")
+            .selected_text(options.edited.label())
+            .show_ui(ui, |ui| {
+                for mode in EditedMode::ALL {
+                    ui.selectable_value(&mut options.edited, mode, mode.label());
+                }
+            })
+            .response
+            .on_hover_text(
+                "Which versions of photos edited in Google Photos get the metadata. \
+                The other version is left as it is.",
+            );
+    });
+}
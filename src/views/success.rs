@@ -1,20 +1,200 @@
-use crate::AppState;
+use std::{io, path::PathBuf};
+
+use crate::{
+    AppState,
+    services::{self, OutputMode, RunSummary},
+};
 use eframe::egui;
 
+use super::diff_table::DiffTable;
+use super::error_list::ErrorList;
+use super::gallery::Gallery;
+use super::utils::{Receiver, open_path, spawn_dialog, spawn_worker};
 use super::{ViewNavigation, Viewable};
 
-pub struct Success;
+type UndoResult = io::Result<Vec<(PathBuf, io::Error)>>;
+
+/// What happened to the output of a cancelled run
+const CANCELLED_ARCHIVE: &str =
+    "The unfinished archive was deleted, running again starts a new one.";
+const CANCELLED_FILES: &str = "Files that were already written keep their metadata. \
+    Running again on the same files continues where it stopped.";
+
+#[derive(Default)]
+pub struct Success {
+    undo_receiver: Option<Receiver<UndoResult>>,
+    undo_result: Option<UndoResult>,
+    diff_table: DiffTable,
+    error_list: ErrorList,
+    report_receiver: Option<Receiver<Option<PathBuf>>>,
+    /// Where the report was saved to, or why it couldn't be
+    report: Option<io::Result<PathBuf>>,
+}
 impl Viewable for Success {
     fn show(
         &mut self,
         app: &mut AppState,
-        ctx: &egui::Context,
+        _ctx: &egui::Context,
         ui: &mut egui::Ui,
     ) -> Option<ViewNavigation> {
-        ui.centered_and_justified(|ui| {
-            ui.heading("Success!");
-            ui.label("You can close the application now.");
+        if let Some(result) = Receiver::poll(&mut self.undo_receiver) {
+            self.undo_result = Some(result.map_err(io::Error::other).and_then(|undo| undo));
+        }
+
+        match Receiver::poll(&mut self.report_receiver) {
+            Some(Ok(Some(path))) => {
+                if let Some(summary) = app.summary.as_ref() {
+                    self.report = Some(summary.export(&app.errors, &path).map(|_| path));
+                }
+            }
+            Some(Err(err)) => self.report = Some(Err(io::Error::other(err))),
+            Some(Ok(None)) | None => {}
+        }
+
+        if let Some(report) = app.report.as_ref() {
+            ui.heading("Dry run finished");
+            ui.label("No files were modified. This is what a run would change:");
+            let nav = navigation(ui);
+            self.diff_table.show(report, ui);
+            return nav;
+        }
+
+        let mut nav = None;
+        ui.vertical_centered(|ui| {
+            if app.cancelled {
+                ui.heading("Cancelled");
+                ui.label(match app.options.output {
+                    OutputMode::Archive(_) => CANCELLED_ARCHIVE,
+                    _ => CANCELLED_FILES,
+                });
+            } else if app.errors.is_empty() {
+                ui.heading("Success!");
+            } else {
+                ui.heading("Finished with errors");
+            }
+            nav = navigation(ui);
+            if let Some(summary) = app.summary.as_ref() {
+                self.show_summary(summary, app, ui);
+            }
+            self.error_list.show(&app.errors, false, ui);
+
+            // only files that were modified in place can be restored
+            if app.options.output != OutputMode::InPlace {
+                return;
+            }
+            match &self.undo_result {
+                None if self.undo_receiver.is_some() => {
+                    ui.label("Restoring original files...");
+                    ui.spinner();
+                }
+                None => {
+                    if ui.button("Undo changes").clicked() {
+                        let path = app.picked_path.clone().expect(
+                            "Did not save file path correctly. Please report this unexpected bug.",
+                        );
+                        self.undo_receiver = Some(spawn_undo(ui.ctx(), path));
+                    }
+                }
+                Some(Ok(failed)) if failed.is_empty() => {
+                    ui.label("All modified files were restored.");
+                }
+                Some(Ok(failed)) => {
+                    ui.label("These files could not be restored:");
+                    egui::ScrollArea::vertical().show(ui, |ui| {
+                        for (path, err) in failed {
+                            ui.label(format!("{}: {}", path.display(), err));
+                        }
+                    });
+                }
+                Some(Err(err)) => {
+                    ui.label(format!("Undo failed: {}", err));
+                }
+            }
         });
-        None
+        nav
     }
 }
+
+/// Go back to change the settings and run again, look at the results or start over with another Takeout.
+fn navigation(ui: &mut egui::Ui) -> Option<ViewNavigation> {
+    ui.horizontal(|ui| {
+        if ui.button("Back to settings").clicked() {
+            return Some(ViewNavigation::Prev);
+        }
+        if ui
+            .button("Gallery")
+            .on_hover_text("Images with a file that failed are outlined")
+            .clicked()
+        {
+            return Some(ViewNavigation::Open(|| Box::new(Gallery::default())));
+        }
+        ui.button("Process another Takeout")
+            .clicked()
+            .then_some(ViewNavigation::Restart)
+    })
+    .inner
+}
+
+impl Success {
+    /// Counts of what the run did, along with ways to look at the results.
+    fn show_summary(&mut self, summary: &RunSummary, app: &AppState, ui: &mut egui::Ui) {
+        egui::Grid::new("run_summary")
+            .striped(true)
+            .num_columns(2)
+            .show(ui, |ui| {
+                for (label, count) in [
+                    ("Files written", summary.processed),
+                    ("Skipped, already processed", summary.skipped),
+                    ("Failed", summary.failed),
+                    ("Media without json file", summary.images_without_json),
+                    ("Json files without media", summary.json_without_image),
+                    ("Media with GPS location written", summary.gps_written),
+                    ("Media with date written", summary.dates_written),
+                ] {
+                    ui.label(label);
+                    ui.label(count.to_string());
+                    ui.end_row();
+                }
+            });
+        if !summary.by_type.is_empty() {
+            let by_type: Vec<_> = summary
+                .by_type
+                .iter()
+                .map(|(extension, count)| format!("{} {}", count, extension))
+                .collect();
+            ui.label(format!("Media written: {}", by_type.join(", ")));
+        }
+
+        ui.horizontal(|ui| {
+            if let Some(path) = app.picked_path.as_ref()
+                && ui.button("Open output folder").clicked()
+            {
+                open_path(ui.ctx(), &services::output_dir(path, &app.options.output));
+            }
+            if ui.button("Save report…").clicked() {
+                self.report_receiver = Some(spawn_dialog(ui.ctx(), || {
+                    rfd::FileDialog::new()
+                        .add_filter("JSON", &["json"])
+                        .set_file_name("report.json")
+                        .save_file()
+                }));
+            }
+            match self.report.as_ref() {
+                Some(Ok(path)) if ui.button("Open report").clicked() => {
+                    open_path(ui.ctx(), path);
+                }
+                Some(Err(err)) => {
+                    ui.label(format!("Saving the report failed: {}", err));
+                }
+                _ => {}
+            }
+        });
+    }
+}
+
+fn spawn_undo(ctx: &egui::Context, path: PathBuf) -> Receiver<UndoResult> {
+    spawn_worker(ctx, move |tx| {
+        tx.send(services::undo(&path))
+            .expect("Failed to send undo result to main thread");
+    })
+}
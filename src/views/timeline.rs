@@ -0,0 +1,227 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex, mpsc},
+};
+
+use crate::{
+    AppState,
+    services::{DateSource, MetadataOptions, Month, TakeoutLibrary, TimeZoneMode, Timeline},
+};
+use eframe::egui;
+
+use super::library::lock;
+use super::utils::{Receiver, spawn_worker};
+use super::{ViewNavigation, Viewable};
+
+/// Height of the tallest bar
+const CHART_HEIGHT: f32 = 200.0;
+const BAR_WIDTH: f32 = 10.0;
+const BAR_GAP: f32 = 2.0;
+
+/// Files that are listed below the chart.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Selection {
+    /// Index into the months of the timeline
+    Month(usize),
+    Undated,
+}
+
+/// A bar chart of how many images have a date in each month, according to the date source of the settings. Months
+/// with a lot more images than usual are highlighted, since they are usually caused by wrong dates. It's opened on top
+/// of the wizard.
+#[derive(Default)]
+pub struct TimelineView {
+    worker: Option<Receiver<Timeline>>,
+    /// The date source and time zone the timeline was computed with, the other options don't change the dates
+    computed_for: Option<(DateSource, TimeZoneMode)>,
+    timeline: Option<Result<Timeline, String>>,
+    selected: Option<Selection>,
+}
+impl Viewable for TimelineView {
+    fn show(
+        &mut self,
+        app: &mut AppState,
+        ctx: &egui::Context,
+        ui: &mut egui::Ui,
+    ) -> Option<ViewNavigation> {
+        if let Some(worker) = self.worker.take() {
+            match worker.rx.try_recv() {
+                Ok(timeline) => self.timeline = Some(worker.join().map(|()| timeline)),
+                Err(mpsc::TryRecvError::Empty) => self.worker = Some(worker),
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.timeline = Some(Err(worker.join_disconnected()));
+                }
+            }
+        }
+
+        let navigation = ui.button("Close").clicked().then_some(ViewNavigation::Prev);
+        ui.heading("Timeline");
+        let Some(library) = app.library(ctx).show(ui) else {
+            return navigation;
+        };
+        let options = &app.options.metadata;
+        if self.worker.is_none()
+            && self.computed_for != Some((options.date_source, options.time_zone))
+        {
+            self.spawn_worker(ctx, library, options.clone());
+        }
+        let date_source = &mut app.options.metadata.date_source;
+        egui::ComboBox::from_label("Date")
+            .selected_text(date_source.label())
+            .show_ui(ui, |ui| {
+                for source in DateSource::ALL {
+                    ui.selectable_value(date_source, source, source.label());
+                }
+            });
+
+        match self.timeline.as_ref() {
+            _ if self.worker.is_some() => {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label("Reading dates...");
+                });
+            }
+            None => {}
+            Some(Err(err)) => {
+                ui.colored_label(
+                    ui.visuals().error_fg_color,
+                    format!("Could not read dates: {}", err),
+                );
+            }
+            Some(Ok(timeline)) => {
+                let mut selected = self.selected;
+                show_chart(timeline, &mut selected, ui);
+                show_spikes(timeline, &mut selected, ui);
+                show_files(timeline, &mut selected, ui);
+                self.selected = selected;
+            }
+        }
+        navigation
+    }
+}
+impl TimelineView {
+    fn spawn_worker(
+        &mut self,
+        ctx: &egui::Context,
+        library: Arc<Mutex<TakeoutLibrary>>,
+        options: MetadataOptions,
+    ) {
+        self.computed_for = Some((options.date_source, options.time_zone));
+        self.selected = None;
+        self.worker = Some(spawn_worker(ctx, move |tx| {
+            let _ = tx.send(lock(&library).timeline(&options));
+        }));
+    }
+}
+
+/// One bar per month, clicking a bar selects its files. Years are written below their January.
+fn show_chart(timeline: &Timeline, selected: &mut Option<Selection>, ui: &mut egui::Ui) {
+    if timeline.months.is_empty() {
+        ui.label("No image has a date.");
+        return;
+    }
+    let max = timeline.max().max(1) as f32;
+    let label_height = ui.text_style_height(&egui::TextStyle::Small) + 4.0;
+    egui::ScrollArea::horizontal().show(ui, |ui| {
+        let width = timeline.months.len() as f32 * (BAR_WIDTH + BAR_GAP);
+        let (rect, _) = ui.allocate_exact_size(
+            egui::vec2(width, CHART_HEIGHT + label_height),
+            egui::Sense::hover(),
+        );
+        let visuals = ui.visuals().clone();
+        for (i, month) in timeline.months.iter().enumerate() {
+            let x = rect.left() + i as f32 * (BAR_WIDTH + BAR_GAP);
+            let bottom = rect.top() + CHART_HEIGHT;
+            // the whole column can be clicked, so months with few images can be hit
+            let column = egui::Rect::from_min_max(
+                egui::pos2(x, rect.top()),
+                egui::pos2(x + BAR_WIDTH, bottom),
+            );
+            let response = ui
+                .interact(
+                    column,
+                    ui.id().with(("timeline_month", i)),
+                    egui::Sense::click(),
+                )
+                .on_hover_text(format!(
+                    "{}: {} files",
+                    month_label(month),
+                    month.files.len()
+                ));
+            if response.clicked() {
+                *selected = Some(Selection::Month(i));
+            }
+
+            let height = CHART_HEIGHT * month.files.len() as f32 / max;
+            let bar = egui::Rect::from_min_max(egui::pos2(x, bottom - height), column.max);
+            let color = if *selected == Some(Selection::Month(i)) {
+                visuals.selection.bg_fill
+            } else if month.is_spike {
+                visuals.error_fg_color
+            } else if response.hovered() {
+                visuals.widgets.hovered.fg_stroke.color
+            } else {
+                visuals.widgets.inactive.fg_stroke.color
+            };
+            ui.painter().rect_filled(bar, 0.0, color);
+            if month.month == 1 || i == 0 {
+                ui.painter().text(
+                    egui::pos2(x, bottom + 2.0),
+                    egui::Align2::LEFT_TOP,
+                    month.year.to_string(),
+                    egui::TextStyle::Small.resolve(ui.style()),
+                    visuals.weak_text_color(),
+                );
+            }
+        }
+    });
+}
+
+/// Shortcuts to the months that are highlighted and to the images without a date.
+fn show_spikes(timeline: &Timeline, selected: &mut Option<Selection>, ui: &mut egui::Ui) {
+    ui.horizontal_wrapped(|ui| {
+        let mut spikes = timeline.spikes().peekable();
+        if spikes.peek().is_some() {
+            ui.label("Unusually many images:");
+        }
+        for (i, month) in spikes {
+            let text = format!("{} ({})", month_label(month), month.files.len());
+            ui.selectable_value(selected, Some(Selection::Month(i)), text);
+        }
+        if !timeline.undated.is_empty() {
+            let text = format!("No date ({})", timeline.undated.len());
+            ui.selectable_value(selected, Some(Selection::Undated), text);
+        }
+    });
+}
+
+fn show_files(timeline: &Timeline, selected: &mut Option<Selection>, ui: &mut egui::Ui) {
+    let (title, files): (String, &[PathBuf]) = match *selected {
+        Some(Selection::Month(i)) => match timeline.months.get(i) {
+            Some(month) => (month_label(month), &month.files),
+            None => return,
+        },
+        Some(Selection::Undated) => ("No date".to_owned(), &timeline.undated),
+        None => {
+            ui.label("Click a month to list its files.");
+            return;
+        }
+    };
+    ui.separator();
+    ui.strong(format!("{}: {} files", title, files.len()));
+    let row_height = ui.text_style_height(&egui::TextStyle::Body);
+    egui::ScrollArea::vertical().auto_shrink(false).show_rows(
+        ui,
+        row_height,
+        files.len(),
+        |ui, rows| {
+            for file in &files[rows] {
+                ui.label(file.display().to_string());
+            }
+        },
+    );
+}
+
+fn month_label(month: &Month) -> String {
+    format!("{}-{:02}", month.year, month.month)
+}
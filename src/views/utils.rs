@@ -1,7 +1,170 @@
-use std::{sync::mpsc, thread};
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+};
+
+use eframe::egui;
+
+use crate::services::Thumbnail;
 
 #[derive(Debug)]
 pub struct Receiver<T> {
     pub rx: mpsc::Receiver<T>,
     pub handle: thread::JoinHandle<()>,
 }
+impl<T> Receiver<T> {
+    /// Wait for the worker to end, once it sent everything or its channel is disconnected. Returns why it panicked,
+    /// since a worker that disconnects before sending what a view waits for usually panicked.
+    pub fn join(self) -> Result<(), String> {
+        self.handle.join().map_err(|panic| {
+            panic
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "The worker stopped unexpectedly".to_owned())
+        })
+    }
+
+    /// Like [`Self::join`], for a worker that stopped before sending what it should have.
+    pub fn join_disconnected(self) -> String {
+        match self.join() {
+            Ok(()) => "The worker stopped unexpectedly".to_owned(),
+            Err(err) => err,
+        }
+    }
+
+    /// Take the answer of a worker in `receiver` that sends a single one, without waiting for it. Returns `None` while
+    /// the worker is still working. Once it answered or stopped without answering, `receiver` is emptied and the
+    /// answer, or why the worker stopped, is returned.
+    pub fn poll(receiver: &mut Option<Self>) -> Option<Result<T, String>> {
+        let worker = receiver.take()?;
+        match worker.rx.try_recv() {
+            Ok(answer) => Some(worker.join().map(|()| answer)),
+            Err(mpsc::TryRecvError::Empty) => {
+                *receiver = Some(worker);
+                None
+            }
+            Err(mpsc::TryRecvError::Disconnected) => Some(Err(worker.join_disconnected())),
+        }
+    }
+}
+
+/// Run `work` on a separate thread. Everything it sends wakes up the UI, so views only need to check for it with
+/// `try_recv` when they are shown instead of polling. The UI is also woken up once `work` returns, so views notice the
+/// channel is disconnected.
+pub fn spawn_worker<T, F>(ctx: &egui::Context, work: F) -> Receiver<T>
+where
+    T: Send + 'static,
+    F: FnOnce(mpsc::Sender<T>) + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel();
+    let ctx = ctx.clone();
+    // services send through plain channels, so their messages are forwarded to be able to repaint for each of them
+    let handle = thread::spawn(move || {
+        let (work_tx, work_rx) = mpsc::channel();
+        let worker = thread::spawn(move || work(work_tx));
+        for message in work_rx {
+            if tx.send(message).is_err() {
+                break;
+            }
+            ctx.request_repaint();
+        }
+        drop(tx);
+        ctx.request_repaint();
+        if let Err(panic) = worker.join() {
+            std::panic::resume_unwind(panic);
+        }
+    });
+    Receiver { rx, handle }
+}
+
+/// Open a native dialog on a separate thread, so the UI keeps rendering while the dialog is shown. The picked path, or
+/// `None` if the dialog was closed without picking one, is sent through the returned receiver.
+pub fn spawn_dialog<F>(ctx: &egui::Context, pick: F) -> Receiver<Option<PathBuf>>
+where
+    F: FnOnce() -> Option<PathBuf> + Send + 'static,
+{
+    spawn_worker(ctx, move |tx| {
+        // nobody waits for the path anymore if the view was left in the meantime
+        let _ = tx.send(pick());
+    })
+}
+
+/// Upload a thumbnail to the GPU, so it can be shown. `name` is only used for debugging.
+pub fn load_thumbnail(
+    ctx: &egui::Context,
+    name: &str,
+    thumbnail: &Thumbnail,
+) -> egui::TextureHandle {
+    let image = egui::ColorImage::from_rgba_unmultiplied(
+        [thumbnail.width as usize, thumbnail.height as usize],
+        &thumbnail.rgba,
+    );
+    ctx.load_texture(name, image, Default::default())
+}
+
+/// Characters that can't be part of the path of a url
+const URL_PATH: &percent_encoding::AsciiSet = &percent_encoding::CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Open a file or folder with the default application of the system.
+pub fn open_path(ctx: &egui::Context, path: &Path) {
+    ctx.open_url(egui::OpenUrl::same_tab(file_url(path)));
+}
+
+/// `file://` url of `path`. Windows paths get forward slashes and a leading slash before their drive letter.
+fn file_url(path: &Path) -> String {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_owned());
+    let path = path.to_string_lossy().replace('\\', "/");
+    let slash = if path.starts_with('/') { "" } else { "/" };
+    format!(
+        "file://{}{}",
+        slash,
+        percent_encoding::utf8_percent_encode(&path, URL_PATH)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_urls_are_encoded() {
+        let url = file_url(Path::new("/photos/a #1?.jpg"));
+        assert_eq!(url, "file:///photos/a%20%231%3F.jpg");
+        #[cfg(windows)]
+        assert_eq!(
+            file_url(Path::new(r"C:\photos\a b.jpg")),
+            "file:///C:/photos/a%20b.jpg"
+        );
+    }
+
+    #[test]
+    fn poll_reports_workers_that_stopped_without_answering() {
+        let ctx = egui::Context::default();
+        let wait = |mut receiver: Option<Receiver<i32>>| loop {
+            if let Some(answer) = Receiver::poll(&mut receiver) {
+                assert!(receiver.is_none());
+                return answer;
+            }
+        };
+
+        let answered = wait(Some(spawn_worker(&ctx, |tx| tx.send(1).unwrap())));
+        let panicked = wait(Some(spawn_worker(&ctx, |_| panic!("Worker failed"))));
+        let silent = wait(Some(spawn_worker(&ctx, |_| {})));
+
+        assert_eq!(answered, Ok(1));
+        assert_eq!(panicked, Err("Worker failed".to_owned()));
+        assert!(silent.is_err());
+    }
+}